@@ -29,17 +29,64 @@ taggedhead = Tagged { $tag } on Rasmus​.krats​.se
 
 posts-year = Posts from { $year }
 posts-tagged = Posts tagged “{ $tag }”
+posts-month = Posts from { $month } { $year }
+posts-day = Posts from { $month } { $day }, { $year }
+archive-h = Archive { $year }
+month-name = { $m ->
+    [1] January
+    [2] February
+    [3] March
+    [4] April
+    [5] May
+    [6] June
+    [7] July
+    [8] August
+    [9] September
+    [10] October
+    [11] November
+   *[12] December
+}
+month-abbr = { $m ->
+    [1] Jan
+    [2] Feb
+    [3] Mar
+    [4] Apr
+    [5] May
+    [6] Jun
+    [7] Jul
+    [8] Aug
+    [9] Sep
+    [10] Oct
+    [11] Nov
+   *[12] Dec
+}
 
 meb-h1 = Rasmus & this site
 meb-about = About Rasmus​.krats​.se
 
+search-h = Search
+search-label = Search posts
+search-button = Search
+search-result = Search results for “{ $q }”
+search-none = Nothing found for “{ $q }”
+search-pages = Pages
+
+pager-newer = ← Newer posts
+pager-older = Older posts →
+page-n = page { $n }
+
 feed-h = Atom feed
 feed-pre = There is an
 feed-link = atom feed for this tag
 
+comments-feed = Comments on Rasmus​.krats​.se
+comments-on = Comments on “{ $title }”
+c-by-on = { $name } on “{ $title }”
+
 fbshare = Share on facebook
 comments = Comments
 write-comments = Write a comment
+mentioned-by = Mentioned by
 recent-comments = New comments
 old-post-comment = This post is { $age } years old, comments are disabled.
 on = on
@@ -54,9 +101,13 @@ c-mail = Email:
 c-mail-h = Not published, except as gravatar.
 c-url = URL:
 c-url-h = Your presentation / homepage (if any).
+c-hp = Leave this field empty:
 c-post = Post the comment
 c-mod = Your comment awaits manual moderation.  Please be patient.
 c-by = Comment by { $name }
+c-reply = Reply
+c-reply-to = Reply to { $name }
+c-post-reply = Post the reply
 
 read-more-comments = Read whole <q>{ $title }</q> with { $n } comments.
 read-more = Read whole <q>{ $title }</q>.
@@ -65,3 +116,10 @@ comment-first = Be the first to comment.
 
 consent-youtube = Klicking play embedds a youtube video.
     That makes it possible for youtube to track you.
+
+too-many = Too many requests
+too-many-detail = You have posted too much in a short time.
+    Please wait { $minutes ->
+        [one] a minute
+       *[other] { $minutes } minutes
+    } and try again.
@@ -29,17 +29,64 @@ taggedhead = Taggat { $tag } på Rasmus​.krats​.se
 
 posts-year = Inlägg från { $year }
 posts-tagged = Inlägg taggade ”{ $tag }”
+posts-month = Inlägg från { $month } { $year }
+posts-day = Inlägg från { $day } { $month } { $year }
+archive-h = Arkiv { $year }
+month-name = { $m ->
+    [1] januari
+    [2] februari
+    [3] mars
+    [4] april
+    [5] maj
+    [6] juni
+    [7] juli
+    [8] augusti
+    [9] september
+    [10] oktober
+    [11] november
+   *[12] december
+}
+month-abbr = { $m ->
+    [1] jan
+    [2] feb
+    [3] mar
+    [4] apr
+    [5] maj
+    [6] jun
+    [7] jul
+    [8] aug
+    [9] sep
+    [10] okt
+    [11] nov
+   *[12] dec
+}
 
 meb-h1 = Rasmus & siten
 meb-about = Om Rasmus​.krats​.se
 
+search-h = Sök
+search-label = Sök inlägg
+search-button = Sök
+search-result = Sökresultat för ”{ $q }”
+search-none = Inget hittades för ”{ $q }”
+search-pages = Sidor
+
+pager-newer = ← Nyare inlägg
+pager-older = Äldre inlägg →
+page-n = sida { $n }
+
 feed-h = Atom feed
 feed-pre = Det finns en
 feed-link = atom feed för denna tagg
 
+comments-feed = Kommentarer på Rasmus​.krats​.se
+comments-on = Kommentarer till ”{ $title }”
+c-by-on = { $name } om ”{ $title }”
+
 fbshare = Dela på facebook
 comments = Kommentarer
 write-comments = Skriv en kommentar
+mentioned-by = Omnämnt av
 recent-comments = Nya kommentarer
 old-post-comment = Det här inlägget är { $age } år gammalt,
     det kan inte längre kommenteras.
@@ -54,9 +101,13 @@ c-mail = Epost:
 c-mail-h = Publiceras inte, utom som gravatar.
 c-url = URL:
 c-url-h = Din presentation / hem­sida (om du vill).
+c-hp = Lämna detta fält tomt:
 c-post = Posta kommentaren
 c-mod = Din kommentar väntar på manuellt godkännande.  Tack för ditt tålamod.
 c-by = Kommentar från { $name }
+c-reply = Svara
+c-reply-to = Svar till { $name }
+c-post-reply = Posta svaret
 
 read-more-comments = Läs hela <q>{ $title }</q> med { $n } kommentarer.
 read-more = Läs hela <q>{ $title }</q>.
@@ -65,3 +116,10 @@ comment-first = Bli först att kommentera.
 
 consent-youtube = Om du klickar Play bäddas en youtube­video in.
     Det ger youtube möjlighet att spåra dig.
+
+too-many = För många anrop
+too-many-detail = Du har postat för mycket på kort tid.
+    Vänta { $minutes ->
+        [one] en minut
+       *[other] { $minutes } minuter
+    } och försök igen.
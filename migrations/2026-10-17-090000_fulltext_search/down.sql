@@ -0,0 +1,5 @@
+drop index idx_metapages_search;
+drop index idx_posts_search;
+drop function search_query;
+drop function search_doc;
+drop function lang_regconfig;
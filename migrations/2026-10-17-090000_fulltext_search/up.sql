@@ -0,0 +1,25 @@
+-- Full-text search over posts and metapages, with the postgres
+-- dictionary matching the language of each document.
+
+create function lang_regconfig(langp varchar)
+  returns regconfig
+  language sql immutable strict parallel safe
+  as $func$
+  select case langp when 'sv' then 'swedish'::regconfig else 'english'::regconfig end
+  $func$;
+
+create function search_doc(langp varchar, title varchar, content text)
+  returns tsvector
+  language sql immutable strict parallel safe
+  as $func$
+  select setweight(to_tsvector(lang_regconfig(langp), title), 'A')
+      || setweight(to_tsvector(lang_regconfig(langp), content), 'B')
+  $func$;
+
+create function search_query(langp varchar, query text)
+  returns tsquery
+  language sql immutable strict parallel safe
+  as $func$ select websearch_to_tsquery(lang_regconfig(langp), query) $func$;
+
+create index idx_posts_search on posts using gin (search_doc(lang, title, content));
+create index idx_metapages_search on metapages using gin (search_doc(lang, title, content));
@@ -0,0 +1,5 @@
+-- A comment may be a reply to another comment on the same post.
+alter table comments
+  add column parent_id integer references comments (id) on delete cascade;
+
+create index idx_comments_parent on comments (parent_id);
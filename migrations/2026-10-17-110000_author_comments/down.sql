@@ -0,0 +1 @@
+alter table comments drop column by_author;
@@ -0,0 +1,2 @@
+-- Comments written by the site owner, such as replies from the cli.
+alter table comments add column by_author boolean not null default false;
@@ -0,0 +1,8 @@
+-- The bayes spam classifier model, as stored by `r4s train-spam`.
+-- The total number of spam and ham comments is stored with the
+-- empty token.
+create table spam_tokens (
+  token varchar primary key,
+  n_spam integer not null,
+  n_ham integer not null
+);
@@ -0,0 +1,12 @@
+-- Verified webmentions of posts.
+create table webmentions (
+  id serial primary key,
+  post_id integer not null references posts (id) on delete cascade,
+  source varchar not null,
+  title varchar,
+  received_at timestamp with time zone not null default now(),
+  from_host inet not null,
+  is_public boolean not null default false,
+  is_spam boolean not null default false,
+  unique (post_id, source)
+);
@@ -0,0 +1,9 @@
+-- Outbound links of posts that webmentions (or pingbacks) are sent for.
+-- The endpoint is null if the target had no endpoint.
+create table sent_mentions (
+  post_id integer not null references posts (id) on delete cascade,
+  target varchar not null,
+  endpoint varchar,
+  sent_at timestamp with time zone not null default now(),
+  primary key (post_id, target)
+);
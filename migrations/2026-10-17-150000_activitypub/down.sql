@@ -0,0 +1,2 @@
+drop table ap_deliveries;
+drop table ap_followers;
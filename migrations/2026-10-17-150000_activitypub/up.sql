@@ -0,0 +1,22 @@
+-- Fediverse actors following the site, one actor per language.
+-- The inbox is the shared inbox of the follower, if it has one.
+create table ap_followers (
+  id serial primary key,
+  lang varchar not null,
+  actor varchar not null,
+  inbox varchar not null,
+  followed_at timestamp with time zone not null default now(),
+  unique (lang, actor)
+);
+
+-- Posts that are delivered to the inboxes of the followers of a
+-- language actor.  Deliveries are recorded per inbox, so a failed
+-- delivery is retried without sending the post again to the inboxes
+-- that got it.
+create table ap_deliveries (
+  post_id integer not null references posts (id) on delete cascade,
+  lang varchar not null,
+  inbox varchar not null,
+  delivered_at timestamp with time zone not null default now(),
+  primary key (post_id, lang, inbox)
+);
@@ -0,0 +1,3 @@
+alter table comments drop column origin_actor;
+alter table comments drop column avatar;
+alter table comments drop column origin;
@@ -0,0 +1,7 @@
+-- Comments from elsewhere, such as replies from the fediverse, have the
+-- id of the original, and may have an avatar url instead of an email.
+-- The actor that wrote the original is kept, so only that actor can
+-- delete it.
+alter table comments add column origin varchar unique;
+alter table comments add column avatar varchar;
+alter table comments add column origin_actor varchar;
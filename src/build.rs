@@ -1,14 +1,203 @@
-use anyhow::Result;
-use ructe::Ructe;
+use anyhow::{Context, Result, anyhow};
+use ructe::{Ructe, StaticFiles};
+use std::fmt::Write as _;
+use std::fs::{create_dir_all, read, read_dir, write};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
 
 fn main() -> Result<()> {
     let mut ructe = Ructe::from_env()?;
     let mut statics = ructe.statics()?;
-    // Use a standard synect style, but replace some colors for improved contrast.
-    statics.add_files("res/img")?;
-    statics.add_files("res/fonts")?;
-    statics.add_files("res/js")?;
-    statics.add_files_as("res/leaflet-1.7.1", "ll171")?;
-    statics.add_sass_file("res/scss/r4s.scss")?;
+    let mut precompressed = Precompressed::new()?;
+    for dir in ["res/img", "res/fonts", "res/js"] {
+        println!("cargo:rerun-if-changed={dir}");
+        for path in files_in(Path::new(dir))? {
+            let name = added(&mut statics, |s| s.add_file(&path))?;
+            precompressed.add(name, &read(&path)?)?;
+        }
+    }
+    add_files_as(
+        &mut statics,
+        &mut precompressed,
+        "res/leaflet-1.7.1",
+        "ll171",
+    )?;
+    let css = compile_sass("res/scss/r4s.scss", &statics)?;
+    let name =
+        added(&mut statics, |s| s.add_file_data("res/scss/r4s.css", &css))?;
+    precompressed.add(name, &css)?;
+    // The statics source is written when dropped.
+    drop(statics);
+    precompressed.write()?;
     Ok(ructe.compile_templates("templates")?)
 }
+
+/// The regular files in `dir`, sorted by name.
+fn files_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in read_dir(dir).with_context(|| format!("Reading {dir:?}"))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Add the files in `dir` (recursively) with unhashed names in `to`.
+fn add_files_as(
+    statics: &mut StaticFiles,
+    precompressed: &mut Precompressed,
+    dir: &str,
+    to: &str,
+) -> Result<()> {
+    for entry in read_dir(dir).with_context(|| format!("Reading {dir:?}"))? {
+        let entry = entry?;
+        let path = entry.path();
+        let to = format!("{to}/{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            let dir = path.to_str().context("Bad static dir")?;
+            add_files_as(statics, precompressed, dir, &to)?;
+        } else {
+            let name = added(statics, |s| s.add_file_as(&path, &to))?;
+            precompressed.add(name, &read(&path)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Add a static file with `add`, and get the url name ructe gave it.
+fn added(
+    statics: &mut StaticFiles,
+    add: impl FnOnce(&mut StaticFiles) -> ructe::Result<&mut StaticFiles>,
+) -> Result<Option<String>> {
+    let before = statics.get_names().clone();
+    add(statics)?;
+    Ok(statics
+        .get_names()
+        .iter()
+        .find(|(rust_name, _)| !before.contains_key(*rust_name))
+        .map(|(_, url_name)| url_name.clone()))
+}
+
+/// Compile a sass file to compressed css.
+///
+/// This is what [`StaticFiles::add_sass_file`] does, but the css is
+/// needed here to compress it.  The `static_name` function gives the
+/// url name of a static file added before.
+fn compile_sass(src: &str, statics: &StaticFiles) -> Result<Vec<u8>> {
+    use rsass::css::CssString;
+    use rsass::input::CargoContext;
+    use rsass::output::{Format, Style};
+    use rsass::sass::{CallError, FormalArgs, Function};
+    use rsass::value::Quotes;
+    use std::sync::Arc;
+
+    let format = Format {
+        style: Style::Compressed,
+        precision: 4,
+    };
+    let (context, scss) =
+        CargoContext::for_path(Path::new(src)).map_err(rsass::Error::from)?;
+    let mut context = context.with_format(format);
+    let names = statics.get_names().clone();
+    context.get_scope().define_function(
+        "static_name".into(),
+        Function::builtin(
+            "",
+            &"static_name".into(),
+            FormalArgs::new(vec![("name".into(), None)]),
+            Arc::new(move |s| {
+                let name: String = s.get("name".into())?;
+                let rust_name = name.replace(['-', '.'], "_");
+                names
+                    .get(&rust_name)
+                    .map(|url| {
+                        CssString::new(url.into(), Quotes::Double).into()
+                    })
+                    .ok_or_else(|| {
+                        CallError::msg(format!(
+                            "Static file {name:?} not found"
+                        ))
+                    })
+            }),
+        ),
+    );
+    Ok(context.transform(scss)?)
+}
+
+/// Brotli and gzip compressed versions of the compressible static
+/// files, so they are not compressed for each request.
+struct Precompressed {
+    dir: PathBuf,
+    files: Vec<(String, PathBuf, PathBuf)>,
+}
+
+impl Precompressed {
+    fn new() -> Result<Self> {
+        let dir = PathBuf::from(std::env::var("OUT_DIR")?);
+        create_dir_all(dir.join("precompressed"))?;
+        Ok(Precompressed {
+            dir,
+            files: Vec::new(),
+        })
+    }
+
+    /// Compress `data` of the static file with the url `name`, if worth it.
+    fn add(&mut self, name: Option<String>, data: &[u8]) -> Result<()> {
+        let name = name.ok_or_else(|| anyhow!("Static file not added"))?;
+        if !is_compressible(Path::new(&name)) {
+            return Ok(());
+        }
+        let br = {
+            let mut buf = Vec::new();
+            brotli::CompressorWriter::new(&mut buf, 4096, 11, 22)
+                .write_all(data)?;
+            buf
+        };
+        // Not worth it unless it saves at least a tenth.
+        if br.len() * 10 >= data.len() * 9 {
+            return Ok(());
+        }
+        let gz = {
+            use flate2::{Compression, write::GzEncoder};
+            let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+            gz.write_all(data)?;
+            gz.finish()?
+        };
+        let file = name.replace('/', "_");
+        let br_path =
+            self.dir.join("precompressed").join(format!("{file}.br"));
+        let gz_path =
+            self.dir.join("precompressed").join(format!("{file}.gz"));
+        write(&br_path, br)?;
+        write(&gz_path, gz)?;
+        self.files.push((name, br_path, gz_path));
+        Ok(())
+    }
+
+    /// Write the rust source for the compressed files.
+    fn write(mut self) -> Result<()> {
+        self.files.sort();
+        let mut src = String::from(
+            "/// Brotli and gzip compressed versions of static files,\n\
+             /// sorted by url name.\n\
+             pub static PRECOMPRESSED: &[(&str, &[u8], &[u8])] = &[\n",
+        );
+        for (name, br, gz) in &self.files {
+            writeln!(
+                src,
+                "    ({name:?}, include_bytes!({br:?}), include_bytes!({gz:?})),",
+            )?;
+        }
+        src.push_str("];\n");
+        let path = self.dir.join("precompressed.rs");
+        write(&path, src).with_context(|| format!("Writing {path:?}"))
+    }
+}
+
+fn is_compressible(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str());
+    matches!(ext, Some("css" | "js" | "svg" | "otf" | "json" | "txt"))
+}
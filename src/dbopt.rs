@@ -2,9 +2,9 @@ use clap::Parser;
 use diesel::Connection as _;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
-use diesel_async::AsyncPgConnection;
 use diesel_async::pooled_connection::AsyncDieselConnectionManager;
 use diesel_async::pooled_connection::deadpool;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
 use std::time::{Duration, Instant};
 use tracing::{debug, warn};
 
@@ -12,7 +12,10 @@ use tracing::{debug, warn};
 pub type Pool = deadpool::Pool<AsyncPgConnection>;
 pub type Connection = deadpool::Object<AsyncPgConnection>;
 
-#[derive(Parser)]
+/// The postgres notification channel for changes in published content.
+pub const CHANGED: &str = "r4s_changed";
+
+#[derive(Clone, Parser)]
 pub struct DbOpt {
     /// How to connect to the postgres database.
     #[clap(long, env = "DATABASE_URL", hide_env_values = true)]
@@ -36,6 +39,16 @@ impl DbOpt {
         Ok(connection)
     }
 
+    /// Get a single asynchronous database connection.
+    ///
+    /// This is for long-lived special uses, such as listening for
+    /// notifications, that should not hold a connection from the pool.
+    pub async fn get_async_db(
+        &self,
+    ) -> Result<AsyncPgConnection, ConnectionError> {
+        AsyncPgConnection::establish(&self.db_url).await
+    }
+
     /// Get a database connection pool from the configured url.
     ///
     /// Since this is mainly for the web server, the pooled connections
@@ -45,3 +58,9 @@ impl DbOpt {
         Pool::builder(config).max_size(20).build()
     }
 }
+
+/// Notify any running server that published content has changed.
+pub fn notify_changed(db: &mut PgConnection) -> QueryResult<()> {
+    diesel::sql_query(format!("NOTIFY {CHANGED}")).execute(db)?;
+    Ok(())
+}
@@ -3,12 +3,17 @@
 
 mod dbopt;
 mod listposts;
+mod mailopt;
 mod modcomments;
 mod models;
+mod outgoing;
 mod readcomments;
 mod readfiles;
+mod replycomment;
 mod schema;
 mod server;
+mod spam;
+mod trainspam;
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -38,14 +43,22 @@ enum R4s {
     List(listposts::Args),
     /// Moderate new coments
     ModerateComments(modcomments::Args),
+    /// Reply to a comment, as the author of the site
+    ReplyComment(replycomment::Args),
     /// Read content from markdown files
     ReadFiles(readfiles::Args),
+    /// Check markdown files for problems
+    Check(readfiles::check::Args),
     /// Read comments from a json dump.
     ReadComments(readcomments::Args),
     /// Dump comments to json for use with read-comments.
     DumpComments(readcomments::DumpArgs),
     /// Run the web server
     RunServer(server::Args),
+    /// Preview content, including drafts, directly from markdown files
+    Dev(server::dev::Args),
+    /// Train the spam filter on moderated comments
+    TrainSpam(trainspam::Args),
 }
 
 impl R4s {
@@ -53,10 +66,14 @@ impl R4s {
         match self {
             R4s::List(args) => args.run(),
             R4s::ModerateComments(args) => args.run(),
+            R4s::ReplyComment(args) => args.run(),
             R4s::ReadFiles(args) => args.run(),
+            R4s::Check(args) => args.run(),
             R4s::ReadComments(args) => args.run(),
             R4s::DumpComments(args) => args.run(),
             R4s::RunServer(args) => run_async(args.run()),
+            R4s::Dev(args) => run_async(args.run()),
+            R4s::TrainSpam(args) => args.run(),
         }
     }
 }
@@ -1,13 +1,19 @@
-use crate::dbopt::DbOpt;
-use crate::models::{Comment, PostComment, PostLink};
+use crate::dbopt::{DbOpt, notify_changed};
+use crate::models::{
+    Comment, MentionModeration, Moderation, PostComment, PostLink, Webmention,
+};
+use crate::replycomment::AuthorOpt;
 use crate::schema::comments::dsl as c;
 use crate::schema::posts::dsl as p;
+use crate::schema::webmentions::dsl as wm;
+use crate::spam::Bayes;
 use anstyle::{AnsiColor, Color, Style};
 use anyhow::{Result, ensure};
 use chrono::{DateTime, Utc};
 use clap::Parser;
 use diesel::dsl::count_star;
 use diesel::prelude::*;
+use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::io::{IsTerminal as _, Write, stdin, stdout};
 use textwrap::wrap;
@@ -17,6 +23,9 @@ pub struct Args {
     #[clap(flatten)]
     db: DbOpt,
 
+    #[clap(flatten)]
+    author: AuthorOpt,
+
     /// Only list the status and moderation queue.
     ///
     /// Does not wait for input, does not modify anything.
@@ -26,6 +35,11 @@ pub struct Args {
     /// Be silent if there is no pending comments.
     #[clap(long, short)]
     silent: bool,
+
+    /// Offer to mark all comments with a spam probability above
+    /// this as spam.
+    #[clap(long, default_value_t = 0.95)]
+    spam_limit: f64,
 }
 
 impl Args {
@@ -67,17 +81,52 @@ impl Args {
         } else {
             (NOSTYLE, NOSTYLE, NOSTYLE)
         };
+        let bayes = Bayes::load(&mut db)?;
+        let spam_probability = c::comments
+            .select((c::id, c::raw_md))
+            .filter(c::is_public.eq(false))
+            .filter(c::is_spam.eq(false))
+            .load::<(i32, String)>(&mut db)?
+            .into_iter()
+            .filter_map(|(id, md)| Some((id, bayes.probability(&md)?)))
+            .collect::<HashMap<_, _>>();
+        let spammy = spam_probability
+            .iter()
+            .filter(|(_, p)| **p > self.spam_limit)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        if !spammy.is_empty() {
+            let question = format!(
+                "There are {} comments with a spam probability above {}.  \
+                 Mark them all as spam?",
+                spammy.len(),
+                self.spam_limit,
+            );
+            if self.list {
+                println!("{question}");
+            } else {
+                match prompt(&question, &["yes", "no", "quit"])? {
+                    "yes" => do_moderate(&spammy, true, &mut db)?,
+                    "no" => (),
+                    _ => return Ok(()),
+                }
+            }
+        }
+
         for comment in mod_queue(&mut db)? {
             let p = comment.p();
             print!(
                 "\n{blue}{bold}{} by {italic}{:?}{italic:#}{bold:#} {blue}<{}>{blue:#}",
                 Ago(comment.posted_at.raw()),
                 comment.name,
-                comment.email,
+                comment.origin.as_deref().unwrap_or(&comment.email),
             );
             if let Some(url) = &comment.url {
                 print!(" {blue}{italic}{url}{italic:#}{blue:#}");
             }
+            if let Some(p) = spam_probability.get(&comment.id()) {
+                print!(" {blue}(spam {p:.2}){blue:#}");
+            }
             println!();
             println!(
                 "{blue}On {italic}{}{italic:#}{blue:#} {blue}({}){blue:#}",
@@ -91,15 +140,22 @@ impl Args {
                 println!();
                 match prompt(
                     "How about this comment?",
-                    &["ok", "spam", "quit"],
+                    &["ok", "reply", "spam", "quit"],
                 )? {
                     "ok" => {
                         println!("Should allow this");
-                        do_moderate(comment.id(), false, &mut db)?;
+                        do_moderate(&[comment.id()], false, &mut db)?;
+                    }
+                    "reply" => {
+                        println!("Should allow this, and reply");
+                        do_moderate(&[comment.id()], false, &mut db)?;
+                        if !self.author.reply(&comment, &mut db)? {
+                            println!("Empty reply, nothing posted.");
+                        }
                     }
                     "spam" => {
                         println!("Should disallow this");
-                        do_moderate(comment.id(), true, &mut db)?;
+                        do_moderate(&[comment.id()], true, &mut db)?;
                     }
                     _ => {
                         println!("Giving up for now");
@@ -109,10 +165,63 @@ impl Args {
             }
         }
 
+        for (mention, post) in mention_queue(&mut db)? {
+            println!(
+                "\n{blue}{bold}{} mention from {italic}{}{italic:#}{bold:#}{blue:#}",
+                Ago(mention.received_at.raw()),
+                mention.name(),
+            );
+            println!(
+                "{blue}{}\nOn {italic}{}{italic:#} ({}){blue:#}",
+                mention.source, post.title, post.year
+            );
+            if !self.list {
+                println!();
+                match prompt(
+                    "How about this mention?",
+                    &["ok", "spam", "quit"],
+                )? {
+                    "ok" => moderate_mention(mention.id, false, &mut db)?,
+                    "spam" => moderate_mention(mention.id, true, &mut db)?,
+                    _ => {
+                        println!("Giving up for now");
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 }
 
+fn mention_queue(
+    db: &mut PgConnection,
+) -> Result<Vec<(Webmention, PostLink)>> {
+    wm::webmentions
+        .inner_join(p::posts)
+        .select((Webmention::as_select(), PostLink::as_select()))
+        .filter(wm::is_public.eq(false))
+        .filter(wm::is_spam.eq(false))
+        .order_by(wm::received_at.desc())
+        .limit(50)
+        .load(db)
+        .map_err(Into::into)
+}
+
+fn moderate_mention(
+    id: i32,
+    spam: bool,
+    db: &mut PgConnection,
+) -> Result<()> {
+    diesel::update(wm::webmentions)
+        .filter(wm::id.eq(id))
+        .set(MentionModeration::new(spam))
+        .execute(db)?;
+    notify_changed(db)?;
+    Ok(())
+}
+
 pub fn mod_queue(db: &mut PgConnection) -> Result<Vec<PostComment>> {
     c::comments
         .inner_join(p::posts)
@@ -144,14 +253,15 @@ impl Display for Ago {
 }
 
 fn do_moderate(
-    comment: i32,
+    comments: &[i32],
     spam: bool,
     db: &mut PgConnection,
 ) -> Result<()> {
     diesel::update(c::comments)
-        .filter(c::id.eq(comment))
-        .set((c::is_public.eq(!spam), c::is_spam.eq(spam)))
+        .filter(c::id.eq_any(comments))
+        .set(Moderation::new(spam))
         .execute(db)?;
+    notify_changed(db)?;
     Ok(())
 }
 
@@ -6,6 +6,8 @@ use crate::server::templates::{Html, HtmlBuffer, ToHtml};
 use diesel::prelude::*;
 use diesel::{dsl::sql, sql_types::Bool};
 use diesel_async::RunQueryDsl;
+use ipnetwork::IpNetwork;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Identifiable, Queryable, Selectable, Associations)]
 #[diesel(belongs_to(Post))]
@@ -17,6 +19,14 @@ pub struct Comment {
     pub name: String,
     pub email: String,
     pub url: Option<String>,
+    pub parent_id: Option<i32>,
+    /// True if this comment is written by the site owner.
+    pub by_author: bool,
+    /// The id of the original, for a comment from elsewhere, such as a
+    /// reply from the fediverse.
+    pub origin: Option<String>,
+    /// An avatar url, for a comment from elsewhere.
+    pub avatar: Option<String>,
 }
 
 impl Comment {
@@ -27,8 +37,15 @@ impl Comment {
         self.id
     }
 
-    pub fn gravatar(&self) -> String {
+    /// The avatar of the commenter.
+    ///
+    /// This is the remote avatar for a comment from elsewhere, or else
+    /// a gravatar for the email.
+    pub fn avatar(&self) -> String {
         use gravatar::{Default, Gravatar, Rating};
+        if let Some(avatar) = &self.avatar {
+            return avatar.clone();
+        }
         Gravatar::new(&self.email)
             .set_size(Some(160))
             .set_default(Some(Default::Retro))
@@ -49,6 +66,55 @@ impl Comment {
     }
 }
 
+/// A comment with its replies.
+#[derive(Debug)]
+pub struct Thread {
+    pub comment: Comment,
+    pub replies: Vec<Thread>,
+}
+
+impl Thread {
+    /// Arrange comments in threads of replies.
+    ///
+    /// The order of the comments is kept within each level.
+    /// A reply to a comment that is not in `comments` is shown on the
+    /// top level.
+    pub fn build(comments: Vec<Comment>) -> Vec<Thread> {
+        let ids = comments.iter().map(|c| c.id).collect::<HashSet<_>>();
+        let mut replies = HashMap::<i32, Vec<Comment>>::new();
+        let mut top = Vec::new();
+        for comment in comments {
+            match comment.parent_id.filter(|p| ids.contains(p)) {
+                Some(parent) => {
+                    replies.entry(parent).or_default().push(comment)
+                }
+                None => top.push(comment),
+            }
+        }
+        fn thread(
+            c: Comment,
+            replies: &mut HashMap<i32, Vec<Comment>>,
+        ) -> Thread {
+            let mine = replies.remove(&c.id).unwrap_or_default();
+            Thread {
+                comment: c,
+                replies: mine
+                    .into_iter()
+                    .map(|c| thread(c, replies))
+                    .collect(),
+            }
+        }
+        top.into_iter().map(|c| thread(c, &mut replies)).collect()
+    }
+}
+
+impl std::ops::Deref for Thread {
+    type Target = Comment;
+    fn deref(&self) -> &Comment {
+        &self.comment
+    }
+}
+
 pub struct LinkName<'a>(&'a Comment);
 
 impl ToHtml for LinkName<'_> {
@@ -84,6 +150,27 @@ impl PostComment {
             .await
     }
 
+    /// The latest public comments, for a feed.
+    ///
+    /// If `post` is given, only comments on that post are included.
+    pub async fn for_feed(
+        post: Option<i32>,
+        limit: i64,
+        db: &mut Connection,
+    ) -> Result<Vec<PostComment>> {
+        let mut query = c::comments
+            .inner_join(p::posts.on(p::id.eq(c::post_id)))
+            .select((Comment::as_select(), PostLink::as_select()))
+            .filter(c::is_public.eq(true))
+            .order_by(c::posted_at.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(post) = post {
+            query = query.filter(c::post_id.eq(post));
+        }
+        query.load(db).await
+    }
+
     pub fn p(&self) -> &PostLink {
         &self.post
     }
@@ -116,3 +203,142 @@ impl std::ops::Deref for PostComment {
         &self.comment
     }
 }
+
+/// The moderation status to set on a comment.
+///
+/// A moderated comment is either public or spam.
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = comments)]
+pub struct Moderation {
+    is_public: bool,
+    is_spam: bool,
+}
+
+impl Moderation {
+    pub fn new(spam: bool) -> Self {
+        Moderation {
+            is_public: !spam,
+            is_spam: spam,
+        }
+    }
+}
+
+/// A comment with the data needed for moderation.
+#[derive(Debug)]
+pub struct ModComment {
+    comment: PostComment,
+    pub from_host: IpNetwork,
+    pub raw_md: String,
+    pub is_public: bool,
+}
+
+impl ModComment {
+    /// Comments waiting for moderation, newest first.
+    pub async fn queue(db: &mut Connection) -> Result<Vec<ModComment>> {
+        Self::load(false, None, 50, db).await
+    }
+    /// The latest public comments.
+    pub async fn public(
+        limit: i64,
+        db: &mut Connection,
+    ) -> Result<Vec<ModComment>> {
+        Self::load(true, None, limit, db).await
+    }
+    /// A specific comment, if it is waiting for moderation.
+    pub async fn pending(
+        id: i32,
+        db: &mut Connection,
+    ) -> Result<Option<ModComment>> {
+        Ok(Self::load(false, Some(id), 1, db).await?.pop())
+    }
+
+    async fn load(
+        public: bool,
+        id: Option<i32>,
+        limit: i64,
+        db: &mut Connection,
+    ) -> Result<Vec<ModComment>> {
+        let mut query = c::comments
+            .inner_join(p::posts.on(p::id.eq(c::post_id)))
+            .select((
+                Comment::as_select(),
+                PostLink::as_select(),
+                c::from_host,
+                c::raw_md,
+                c::is_public,
+            ))
+            .filter(c::is_public.eq(public))
+            .filter(c::is_spam.eq(false))
+            .order_by(c::posted_at.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(id) = id {
+            query = query.filter(c::id.eq(id));
+        }
+        query
+            .load::<(Comment, PostLink, IpNetwork, String, bool)>(db)
+            .await
+            .map(|comments| {
+                comments
+                    .into_iter()
+                    .map(|(comment, post, from_host, raw_md, is_public)| {
+                        ModComment {
+                            comment: PostComment { comment, post },
+                            from_host,
+                            raw_md,
+                            is_public,
+                        }
+                    })
+                    .collect()
+            })
+    }
+}
+
+impl std::ops::Deref for ModComment {
+    type Target = PostComment;
+    fn deref(&self) -> &PostComment {
+        &self.comment
+    }
+}
+
+#[cfg(test)]
+fn comment(id: i32, parent_id: Option<i32>) -> Comment {
+    Comment {
+        id,
+        post_id: 1,
+        posted_at: DateTime::wrap(chrono::Utc::now()),
+        content: format!("<p>Comment {id}</p>"),
+        name: "Someone".into(),
+        email: "someone@example.com".into(),
+        url: None,
+        parent_id,
+        by_author: false,
+        origin: None,
+        avatar: None,
+    }
+}
+
+#[cfg(test)]
+fn ids(threads: &[Thread]) -> String {
+    threads
+        .iter()
+        .map(|t| match &t.replies[..] {
+            [] => t.comment.id.to_string(),
+            r => format!("{}({})", t.comment.id, ids(r)),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn threads() {
+    let comments = vec![
+        comment(1, None),
+        comment(2, Some(1)),
+        comment(3, None),
+        comment(4, Some(2)),
+        comment(5, Some(1)),
+        comment(6, Some(99)),
+    ];
+    assert_eq!(ids(&Thread::build(comments)), "1(2(4) 5) 3 6");
+}
@@ -20,6 +20,21 @@ impl std::ops::Deref for FullPost {
 }
 
 impl FullPost {
+    /// A post that is not (yet) in the database, for previews.
+    pub fn new(
+        post: Post,
+        front_image: Option<String>,
+        description: String,
+        use_leaflet: bool,
+    ) -> Self {
+        FullPost {
+            post,
+            front_image,
+            description,
+            use_leaflet,
+        }
+    }
+
     pub async fn load(
         year: i16,
         slug: &Slug,
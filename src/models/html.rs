@@ -0,0 +1,22 @@
+/// Unescape the most common html entities.
+///
+/// This is not a complete html decoder, but enough for titles and
+/// attribute values of the pages fetched for webmentions and the
+/// content of fediverse replies.
+pub fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+#[test]
+fn unescape_entities() {
+    assert_eq!(
+        unescape_html("&lt;a&gt; &quot;b&quot; &#39;c&apos;&nbsp;&amp;lt;"),
+        "<a> \"b\" 'c'\u{a0}&lt;",
+    );
+}
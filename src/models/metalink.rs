@@ -0,0 +1,39 @@
+use super::{Matches, MyLang, Result, Slug};
+use super::{search_doc, search_query, ts_rank};
+use crate::dbopt::Connection;
+use crate::schema::metapages::{self, dsl as m};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+/// Enough data about a meta page to link to it.
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = metapages)]
+pub struct MetaLink {
+    pub slug: Slug,
+    pub lang: MyLang,
+    pub title: String,
+}
+
+impl MetaLink {
+    /// Meta pages in `lang` matching a full-text search `query`.
+    pub async fn search(
+        query: &str,
+        lang: &str,
+        limit: u32,
+        db: &mut Connection,
+    ) -> Result<Vec<MetaLink>> {
+        let doc = search_doc(m::lang, m::title, m::content);
+        let query = search_query(lang, query);
+        m::metapages
+            .select(MetaLink::as_select())
+            .filter(m::lang.eq(lang))
+            .filter(Matches::new(doc, query))
+            .order(ts_rank(doc, query).desc())
+            .limit(limit.into())
+            .load(db)
+            .await
+    }
+    pub fn url(&self) -> String {
+        format!("/{}.{}", self.slug, self.lang)
+    }
+}
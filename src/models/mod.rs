@@ -1,27 +1,35 @@
 use diesel::define_sql_function;
-use diesel::sql_types::{Smallint, Timestamptz, Varchar};
+use diesel::sql_types::{Smallint, Text, Timestamptz, Varchar};
 
 mod comment;
 mod datetime;
 mod fullpost;
+mod html;
 mod markdown;
+mod metalink;
 mod mylang;
 mod post;
 mod postlink;
 mod slug;
 mod tag;
 mod teaser;
+mod webmention;
 
-pub use self::comment::{Comment, PostComment};
+pub use self::comment::{
+    Comment, ModComment, Moderation, PostComment, Thread,
+};
 pub use self::datetime::DateTime;
 pub use self::fullpost::FullPost;
+pub use self::html::unescape_html;
 pub use self::markdown::safe_md2html;
+pub use self::metalink::MetaLink;
 pub use self::mylang::MyLang;
 pub use self::post::Post;
 pub use self::postlink::PostLink;
 pub use self::slug::Slug;
 pub use self::tag::{PostTag, Tag};
 pub use self::teaser::Teaser;
+pub use self::webmention::{MentionModeration, ModMention, Webmention};
 
 type Result<T, E = diesel::result::Error> = std::result::Result<T, E>;
 
@@ -32,3 +40,29 @@ define_sql_function! {
 define_sql_function! {
     fn has_lang(yearp: Smallint, slugp: Varchar, langp: Varchar) -> Bool;
 }
+
+/// The postgres full-text search document type.
+#[derive(diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "tsvector"))]
+pub struct TsVector;
+
+/// The postgres full-text search query type.
+#[derive(diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "tsquery"))]
+pub struct TsQuery;
+
+define_sql_function! {
+    /// Search document for a title and html content in a language.
+    fn search_doc(langp: Varchar, title: Varchar, content: Text) -> TsVector;
+}
+
+define_sql_function! {
+    /// Parse a user-entered search query, stemmed for a language.
+    fn search_query(langp: Varchar, query: Text) -> TsQuery;
+}
+
+define_sql_function! {
+    fn ts_rank(doc: TsVector, query: TsQuery) -> Float;
+}
+
+diesel::infix_operator!(Matches, " @@ ", backend: diesel::pg::Pg);
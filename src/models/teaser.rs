@@ -1,11 +1,14 @@
-use super::{Post, PostTag, Result, Tag, has_lang, year_of_date};
+use super::{Matches, Post, PostTag, Result, Tag, has_lang, year_of_date};
+use super::{search_doc, search_query, ts_rank};
 use crate::dbopt::Connection;
 use crate::schema::comments::dsl as c;
 use crate::schema::post_tags::dsl as pt;
 use crate::schema::posts::dsl as p;
+use chrono::{DateTime, Utc};
 use diesel::BelongingToDsl;
 use diesel::associations::HasTable;
-use diesel::dsl::{not, sql};
+use diesel::dsl::{auto_type, not, sql};
+use diesel::expression::SqlLiteral;
 use diesel::prelude::*;
 use diesel::sql_types::BigInt;
 use diesel_async::RunQueryDsl;
@@ -20,37 +23,33 @@ pub struct Teaser {
 }
 
 impl Teaser {
+    /// A teaser that is not (yet) in the database, for previews.
+    ///
+    /// The `content` of `post` is the teaser itself.
+    pub fn new(post: Post, tags: Vec<Tag>, is_more: bool) -> Self {
+        Teaser {
+            post,
+            tags,
+            is_more,
+            n_comments: 0,
+        }
+    }
+
     pub async fn recent(
         lang: &str,
         limit: u32,
+        offset: u32,
         db: &mut Connection,
     ) -> Result<Vec<Self>> {
-        let posts = p::posts
-            .left_join(
-                c::comments
-                    .on(c::post_id.eq(p::id).and(c::is_public.eq(true))),
-            )
-            .select((
-                (
-                    p::id,
-                    p::slug,
-                    p::lang,
-                    p::title,
-                    p::posted_at,
-                    p::updated_at,
-                    p::teaser,
-                ),
-                p::teaser.ne(p::content),
-                sql::<BigInt>("count(distinct comments.id)"),
-            ))
+        let posts = teasers()
             .filter(p::lang.eq(lang).or(not(has_lang(
                 year_of_date(p::posted_at),
                 p::slug,
                 lang,
             ))))
-            .group_by(p::posts::all_columns())
-            .order(p::updated_at.desc())
+            .order((p::updated_at.desc(), p::id.desc()))
             .limit(limit.into())
+            .offset(offset.into())
             .load::<(Post, bool, i64)>(db)
             .await?;
         Self::with_tags(posts, db).await
@@ -60,24 +59,7 @@ impl Teaser {
         lang: &str,
         db: &mut Connection,
     ) -> Result<Vec<Teaser>> {
-        let posts = p::posts
-            .left_join(
-                c::comments
-                    .on(c::post_id.eq(p::id).and(c::is_public.eq(true))),
-            )
-            .select((
-                (
-                    p::id,
-                    p::slug,
-                    p::lang,
-                    p::title,
-                    p::posted_at,
-                    p::updated_at,
-                    p::teaser,
-                ),
-                p::teaser.ne(p::content),
-                sql::<BigInt>("count(distinct comments.id)"),
-            ))
+        let posts = teasers()
             .filter(
                 year_of_date(p::posted_at)
                     .eq(year)
@@ -88,37 +70,41 @@ impl Teaser {
                 p::slug,
                 lang,
             ))))
-            .group_by(p::posts::all_columns())
             .order(p::updated_at.asc())
             .load::<(Post, bool, i64)>(db)
             .await?;
         Self::with_tags(posts, db).await
     }
 
+    /// Posts published from `from` until (but not including) `to`.
+    pub async fn posted_between(
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        lang: &str,
+        db: &mut Connection,
+    ) -> Result<Vec<Teaser>> {
+        let posts = teasers()
+            .filter(p::posted_at.ge(from))
+            .filter(p::posted_at.lt(to))
+            .filter(p::lang.eq(lang).or(not(has_lang(
+                year_of_date(p::posted_at),
+                p::slug,
+                lang,
+            ))))
+            .order(p::posted_at.asc())
+            .load::<(Post, bool, i64)>(db)
+            .await?;
+        Self::with_tags(posts, db).await
+    }
+
     pub async fn tagged(
         tag_id: i32,
         lang: &str,
         limit: u32,
+        offset: u32,
         db: &mut Connection,
     ) -> Result<Vec<Teaser>> {
-        let posts = p::posts
-            .left_join(
-                c::comments
-                    .on(c::post_id.eq(p::id).and(c::is_public.eq(true))),
-            )
-            .select((
-                (
-                    p::id,
-                    p::slug,
-                    p::lang,
-                    p::title,
-                    p::posted_at,
-                    p::updated_at,
-                    p::teaser,
-                ),
-                p::teaser.ne(p::content),
-                sql::<BigInt>("count(distinct comments.id)"),
-            ))
+        let posts = teasers()
             .filter(
                 p::id.eq_any(
                     pt::post_tags
@@ -131,14 +117,55 @@ impl Teaser {
                 p::slug,
                 lang,
             ))))
-            .group_by(p::posts::all_columns())
-            .order(p::updated_at.desc())
+            .order((p::updated_at.desc(), p::id.desc()))
             .limit(limit.into())
+            .offset(offset.into())
             .load::<(Post, bool, i64)>(db)
             .await?;
         Self::with_tags(posts, db).await
     }
 
+    /// Posts matching a full-text search `query`, best match first.
+    ///
+    /// Posts in `lang` are preferred, but other posts are included if
+    /// not translated.  Each post is indexed with the dictionary of its
+    /// own language, so the query is interpreted with that too.
+    pub async fn search(
+        query: &str,
+        lang: &str,
+        limit: u32,
+        db: &mut Connection,
+    ) -> Result<Vec<Teaser>> {
+        let doc = search_doc(p::lang, p::title, p::content);
+        let query = search_query(p::lang, query);
+        let posts = teasers()
+            .filter(Matches::new(doc, query))
+            .filter(p::lang.eq(lang).or(not(has_lang(
+                year_of_date(p::posted_at),
+                p::slug,
+                lang,
+            ))))
+            .order((ts_rank(doc, query).desc(), p::updated_at.desc()))
+            .limit(limit.into())
+            .load::<(Post, bool, i64)>(db)
+            .await?;
+        Self::with_tags(posts, db).await
+    }
+
+    /// A single post in `lang`, if it exists.
+    pub async fn by_id(
+        id: i32,
+        lang: &str,
+        db: &mut Connection,
+    ) -> Result<Option<Teaser>> {
+        let posts = teasers()
+            .filter(p::id.eq(id))
+            .filter(p::lang.eq(lang))
+            .load::<(Post, bool, i64)>(db)
+            .await?;
+        Ok(Self::with_tags(posts, db).await?.pop())
+    }
+
     async fn with_tags(
         posts: Vec<(Post, bool, i64)>,
         db: &mut Connection,
@@ -181,14 +208,74 @@ impl Teaser {
             (false, false) => fl!(lang, "comment-first"),
         }
     }
+    pub fn n_comments(&self) -> u32 {
+        self.n_comments
+    }
     pub fn tags(&self) -> &[Tag] {
         &self.tags
     }
 }
 
+/// Posts with their teaser, if there is more, and the number of
+/// public comments.  Loads as `(Post, bool, i64)`.
+#[auto_type]
+fn teasers() -> _ {
+    let n_comments: SqlLiteral<BigInt> = sql("count(distinct comments.id)");
+    p::posts
+        .left_join(
+            c::comments.on(c::post_id.eq(p::id).and(c::is_public.eq(true))),
+        )
+        .select((
+            (
+                p::id,
+                p::slug,
+                p::lang,
+                p::title,
+                p::posted_at,
+                p::updated_at,
+                p::teaser,
+            ),
+            p::teaser.ne(p::content),
+            n_comments,
+        ))
+        .group_by(p::id)
+}
+
 impl std::ops::Deref for Teaser {
     type Target = Post;
     fn deref(&self) -> &Post {
         &self.post
     }
 }
+
+#[tokio::test]
+#[ignore = "needs a database in DATABASE_URL"]
+async fn search_in_post_language() {
+    use crate::dbopt::DbOpt;
+    use clap::Parser;
+    use diesel_async::AsyncConnection;
+    let pool = DbOpt::parse_from(["r4s"]).build_pool().unwrap();
+    let mut db = pool.get().await.unwrap();
+    db.begin_test_transaction().await.unwrap();
+    let id = diesel::insert_into(p::posts)
+        .values((
+            p::slug.eq("search-test"),
+            p::title.eq("Cykeltur"),
+            p::lang.eq("sv"),
+            p::content.eq("<p>Vi cyklade runt sjön, och cyklarna höll.</p>"),
+            p::teaser.eq(""),
+            p::description.eq(""),
+            p::use_leaflet.eq(false),
+            p::orig_md.eq(""),
+        ))
+        .returning(p::id)
+        .get_result::<i32>(&mut db)
+        .await
+        .unwrap();
+    // The post is only in swedish, so it is shown in english too, and
+    // the query is stemmed as swedish for it.
+    for lang in ["sv", "en"] {
+        let found = Teaser::search("cyklar", lang, 30, &mut db).await;
+        assert!(found.unwrap().iter().any(|t| t.id == id), "{lang}");
+    }
+}
@@ -0,0 +1,105 @@
+use super::{DateTime, Post, PostLink, Result};
+use crate::dbopt::Connection;
+use crate::schema::posts::dsl as p;
+use crate::schema::webmentions::{self, dsl as wm};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use ipnetwork::IpNetwork;
+
+/// A verified mention of a post on another site.
+#[derive(Debug, Identifiable, Queryable, Selectable, Associations)]
+#[diesel(belongs_to(Post))]
+pub struct Webmention {
+    pub id: i32,
+    pub post_id: i32,
+    /// The url of the mentioning page.
+    pub source: String,
+    /// The title of the mentioning page, if it has one.
+    pub title: Option<String>,
+    pub received_at: DateTime,
+}
+
+impl Webmention {
+    pub fn html_id(&self) -> String {
+        format!("m{:x}", self.id)
+    }
+    /// The title of the source, or its host name if it has no title.
+    pub fn name(&self) -> &str {
+        self.title
+            .as_deref()
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| host(&self.source))
+    }
+}
+
+fn host(url: &str) -> &str {
+    let url = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    url.split(['/', '?', '#']).next().unwrap_or(url)
+}
+
+/// A webmention with the data needed for moderation.
+#[derive(Debug)]
+pub struct ModMention {
+    mention: Webmention,
+    pub post: PostLink,
+    pub from_host: IpNetwork,
+}
+
+impl ModMention {
+    /// Webmentions waiting for moderation, newest first.
+    pub async fn queue(db: &mut Connection) -> Result<Vec<ModMention>> {
+        wm::webmentions
+            .inner_join(p::posts.on(p::id.eq(wm::post_id)))
+            .select((
+                Webmention::as_select(),
+                PostLink::as_select(),
+                wm::from_host,
+            ))
+            .filter(wm::is_public.eq(false))
+            .filter(wm::is_spam.eq(false))
+            .order_by(wm::received_at.desc())
+            .limit(50)
+            .load::<(Webmention, PostLink, IpNetwork)>(db)
+            .await
+            .map(|mentions| {
+                mentions
+                    .into_iter()
+                    .map(|(mention, post, from_host)| ModMention {
+                        mention,
+                        post,
+                        from_host,
+                    })
+                    .collect()
+            })
+    }
+}
+
+impl std::ops::Deref for ModMention {
+    type Target = Webmention;
+    fn deref(&self) -> &Webmention {
+        &self.mention
+    }
+}
+
+/// The moderation status to set on a webmention.
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = webmentions)]
+pub struct MentionModeration {
+    is_public: bool,
+    is_spam: bool,
+}
+
+impl MentionModeration {
+    pub fn new(spam: bool) -> Self {
+        MentionModeration {
+            is_public: !spam,
+            is_spam: spam,
+        }
+    }
+}
+
+#[test]
+fn source_host() {
+    assert_eq!(host("https://example.org/2024/post"), "example.org");
+    assert_eq!(host("http://example.org:8080?q=1"), "example.org:8080");
+}
@@ -0,0 +1,329 @@
+//! Http requests to urls chosen by someone else.
+//!
+//! A webmention source, a link target or an ActivityPub actor may point
+//! anywhere, including into the network of the server itself.  The
+//! clients here only connect to global addresses, also when following
+//! redirects, and never downgrade from https to http.
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{Client, RequestBuilder, Url, redirect};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+const USER_AGENT: &str = concat!("r4s/", env!("CARGO_PKG_VERSION"));
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A http client for requests to urls chosen by others.
+#[derive(Clone)]
+pub struct Outgoing {
+    client: Client,
+    /// Allow any address, and http where https is otherwise required.
+    ///
+    /// Only for testing, with local instances.
+    local: bool,
+}
+
+impl Outgoing {
+    /// Create a client that only connects to global addresses.
+    ///
+    /// If `local` is true, any address is allowed.
+    pub fn new(local: bool) -> reqwest::Result<Self> {
+        let builder =
+            Client::builder().user_agent(USER_AGENT).timeout(TIMEOUT);
+        let client = if local {
+            builder.build()?
+        } else {
+            builder
+                .dns_resolver(GlobalResolver)
+                // A proxy would resolve the name itself.
+                .no_proxy()
+                .redirect(redirect_policy())
+                .build()?
+        };
+        Ok(Outgoing { client, local })
+    }
+
+    /// Start a GET request to `url`, if allowed by [`Outgoing::check`].
+    pub fn get(&self, url: &Url) -> Result<RequestBuilder, Blocked> {
+        self.check(url)?;
+        Ok(self.client.get(url.clone()))
+    }
+
+    /// Start a POST request to `url`, if allowed by [`Outgoing::check`].
+    pub fn post(&self, url: &Url) -> Result<RequestBuilder, Blocked> {
+        self.check(url)?;
+        Ok(self.client.post(url.clone()))
+    }
+
+    /// Check that a request to `url` may be done.
+    ///
+    /// The scheme must be http or https, and a literal address must be
+    /// global.  Host names are checked when resolved by the client.
+    pub fn check(&self, url: &Url) -> Result<(), Blocked> {
+        check(url, self.local)
+    }
+
+    /// Check that a request to `url` may be done, and that it is https.
+    pub fn check_https(&self, url: &Url) -> Result<(), Blocked> {
+        if self.local {
+            self.check(url)
+        } else {
+            check_https(url)
+        }
+    }
+}
+
+/// The same as [`Outgoing`](super::Outgoing), with a blocking client.
+pub mod blocking {
+    use super::{Blocked, GlobalResolver, TIMEOUT, USER_AGENT};
+    use super::{check, redirect_policy};
+    use reqwest::Url;
+    use reqwest::blocking::{Client, RequestBuilder};
+    use std::sync::Arc;
+
+    /// A blocking http client for requests to urls chosen by others.
+    #[derive(Clone)]
+    pub struct Outgoing {
+        client: Client,
+        /// Allow any address.  Only for testing, with local servers.
+        local: bool,
+    }
+
+    impl Outgoing {
+        /// Create a client that only connects to global addresses.
+        ///
+        /// If `local` is true, any address is allowed.
+        pub fn new(local: bool) -> reqwest::Result<Self> {
+            let builder =
+                Client::builder().user_agent(USER_AGENT).timeout(TIMEOUT);
+            let client = if local {
+                builder.build()?
+            } else {
+                builder
+                    .dns_resolver(Arc::new(GlobalResolver))
+                    .no_proxy()
+                    .redirect(redirect_policy())
+                    .build()?
+            };
+            Ok(Outgoing { client, local })
+        }
+
+        /// Start a GET request to `url`, if allowed by [`Outgoing::check`].
+        pub fn get(&self, url: &Url) -> Result<RequestBuilder, Blocked> {
+            self.check(url)?;
+            Ok(self.client.get(url.clone()))
+        }
+
+        /// Start a POST request to `url`, if allowed by [`Outgoing::check`].
+        pub fn post(&self, url: &Url) -> Result<RequestBuilder, Blocked> {
+            self.check(url)?;
+            Ok(self.client.post(url.clone()))
+        }
+
+        /// Check that a request to `url` may be done.
+        ///
+        /// The same rules as for the async [`Outgoing`](super::Outgoing)
+        /// apply.
+        pub fn check(&self, url: &Url) -> Result<(), Blocked> {
+            check(url, self.local)
+        }
+    }
+}
+
+/// Follow a limited number of redirects, only to allowed urls.
+fn redirect_policy() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        let prev = attempt.previous().last();
+        if attempt.previous().len() > 10 {
+            attempt.error("Too many redirects")
+        } else if let Err(e) = check_redirect(prev, attempt.url()) {
+            attempt.error(e)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+fn check(url: &Url, local: bool) -> Result<(), Blocked> {
+    if local {
+        check_scheme(url, &["http", "https"])
+    } else {
+        check_url(url)
+    }
+}
+
+fn check_scheme(url: &Url, schemes: &[&str]) -> Result<(), Blocked> {
+    if schemes.contains(&url.scheme()) {
+        Ok(())
+    } else {
+        Err(Blocked(url.to_string()))
+    }
+}
+
+fn check_url(url: &Url) -> Result<(), Blocked> {
+    check_scheme(url, &["http", "https"])?;
+    let Some(host) = url.host_str() else {
+        return Err(Blocked(url.to_string()));
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) if !is_global(ip) => Err(Blocked(url.to_string())),
+        _ => Ok(()),
+    }
+}
+
+fn check_https(url: &Url) -> Result<(), Blocked> {
+    check_scheme(url, &["https"])?;
+    check_url(url)
+}
+
+fn check_redirect(prev: Option<&Url>, next: &Url) -> Result<(), Blocked> {
+    match prev {
+        Some(prev) if prev.scheme() == "https" => check_https(next),
+        _ => check_url(next),
+    }
+}
+
+/// A request to a url that is not allowed.
+#[derive(Debug, thiserror::Error)]
+#[error("Refusing request to {0}")]
+pub struct Blocked(String);
+
+/// A dns resolver that only gives global addresses.
+struct GlobalResolver;
+
+impl Resolve for GlobalResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_global(addr.ip()))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                return Err(Blocked(name.as_str().into()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// True if `ip` is a globally reachable address.
+///
+/// Loopback, private, link-local (including cloud metadata),
+/// shared, documentation and other special-purpose addresses are not.
+fn is_global(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => is_global_v6(ip),
+    }
+}
+
+fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(a == 0
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_documentation()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // Shared address space, for carrier-grade nat.
+        || (a == 100 && (b & 0xc0) == 64)
+        // Protocol assignments.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking.
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved.
+        || a >= 240)
+}
+
+fn is_global_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    if segments[0] == 0x2002 {
+        // 6to4, with an embedded ipv4 address.
+        let [a, b] = segments[1].to_be_bytes();
+        let [c, d] = segments[2].to_be_bytes();
+        return is_global_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    // Only global unicast, except teredo and documentation.
+    (segments[0] & 0xe000) == 0x2000
+        && !(segments[0] == 0x2001 && segments[1] == 0)
+        && !(segments[0] == 0x2001 && segments[1] == 0xdb8)
+}
+
+#[test]
+fn global_addresses() {
+    for ip in ["93.184.215.14", "2a00:1450::64", "::ffff:1.1.1.1"] {
+        assert!(is_global(ip.parse().unwrap()), "{ip}");
+    }
+    for ip in [
+        "0.0.0.0",
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "255.255.255.255",
+        "::",
+        "::1",
+        "::ffff:127.0.0.1",
+        "fc00::1",
+        "fd00:ec2::254",
+        "fe80::1",
+        "2001:db8::1",
+        "2002:7f00:1::",
+        "64:ff9b::a00:1",
+    ] {
+        assert!(!is_global(ip.parse().unwrap()), "{ip}");
+    }
+}
+
+#[test]
+fn checked_urls() {
+    let check = |url: &str| check_url(&Url::parse(url).unwrap()).is_ok();
+    assert!(check("https://example.org/a"));
+    assert!(check("http://93.184.215.14/a"));
+    assert!(!check("http://127.0.0.1:8080/a"));
+    assert!(!check("http://[::1]/a"));
+    assert!(!check("http://169.254.169.254/latest/meta-data/"));
+    assert!(!check("file:///etc/passwd"));
+    assert!(
+        check_https(&Url::parse("http://example.org/").unwrap()).is_err()
+    );
+}
+
+#[tokio::test]
+async fn resolve_only_global() {
+    let name = "localhost".parse().unwrap();
+    assert!(GlobalResolver.resolve(name).await.is_err());
+}
+
+#[tokio::test]
+async fn no_redirect_to_local() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener =
+        tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 4096];
+        let _ = conn.read(&mut buf).await.unwrap();
+        conn.write_all(
+            b"HTTP/1.1 302 Found\r\n\
+              Location: http://127.0.0.1:1/secret\r\n\
+              Content-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    });
+    // Use the inner client, as the first request is to a local
+    // address, to test only the redirect.
+    let err = Outgoing::new(false)
+        .unwrap()
+        .client
+        .get(format!("http://{addr}/start"))
+        .send()
+        .await
+        .unwrap_err();
+    assert!(err.is_redirect(), "{err:?}");
+}
@@ -1,5 +1,5 @@
 //! Read comments from a json dump.  This is kind of a one-time operation.
-use crate::dbopt::DbOpt;
+use crate::dbopt::{DbOpt, notify_changed};
 use crate::models::{DateTime, safe_md2html, year_of_date};
 use crate::schema::comments::dsl as c;
 use crate::schema::posts::dsl as p;
@@ -10,9 +10,11 @@ use diesel::prelude::*;
 use ipnetwork::IpNetwork;
 use lazy_regex::regex_captures;
 use serde::{self, Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::PathBuf;
 use std::str::FromStr;
+use tracing::warn;
 
 #[derive(Parser)]
 pub struct Args {
@@ -35,7 +37,10 @@ impl Args {
         let file = File::open(&self.path)
             .with_context(|| format!("Failed to read {:?}", self.path))?;
 
-        let comments = serde_json::from_reader::<_, Vec<Dumped>>(file)?;
+        let mut comments = serde_json::from_reader::<_, Vec<Dumped>>(file)?;
+        // Make sure each comment is inserted before any replies to it.
+        comments.sort_by_key(|c| c.date.raw());
+        let mut ids = HashMap::new();
 
         if self.purge {
             diesel::delete(c::comments).execute(&mut db)?;
@@ -50,7 +55,14 @@ impl Args {
                 .filter(p::lang.eq(&post.lang))
                 .first(&mut db)?;
 
-            diesel::insert_into(c::comments)
+            let parent = comment.reply_to.and_then(|r| {
+                let parent = ids.get(&r).copied();
+                if parent.is_none() {
+                    warn!("Comment {} replies to missing {r}.", comment.id);
+                }
+                parent
+            });
+            let id = diesel::insert_into(c::comments)
                 .values((
                     c::post_id.eq(post),
                     c::content.eq(comment.html()),
@@ -61,9 +73,14 @@ impl Args {
                     c::from_host.eq(comment.by_ip),
                     c::raw_md.eq(&comment.comment),
                     c::is_public.eq(true),
+                    c::parent_id.eq(parent),
+                    c::by_author.eq(comment.by_author),
                 ))
-                .execute(&mut db)?;
+                .returning(c::id)
+                .get_result::<i32>(&mut db)?;
+            ids.insert(comment.id, id);
         }
+        notify_changed(&mut db)?;
         Ok(())
     }
 }
@@ -83,6 +100,7 @@ impl DumpArgs {
             .filter(c::is_public.eq(true))
             .inner_join(posts::table)
             .select(Dumped::as_select())
+            .order((c::posted_at, c::id))
             .load(&mut self.db.get_db()?)?;
         std::fs::write(&self.path, serde_json::to_string_pretty(&comments)?)?;
         Ok(())
@@ -92,6 +110,15 @@ impl DumpArgs {
 #[derive(Debug, Deserialize, Serialize, Queryable, Selectable)]
 #[diesel(table_name = comments)]
 struct Dumped {
+    /// The id is only used to refer to a comment from replies.
+    #[serde(default)]
+    id: i32,
+    #[diesel(column_name = parent_id)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reply_to: Option<i32>,
+    /// True for comments by the site owner.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    by_author: bool,
     #[diesel(column_name = name)]
     by_name: String,
     #[diesel(column_name = email)]
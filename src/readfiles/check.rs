@@ -0,0 +1,365 @@
+//! Check content files for problems, without storing anything.
+//!
+//! All problems found are reported, with the file and line where they
+//! are, rather than stopping at the first.
+use super::markdown::{ContentMeta, Ctx, find_teaser};
+use super::{
+    ImgClient, ImgClientOpt, asset_spec, codeblocks, md_files, slug_and_lang,
+    web_client,
+};
+use crate::models::MyLang;
+use anyhow::{Result, bail};
+use chrono::{Datelike, Local};
+use lazy_regex::regex_captures;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Tag, TagEnd};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs::read_to_string;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+#[command(
+    mut_arg("base", |a| a.required(false)),
+    mut_arg("user", |a| a.required(false)),
+    mut_arg("password", |a| a.required(false))
+)]
+pub struct Args {
+    /// Check that referenced images exist and are public.
+    ///
+    /// Images are not checked if no image api is given.
+    #[clap(flatten)]
+    img: Option<ImgClientOpt>,
+
+    /// The paths to check.
+    ///
+    /// Links within the site are checked against the content in these
+    /// paths, so give the full content directory to check links.
+    #[clap(value_parser, required = true)]
+    files: Vec<PathBuf>,
+}
+
+impl Args {
+    pub fn run(self) -> Result<()> {
+        let mut files = Vec::new();
+        for path in &self.files {
+            md_files(path, &mut files)?;
+        }
+        files.sort();
+        let imgcli = match self.img {
+            Some(mut img) => {
+                img.make_images_public = false;
+                Some(img.client(web_client()?))
+            }
+            None => None,
+        };
+        let mut checker = Checker {
+            urls: files.iter().filter_map(|f| page_url(f)).collect(),
+            imgcli,
+            images: HashMap::new(),
+            problems: Vec::new(),
+        };
+        for path in &files {
+            checker.check_file(path);
+        }
+        for problem in &checker.problems {
+            println!("{problem}");
+        }
+        match checker.problems.len() {
+            0 => Ok(()),
+            n => bail!("Found {n} problems in {} files", files.len()),
+        }
+    }
+}
+
+struct Checker {
+    /// The urls of all pages in the checked content.
+    urls: BTreeSet<String>,
+    /// Client to check images with, if any.
+    imgcli: Option<ImgClient>,
+    /// Checked images, and the problem with each, if any.
+    images: HashMap<String, Option<String>>,
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn check_file(&mut self, path: &Path) {
+        let (slug, lang) = match slug_and_lang(path) {
+            Ok(sl) => sl,
+            Err(e) => return self.problem(path, None, e),
+        };
+        let lang = match lang.parse::<MyLang>() {
+            Ok(lang) => lang,
+            Err(_) => {
+                return self.problem(
+                    path,
+                    None,
+                    format!("Unknown language {lang:?} in file name"),
+                );
+            }
+        };
+        match read_to_string(path) {
+            Ok(contents) => self.check_markdown(path, &contents, slug, lang),
+            Err(e) => self.problem(path, None, e),
+        }
+    }
+
+    fn check_markdown(
+        &mut self,
+        path: &Path,
+        markdown: &str,
+        slug: &str,
+        lang: MyLang,
+    ) {
+        let at = |range: &Range<usize>| Some(line_of(markdown, range.start));
+        let ctx = Ctx::new(markdown, slug, lang);
+        let mut events = ctx.offset_iter();
+        let meta = match (events.next(), events.next(), events.next()) {
+            (
+                Some((Event::Start(Tag::MetadataBlock(_)), _)),
+                Some((Event::Text(data), range)),
+                Some((Event::End(TagEnd::MetadataBlock(_)), _)),
+            ) => self.check_meta(path, &data, line_of(markdown, range.start)),
+            _ => {
+                self.problem(path, Some(1), "Expected metadata block");
+                return;
+            }
+        };
+        let year = meta
+            .pubdate
+            .map_or_else(|| Local::now().year(), |d| d.year());
+        let files = meta
+            .files()
+            .filter_map(|spec| asset_spec(spec).ok())
+            .map(|(name, _)| (name.into(), format!("/s/{year}/{name}")))
+            .collect();
+        let _ = ctx.set_files(files);
+
+        // The events are parsed again, with the asset names known.
+        let mut events = ctx.offset_iter().skip_while(|(e, _)| {
+            !matches!(e, Event::End(TagEnd::MetadataBlock(_)))
+        });
+        events.next();
+        match events.next() {
+            Some((
+                Event::Start(Tag::Heading {
+                    level: HeadingLevel::H1,
+                    ..
+                }),
+                _,
+            )) => (),
+            Some((_, range)) => {
+                self.problem(path, at(&range), "Expected h1 title")
+            }
+            None => self.problem(path, None, "Expected h1 title"),
+        }
+        let mut body = false;
+        let mut items = Vec::new();
+        for (event, range) in events {
+            match &event {
+                Event::End(TagEnd::Heading(HeadingLevel::H1)) if !body => {
+                    body = true;
+                    continue;
+                }
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(
+                    fence,
+                ))) => {
+                    if let Some(bang) = fence.strip_prefix('!')
+                        && !codeblocks::is_magic(bang)
+                    {
+                        self.problem(
+                            path,
+                            at(&range),
+                            format!("Unknown magic {:?}", fence.as_ref()),
+                        );
+                    }
+                }
+                Event::Start(Tag::Link { dest_url, .. }) => {
+                    if let Some(url) = page_link(dest_url)
+                        && !self.urls.contains(url)
+                    {
+                        self.problem(
+                            path,
+                            at(&range),
+                            format!("Broken link to {url}"),
+                        );
+                    }
+                }
+                Event::Start(Tag::Image {
+                    dest_url, title, ..
+                }) => {
+                    if let Some(problem) = self.check_image(dest_url, title) {
+                        self.problem(path, at(&range), problem);
+                    }
+                }
+                _ => (),
+            }
+            if body {
+                items.push((event, range));
+            }
+        }
+        if !meta.is_meta {
+            let (events, ranges) =
+                items.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+            if let Err(e) = find_teaser(&events) {
+                self.problem(path, ranges.get(e.index).and_then(at), e);
+            }
+        }
+    }
+
+    /// Check the metadata block, one line at a time.
+    ///
+    /// The metadata of the correct lines is returned.
+    fn check_meta(
+        &mut self,
+        path: &Path,
+        data: &str,
+        first_line: usize,
+    ) -> ContentMeta {
+        let mut good = String::new();
+        for (i, line) in data.lines().enumerate() {
+            let at = Some(first_line + i);
+            match line.parse::<ContentMeta>() {
+                Ok(meta) => {
+                    for spec in meta.files() {
+                        self.check_asset(path, at, spec);
+                    }
+                    good.push_str(line);
+                    good.push('\n');
+                }
+                Err(e) => self.problem(path, at, e),
+            }
+        }
+        good.parse().unwrap_or_default()
+    }
+
+    fn check_asset(&mut self, path: &Path, at: Option<usize>, spec: &str) {
+        match asset_spec(spec) {
+            Ok((name, _)) => {
+                let file = path.parent().unwrap_or(Path::new(".")).join(name);
+                if !file.is_file() {
+                    self.problem(path, at, format!("Missing asset {file:?}"));
+                }
+            }
+            Err(e) => self.problem(path, at, format!("{e} {spec:?}")),
+        }
+    }
+
+    /// Check that an rphotos image exists and is public.
+    fn check_image(&mut self, dest_url: &str, title: &str) -> Option<String> {
+        let imgref = if title.is_empty() {
+            // Old format, with the image ref first in the url.
+            regex_captures!(r"^[A-Za-z0-9/._-]*", dest_url)?
+        } else {
+            dest_url
+        };
+        if imgref == "cover" {
+            return None;
+        }
+        let imgcli = self.imgcli.as_mut()?;
+        self.images
+            .entry(imgref.into())
+            .or_insert_with(|| match imgcli.fetch(imgref) {
+                Ok(info) if info.is_public() => None,
+                Ok(_) => Some(format!("Image {imgref:?} is not public")),
+                Err(e) => Some(e.to_string()),
+            })
+            .clone()
+    }
+
+    fn problem(
+        &mut self,
+        path: &Path,
+        line: Option<usize>,
+        message: impl fmt::Display,
+    ) {
+        self.problems.push(Problem {
+            path: path.into(),
+            line,
+            message: message.to_string(),
+        });
+    }
+}
+
+struct Problem {
+    path: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(out, "{}:{line}: ", self.path.display())?,
+            None => write!(out, "{}: ", self.path.display())?,
+        }
+        out.write_str(&self.message)
+    }
+}
+
+/// The url of the page in the markdown file `path`, if it can be read.
+fn page_url(path: &Path) -> Option<String> {
+    let (slug, lang) = slug_and_lang(path).ok()?;
+    let contents = read_to_string(path).ok()?;
+    let ctx = Ctx::new(&contents, slug, lang.parse().ok()?);
+    let src = ctx.parser().ok()?;
+    Some(src.get_url().to_string())
+}
+
+/// The page part of `url`, if it is a link to a post or meta page.
+fn page_link(url: &str) -> Option<&str> {
+    let (_, page) =
+        regex_captures!(r"^(/(?:\d{4}/)?[\w-]+\.[a-z]{2})(?:#.*)?$", url)?;
+    (page != "/search.en" && page != "/search.sv").then_some(page)
+}
+
+/// The (1-based) line number of a byte `offset` in `text`.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+#[test]
+fn check_problems() {
+    let mut checker = Checker {
+        urls: ["/2024/hello.en".to_string()].into(),
+        imgcli: None,
+        images: HashMap::new(),
+        problems: Vec::new(),
+    };
+    checker.check_markdown(
+        Path::new("x.en.md"),
+        "---\n\
+         pubdate: 2024-01-01T10:00:00+01:00\n\
+         colour: blue\n\
+         res: bad spec\n\
+         ---\n\
+         # Title\n\
+         \n\
+         See [hello](/2024/hello.en#x) and [gone](/2023/gone.en).\n\
+         \n\
+         ```!magic\n\
+         x\n\
+         ```\n\
+         \n\
+         | a | b |\n\
+         |---|---|\n\
+         | 1 | 2 |\n",
+        "x",
+        MyLang::En,
+    );
+    assert_eq!(
+        checker
+            .problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        [
+            "x.en.md:3: Unknown metadata \"colour\": \"blue\"",
+            "x.en.md:4: Bad asset spec \"bad spec\"",
+            "x.en.md:8: Broken link to /2023/gone.en",
+            "x.en.md:10: Unknown magic \"!magic\"",
+            "x.en.md:14: Unexpected Start(Table([None, None])) \
+             when finding teaser",
+        ],
+    );
+}
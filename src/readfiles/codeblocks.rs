@@ -22,11 +22,11 @@ pub fn handle(
 ) -> Result<()> {
     if let Some(lang) = lang {
         if let Some(bang) = lang.strip_prefix('!') {
-            match bang.split_once(' ').unwrap_or((bang, "")) {
-                ("leaflet", "") => leaflet(out, code),
-                ("qr", caption) => qr(out, caption, code),
-                ("embed", "") => embed(out, loader, url, code),
-                _ => bail!("Magic for {lang:?} not implemented"),
+            match Magic::parse(bang) {
+                Some(Magic::Leaflet) => leaflet(out, code),
+                Some(Magic::Qr(caption)) => qr(out, caption, code),
+                Some(Magic::Embed) => embed(out, loader, url, code),
+                None => bail!("Magic for {lang:?} not implemented"),
             }
         } else {
             highlight(out, lang, code)
@@ -39,6 +39,31 @@ pub fn handle(
     }
 }
 
+/// True if `bang` (a fence without the `"!"`) is a known magic.
+pub fn is_magic(bang: &str) -> bool {
+    Magic::parse(bang).is_some()
+}
+
+/// The known magic code blocks.
+enum Magic<'a> {
+    Leaflet,
+    /// A qr code, with a caption.
+    Qr(&'a str),
+    Embed,
+}
+
+impl<'a> Magic<'a> {
+    /// Parse `bang`, a fence without the `"!"`.
+    fn parse(bang: &'a str) -> Option<Self> {
+        match bang.split_once(' ').unwrap_or((bang, "")) {
+            ("leaflet", "") => Some(Magic::Leaflet),
+            ("qr", caption) => Some(Magic::Qr(caption)),
+            ("embed", "") => Some(Magic::Embed),
+            _ => None,
+        }
+    }
+}
+
 pub fn highlight(out: &mut String, lang: &str, code: &str) -> Result<()> {
     out.push_str("<pre data-lang=\"");
     escape_html(&mut *out, lang)?;
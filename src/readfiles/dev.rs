@@ -0,0 +1,219 @@
+//! Render content directly from markdown files, for previewing.
+//!
+//! Nothing is written to the database; assets are kept in memory.
+use super::markdown::{Body, ContentParser, Ctx};
+use super::watch::Watcher;
+use super::{ImgClientOpt, Loader, md_files, slug_and_lang, web_client};
+use crate::models::{DateTime, FullPost, MyLang, Post, Tag, Teaser};
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use slug::slugify;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// A post or meta page, rendered to html.
+pub enum Page {
+    Meta {
+        lang: MyLang,
+        title: String,
+        content: String,
+    },
+    /// A post, and its teaser as on the front page.
+    Post {
+        post: Box<FullPost>,
+        teaser: Box<Teaser>,
+    },
+}
+
+/// A markdown file in the content paths.
+pub struct Source {
+    pub path: PathBuf,
+    pub url: String,
+    pub is_post: bool,
+    pub is_draft: bool,
+}
+
+pub struct Renderer {
+    loader: Loader,
+    paths: Vec<PathBuf>,
+}
+
+impl Renderer {
+    pub fn new(img: &ImgClientOpt, paths: Vec<PathBuf>) -> Result<Self> {
+        let web = web_client()?;
+        Ok(Renderer {
+            loader: Loader {
+                include_drafts: true,
+                force: true,
+                dry_run: None,
+                db: None,
+                assets: HashMap::new(),
+                imgcli: img.client(web.clone()),
+                mentioner: None,
+                pending_mentions: Vec::new(),
+                web,
+            },
+            paths,
+        })
+    }
+
+    /// All markdown files in the content paths, drafts included.
+    ///
+    /// A file that can't be parsed gets an url from its name only, so
+    /// that viewing it shows the error.
+    pub fn sources(&self) -> Result<Vec<Source>> {
+        let mut files = Vec::new();
+        for path in &self.paths {
+            md_files(path, &mut files)?;
+        }
+        files.sort();
+        files
+            .into_iter()
+            .map(|path| {
+                let (slug, lang) = slug_and_lang(&path)?;
+                let contents = read_to_string(&path)?;
+                let (url, is_post, is_draft) = match lang.parse() {
+                    Ok(lang) => {
+                        match Ctx::new(&contents, slug, lang).parser() {
+                            Ok(src) => (
+                                src.get_url().to_string(),
+                                !src.meta().is_meta,
+                                src.meta().pubdate.is_none()
+                                    && !src.meta().is_meta,
+                            ),
+                            Err(_) => {
+                                (format!("/{slug}.{lang}"), false, false)
+                            }
+                        }
+                    }
+                    Err(_) => (format!("/{slug}.{lang}"), false, false),
+                };
+                Ok(Source {
+                    path,
+                    url,
+                    is_post,
+                    is_draft,
+                })
+            })
+            .collect()
+    }
+
+    /// Render the file for `slug` in `lang` from `year`, if any.
+    ///
+    /// Meta pages have the year 0 and drafts the current year, as in
+    /// the urls from [`Renderer::sources`].  A file that can't be
+    /// parsed has no year, so its error is shown as a meta page.
+    pub fn render(
+        &mut self,
+        year: i16,
+        slug: &str,
+        lang: MyLang,
+    ) -> Result<Option<Page>> {
+        let name = format!("{slug}.{lang}.md");
+        let mut files = Vec::new();
+        for path in &self.paths {
+            md_files(path, &mut files)?;
+        }
+        for path in files
+            .into_iter()
+            .filter(|f| f.file_name().is_some_and(|f| *f == *name))
+        {
+            let contents = read_to_string(&path)?;
+            let ctx = Ctx::new(&contents, slug, lang);
+            match ctx.parser() {
+                Ok(src) if src.year == year => {
+                    return self.render_src(&path, slug, lang, src).map(Some);
+                }
+                Ok(_) => (),
+                Err(e) if year == 0 => return Err(e),
+                Err(_) => (),
+            }
+        }
+        Ok(None)
+    }
+
+    fn render_src(
+        &mut self,
+        path: &Path,
+        slug: &str,
+        lang: MyLang,
+        mut src: ContentParser,
+    ) -> Result<Page> {
+        src.load_assets(path, &mut self.loader)?;
+        let meta = src.meta();
+        if meta.is_meta {
+            let title = src.load_title(&mut self.loader)?;
+            let content = src.into_html(&mut self.loader)?;
+            return Ok(Page::Meta {
+                lang,
+                title,
+                content,
+            });
+        }
+        let posted_at = meta.pubdate.map_or_else(Utc::now, Into::into);
+        let updated_at =
+            meta.update.as_ref().map_or(posted_at, |u| u.date.into());
+        let tags = meta
+            .tags
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .map(|name| {
+                let name = name.trim();
+                Ok(Tag {
+                    id: 0,
+                    slug: slugify(name)
+                        .parse()
+                        .map_err(|()| anyhow!("Bad tag {name:?}"))?,
+                    name: name.into(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let body = Body::load(src, &mut self.loader)?;
+        let post = |content| -> Result<Post> {
+            Ok(Post {
+                id: 0,
+                slug: slug.parse().map_err(|()| anyhow!("Bad slug"))?,
+                lang,
+                title: body.title.clone(),
+                posted_at: DateTime::wrap(posted_at),
+                updated_at: DateTime::wrap(updated_at),
+                content,
+            })
+        };
+        let is_more = body.teaser != body.body;
+        let teaser = Teaser::new(post(body.teaser.clone())?, tags, is_more);
+        let post = FullPost::new(
+            post(body.body.clone())?,
+            body.front_image,
+            body.summary,
+            body.use_leaflet,
+        );
+        Ok(Page::Post {
+            post: Box::new(post),
+            teaser: Box::new(teaser),
+        })
+    }
+
+    /// The mime type and content of an asset from a rendered page.
+    pub fn asset(&self, year: i16, name: &str) -> Option<(&str, &[u8])> {
+        self.loader
+            .assets
+            .get(&format!("/s/{year}/{name}"))
+            .map(|(mime, content)| (mime.as_str(), content.as_slice()))
+    }
+}
+
+/// Call `changed` each time some files in `paths` are changed.
+pub fn watch(paths: &[PathBuf], mut changed: impl FnMut()) -> Result<()> {
+    let mut watcher = Watcher::new()?;
+    for path in paths {
+        watcher.add(path)?;
+    }
+    loop {
+        let files = watcher.wait()?;
+        info!("Changed: {files:?}");
+        changed();
+    }
+}
@@ -0,0 +1,116 @@
+//! Report what reading files would change, without changing anything.
+use similar::TextDiff;
+use std::fmt::Display;
+
+/// Counts of what a dry run found, and would have done.
+#[derive(Default)]
+pub struct Report {
+    new: u32,
+    updated: u32,
+    retagged: u32,
+    unchanged: u32,
+    assets: u32,
+}
+
+impl Report {
+    pub fn new_page(&mut self, page: impl Display) {
+        println!("New: {page}");
+        self.new += 1;
+    }
+
+    /// Compare the stored and new version of an existing page.
+    ///
+    /// Each field is `(name, stored, new)`.  The tags, if given, are
+    /// `(stored, new)`, and compared without regard to order or case.
+    pub fn page(
+        &mut self,
+        page: impl Display,
+        fields: &[(&str, &str, &str)],
+        tags: Option<(Vec<String>, Vec<String>)>,
+    ) {
+        let diffs = fields
+            .iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(name, old, new)| diff(name, old, new))
+            .collect::<Vec<_>>();
+        let tags = tags.and_then(|(old, new)| {
+            let (old, new) = (tag_list(old), tag_list(new));
+            (old != new).then(|| format!("Tags: {old} -> {new}"))
+        });
+        if !diffs.is_empty() {
+            println!("Updated: {page}");
+            self.updated += 1;
+        } else if tags.is_some() {
+            println!("Retagged: {page}");
+            self.retagged += 1;
+        } else {
+            println!("Unchanged: {page}");
+            self.unchanged += 1;
+            return;
+        }
+        if let Some(tags) = tags {
+            println!("{tags}");
+        }
+        for diff in diffs {
+            print!("{diff}");
+        }
+    }
+
+    /// An asset would be created (if `id` is `None`) or updated.
+    pub fn asset(&mut self, id: Option<i32>, url: &str) {
+        match id {
+            Some(id) => println!("Updated asset: #{id} {url}"),
+            None => println!("New asset: {url}"),
+        }
+        self.assets += 1;
+    }
+
+    pub fn summary(&self) {
+        println!(
+            "Dry run: {} new, {} updated, {} retagged, {} unchanged, \
+             {} assets to store.",
+            self.new,
+            self.updated,
+            self.retagged,
+            self.unchanged,
+            self.assets,
+        );
+    }
+}
+
+/// A unified diff of the `name` field, from `old` to `new`.
+fn diff(name: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .missing_newline_hint(false)
+        .header(&format!("{name} (stored)"), &format!("{name} (new)"))
+        .to_string()
+}
+
+fn tag_list(tags: Vec<String>) -> String {
+    let mut tags = tags
+        .into_iter()
+        .map(|t| t.trim().to_lowercase())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.join(", ")
+}
+
+#[test]
+fn diff_content() {
+    assert_eq!(
+        diff(
+            "content",
+            "<p>One</p>\n<p>\"Two\"</p>\n<p>Three</p>\n",
+            "<p>One</p>\n<p><q>Two</q></p>\n<p>Three</p>\n",
+        ),
+        "--- content (stored)\n\
+         +++ content (new)\n\
+         @@ -1,3 +1,3 @@\n \
+         <p>One</p>\n\
+         -<p>\"Two\"</p>\n\
+         +<p><q>Two</q></p>\n \
+         <p>Three</p>\n",
+    );
+}
@@ -7,8 +7,8 @@ use chrono::{Datelike, Local};
 use i18n_embed_fl::fl;
 use lazy_regex::{regex_captures, regex_replace_all};
 use pulldown_cmark::{
-    BrokenLink, BrokenLinkCallback, CowStr, Event, Options, Parser, Tag,
-    TagEnd,
+    BrokenLink, BrokenLinkCallback, CowStr, Event, OffsetIter, Options,
+    Parser, Tag, TagEnd,
 };
 use std::cell::OnceCell;
 use std::path::Path;
@@ -54,6 +54,16 @@ impl<'a> Ctx<'a> {
         })
     }
 
+    /// All markdown events, with their ranges in the source.
+    pub fn offset_iter(&self) -> OffsetIter<'_, &Ctx<'_>> {
+        Parser::new_with_broken_link_callback(
+            self.markdown,
+            Options::all(),
+            Some(self),
+        )
+        .into_offset_iter()
+    }
+
     pub fn set_files(&self, files: Vec<(String, String)>) -> Result<()> {
         self.files
             .set(files.clone())
@@ -202,7 +212,7 @@ impl FromStr for ContentMeta {
                 }
                 ("update", v) => result.update = Some(v.parse()?),
                 ("meta", _) => result.is_meta = true,
-                (k, v) => todo!("Handle metadata {k:?}; {v:?}"),
+                (k, v) => bail!("Unknown metadata {k:?}: {v:?}"),
             }
         }
         Ok(result)
@@ -266,7 +276,7 @@ impl Body {
         let body = html::collect(items.iter().cloned(), loader, &url)?;
 
         let (teaser, summary) =
-            if let Some(teaser_items) = find_teaser(&items) {
+            if let Some(teaser_items) = find_teaser(&items)? {
                 let mut teaser_extra = String::new();
                 let extra_teaser = match &data.meta.update {
                     Some(update) if !update.info.is_empty() => {
@@ -345,20 +355,41 @@ fn img_is_front(title: &str, dest_url: &str) -> bool {
     title.contains("front") || dest_url.contains("front")
 }
 
-fn find_teaser<'a>(all: &'a [Event<'a>]) -> Option<&'a [Event<'a>]> {
-    all.iter()
-        .position(|e| {
-            matches!(e, Event::Html(s) if s.as_ref() == "<!-- more -->\n")
-        })
-        .map(|pos| pos - 1)
-        .or_else(|| find_teaser_by_size(all))
-        .map(|end| {
-            debug!("Tesaser is {end} items out of {}", all.len());
-            &all[..end]
-        })
+/// Find the teaser part of the items of a post (excluding the title).
+pub(super) fn find_teaser<'a>(
+    all: &'a [Event<'a>],
+) -> Result<Option<&'a [Event<'a>]>, TeaserError> {
+    let end = match all.iter().position(
+        |e| matches!(e, Event::Html(s) if s.as_ref() == "<!-- more -->\n"),
+    ) {
+        Some(pos) => Some(pos - 1),
+        None => find_teaser_by_size(all)?,
+    };
+    Ok(end.map(|end| {
+        debug!("Tesaser is {end} items out of {}", all.len());
+        &all[..end]
+    }))
 }
 
-fn find_teaser_by_size<'a>(all: &'a [Event<'a>]) -> Option<usize> {
+/// An item that the teaser weighting can't handle.
+#[derive(Debug, thiserror::Error)]
+#[error("Unexpected {event} when finding teaser")]
+pub struct TeaserError {
+    /// The index of the item.
+    pub index: usize,
+    event: String,
+}
+
+impl TeaserError {
+    fn new(index: usize, event: &Event) -> Self {
+        let event = format!("{event:?}");
+        TeaserError { index, event }
+    }
+}
+
+fn find_teaser_by_size<'a>(
+    all: &'a [Event<'a>],
+) -> Result<Option<usize>, TeaserError> {
     let low_limit = 720;
     let high_limit = 1100;
 
@@ -368,45 +399,45 @@ fn find_teaser_by_size<'a>(all: &'a [Event<'a>]) -> Option<usize> {
     while let Some((i, e)) = enumerated.next() {
         if weight > low_limit {
             debug!("Weight stop at {i} ({weight} before {e:?})");
-            return Some(i - 1);
+            return Ok(Some(i - 1));
         }
 
         match e {
             Event::Start(Tag::Paragraph) => {
                 let (_ii, mut w, has_img) =
-                    inline_until(enumerated.by_ref(), TagEnd::Paragraph);
+                    inline_until(enumerated.by_ref(), TagEnd::Paragraph)?;
                 w += 80;
                 debug!("Paragraph at {i} ({has_img} after {weight}) is {w}");
                 weight += w;
                 let extra = if i > 0 && has_img { 400 } else { 0 };
                 if weight + extra > high_limit {
-                    return Some(i - 1);
+                    return Ok(Some(i - 1));
                 }
             }
             Event::Start(Tag::BlockQuote(kind)) => {
                 let (_ii, mut w, _) = inline_until(
                     enumerated.by_ref(),
                     TagEnd::BlockQuote(*kind),
-                );
+                )?;
                 w += 150;
                 debug!("Blockquote at {i} (after {weight}) is {w}");
                 weight += w;
                 if weight > high_limit {
-                    return Some(i - 1);
+                    return Ok(Some(i - 1));
                 }
             }
             Event::Start(Tag::CodeBlock(_)) => {
                 let (_ii, mut w, _) =
-                    inline_until(enumerated.by_ref(), TagEnd::CodeBlock);
+                    inline_until(enumerated.by_ref(), TagEnd::CodeBlock)?;
                 w += 100;
                 debug!("Codeblock at {i} (after {weight}) is {w}");
                 weight += w;
                 if weight > high_limit {
-                    return Some(i - 1);
+                    return Ok(Some(i - 1));
                 }
             }
             Event::Start(Tag::HtmlBlock) => {
-                for (_ii, e) in enumerated.by_ref() {
+                for (ii, e) in enumerated.by_ref() {
                     match e {
                         Event::End(TagEnd::HtmlBlock) => {
                             break;
@@ -415,40 +446,43 @@ fn find_teaser_by_size<'a>(all: &'a [Event<'a>]) -> Option<usize> {
                             weight += s.len() / 4;
                             if weight > high_limit {
                                 debug!("Html block reached {weight}, stop.");
-                                return Some(i - 1);
+                                return Ok(Some(i - 1));
                             }
                         }
-                        e => todo!("Handle {e:?} in html block"),
+                        e => return Err(TeaserError::new(ii, e)),
                     }
                 }
             }
             Event::Start(Tag::List(_)) => {
                 if weight + 200 > low_limit {
-                    return Some(i - 1);
+                    return Ok(Some(i - 1));
                 }
             }
             Event::End(TagEnd::List(_)) => (), // i += 1),
             Event::Start(Tag::Item) => {
                 let (_ii, mut w, _) =
-                    inline_until(enumerated.by_ref(), TagEnd::Item);
+                    inline_until(enumerated.by_ref(), TagEnd::Item)?;
                 w += 30;
                 debug!("Item at {i} (after {weight}) is {w}");
                 weight += w;
                 if weight > high_limit {
-                    return Some(i - 1);
+                    return Ok(Some(i - 1));
                 }
             }
 
             // No sections or chapters in the teaser!
-            Event::Start(Tag::Heading { .. }) => return Some(i - 1),
+            Event::Start(Tag::Heading { .. }) => return Ok(Some(i - 1)),
 
-            e => todo!("Unexpected root-level at {i}: {e:?}"),
+            e => return Err(TeaserError::new(i, e)),
         }
     }
-    None
+    Ok(None)
 }
 
-fn inline_until<'a, I>(items: &mut I, end: TagEnd) -> (usize, usize, bool)
+fn inline_until<'a, I>(
+    items: &mut I,
+    end: TagEnd,
+) -> Result<(usize, usize, bool), TeaserError>
 where
     I: Iterator<Item = (usize, &'a Event<'a>)>,
 {
@@ -457,11 +491,11 @@ where
     while let Some((ii, e)) = items.next() {
         match e {
             Event::End(e) if *e == end => {
-                return (ii, weight, has_img);
+                return Ok((ii, weight, has_img));
             }
             Event::Start(Tag::Paragraph) => {
                 let (_, w, h_i) =
-                    inline_until(items.by_ref(), TagEnd::Paragraph);
+                    inline_until(items.by_ref(), TagEnd::Paragraph)?;
                 has_img |= h_i;
                 weight += w + 60;
             }
@@ -488,7 +522,7 @@ where
                 | TagEnd::Image,
             ) => (),
 
-            e => todo!("Handle {e:?} in inline"),
+            e => return Err(TeaserError::new(ii, e)),
         }
     }
     unreachable!("Inline ended before file");
@@ -0,0 +1,359 @@
+//! Send webmentions, or pingbacks, for outbound links in posts.
+use crate::models::unescape_html;
+use crate::outgoing::blocking::Outgoing;
+use crate::schema::sent_mentions::dsl as sm;
+use anyhow::{Result, bail};
+use diesel::prelude::*;
+use lazy_regex::regex;
+use reqwest::Url;
+use reqwest::header::{CONTENT_TYPE, HeaderName, LINK};
+use std::collections::BTreeSet;
+use std::io::Read;
+use tracing::{debug, info, warn};
+
+/// The largest part of a target page to look for an endpoint in.
+const MAX_PAGE: u64 = 1 << 20;
+
+/// Sends mentions, only to targets and endpoints on global addresses.
+pub struct Mentioner {
+    web: Outgoing,
+    base: Url,
+}
+
+impl Mentioner {
+    pub fn new(web: Outgoing, base: &str) -> Result<Self> {
+        Ok(Mentioner {
+            web,
+            base: Url::parse(base)?,
+        })
+    }
+
+    /// Notify the outbound links of a post that are not notified yet.
+    ///
+    /// Links that are removed from the post are notified once more, so
+    /// the target can remove the mention.  A link is recorded as sent
+    /// even if the target has no endpoint, and is intentionally not
+    /// tried again, as most pages never get one.  A link where sending
+    /// failed is not recorded, so it is tried again with the next
+    /// update of the post.
+    pub fn send(
+        &self,
+        post_id: i32,
+        path: &str,
+        html: &str,
+        db: &mut PgConnection,
+    ) -> Result<()> {
+        let source = self.base.join(path)?;
+        let links = outbound_links(html, &self.base);
+        let sent = sm::sent_mentions
+            .select(sm::target)
+            .filter(sm::post_id.eq(post_id))
+            .load::<String>(db)?
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        for target in links.difference(&sent) {
+            if let Some(endpoint) = self.notify(&source, target) {
+                diesel::insert_into(sm::sent_mentions)
+                    .values((
+                        sm::post_id.eq(post_id),
+                        sm::target.eq(target),
+                        sm::endpoint.eq(endpoint),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(db)?;
+            }
+        }
+        for target in sent.difference(&links) {
+            if self.notify(&source, target).is_some() {
+                diesel::delete(sm::sent_mentions)
+                    .filter(sm::post_id.eq(post_id))
+                    .filter(sm::target.eq(target))
+                    .execute(db)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Notify `target` that `source` links to it.
+    ///
+    /// Returns None on failure, or the endpoint used, which is None if
+    /// the target has no endpoint.
+    fn notify(&self, source: &Url, target: &str) -> Option<Option<String>> {
+        let result = Url::parse(target)
+            .map_err(Into::into)
+            .and_then(|target| self.discover(&target))
+            .and_then(|endpoint| match endpoint {
+                Some(endpoint) => {
+                    self.send_to(&endpoint, source, target)?;
+                    Ok(Some(endpoint))
+                }
+                None => Ok(None),
+            });
+        match result {
+            Ok(Some(endpoint)) => {
+                info!(%source, target, ?endpoint, "Sent mention.");
+                Some(Some(endpoint.url().to_string()))
+            }
+            Ok(None) => {
+                debug!(target, "No mention endpoint.");
+                Some(None)
+            }
+            Err(err) => {
+                warn!(%source, target, "Failed to send mention: {err:#}");
+                None
+            }
+        }
+    }
+
+    /// Find the webmention or pingback endpoint of `target`, if any.
+    fn discover(&self, target: &Url) -> Result<Option<Endpoint>> {
+        let resp = self.web.get(target)?.send()?.error_for_status()?;
+        let url = resp.url().clone();
+        let header = |name| {
+            resp.headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        let links = header(LINK);
+        let pingback = header(HeaderName::from_static("x-pingback"));
+        let is_html = header(CONTENT_TYPE)
+            .first()
+            .is_some_and(|t| t.contains("html"));
+        if let Some(href) =
+            links.iter().find_map(|h| link_header_webmention(h))
+        {
+            return self
+                .endpoint(&url, &href, Endpoint::Webmention)
+                .map(Some);
+        }
+        let mut html = String::new();
+        if is_html {
+            let mut page = Vec::new();
+            resp.take(MAX_PAGE).read_to_end(&mut page)?;
+            html = String::from_utf8_lossy(&page).into_owned();
+        }
+        if let Some(href) = html_link(&html, "webmention") {
+            self.endpoint(&url, &href, Endpoint::Webmention).map(Some)
+        } else if let Some(href) = pingback
+            .into_iter()
+            .next()
+            .or_else(|| html_link(&html, "pingback"))
+        {
+            self.endpoint(&url, &href, Endpoint::Pingback).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The endpoint at `href` from `page`, if it may be posted to.
+    ///
+    /// The endpoint is chosen by the target page, so it may point
+    /// anywhere, such as into the local network.
+    fn endpoint(
+        &self,
+        page: &Url,
+        href: &str,
+        kind: fn(Url) -> Endpoint,
+    ) -> Result<Endpoint> {
+        let url = page.join(href)?;
+        self.web.check(&url)?;
+        Ok(kind(url))
+    }
+
+    fn send_to(
+        &self,
+        endpoint: &Endpoint,
+        source: &Url,
+        target: &str,
+    ) -> Result<()> {
+        match endpoint {
+            Endpoint::Webmention(url) => {
+                self.web
+                    .post(url)?
+                    .form(&[("source", source.as_str()), ("target", target)])
+                    .send()?
+                    .error_for_status()?;
+            }
+            Endpoint::Pingback(url) => {
+                let response = self
+                    .web
+                    .post(url)?
+                    .header(CONTENT_TYPE, "text/xml")
+                    .body(pingback_call(source.as_str(), target))
+                    .send()?
+                    .error_for_status()?
+                    .text()?;
+                if response.contains("<fault>") {
+                    bail!("Pingback fault: {response}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Endpoint {
+    Webmention(Url),
+    Pingback(Url),
+}
+
+impl Endpoint {
+    fn url(&self) -> &Url {
+        match self {
+            Endpoint::Webmention(url) | Endpoint::Pingback(url) => url,
+        }
+    }
+}
+
+/// The absolute links in `html` to other sites than `base`.
+fn outbound_links(html: &str, base: &Url) -> BTreeSet<String> {
+    regex!(r#"(?i)<a\s[^>]*\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#)
+        .captures_iter(html)
+        .filter_map(|c| c.get(1).or(c.get(2)).or(c.get(3)))
+        .filter_map(|href| Url::parse(&unescape_html(href.as_str())).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .filter(|url| url.origin() != base.origin())
+        .map(|mut url| {
+            url.set_fragment(None);
+            url.into()
+        })
+        .collect()
+}
+
+/// The webmention endpoint in a `Link` header value, if any.
+fn link_header_webmention(header: &str) -> Option<String> {
+    regex!(r"<([^>]*)>([^<]*)")
+        .captures_iter(header)
+        .find_map(|c| {
+            let params = c.get(2)?.as_str();
+            regex!(r#"(?i)\brel\s*=\s*(?:"([^"]*)"|([^\s;,]+))"#)
+                .captures_iter(params)
+                .filter_map(|r| r.get(1).or(r.get(2)))
+                .any(|rel| has_word(rel.as_str(), "webmention"))
+                .then(|| c[1].to_string())
+        })
+}
+
+/// The href of the first `link` or `a` element in `html` with `rel`.
+fn html_link(html: &str, rel: &str) -> Option<String> {
+    regex!(r"(?i)<(?:link|a)\s[^>]*>")
+        .find_iter(html)
+        .find_map(|tag| {
+            let mut has_rel = false;
+            let mut href = None;
+            for attr in regex!(
+                r#"(?i)\b(rel|href)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#
+            )
+            .captures_iter(tag.as_str())
+            {
+                let value = attr.get(2).or(attr.get(3)).or(attr.get(4));
+                let value = value.map(|v| v.as_str()).unwrap_or_default();
+                if attr[1].eq_ignore_ascii_case("rel") {
+                    has_rel = has_word(value, rel);
+                } else {
+                    href = Some(unescape_html(value));
+                }
+            }
+            href.filter(|_| has_rel)
+        })
+}
+
+fn has_word(words: &str, word: &str) -> bool {
+    words
+        .split_whitespace()
+        .any(|w| w.eq_ignore_ascii_case(word))
+}
+
+fn pingback_call(source: &str, target: &str) -> String {
+    let param = |url: &str| {
+        format!(
+            "<param><value><string>{}</string></value></param>",
+            url.replace('&', "&amp;").replace('<', "&lt;"),
+        )
+    };
+    format!(
+        "<?xml version=\"1.0\"?>\n<methodCall>\
+         <methodName>pingback.ping</methodName>\
+         <params>{}{}</params></methodCall>\n",
+        param(source),
+        param(target),
+    )
+}
+
+#[test]
+fn find_outbound_links() {
+    let base = Url::parse("https://rasmus.krats.se").unwrap();
+    let html = "<p><a href='https://lib.rs/crates/r4s#top'>r4s</a>, \
+                <a href=\"/2024/hello.en\">hello</a>, \
+                <a href=\"https://rasmus.krats.se/2023/x.en\">x</a>, \
+                <a href=\"mailto:a@example.org\">mail</a>, \
+                <a class=\"wp\" href=\"https://example.org/?a=1&amp;b=2\">\
+                a</a>.</p>";
+    assert_eq!(
+        outbound_links(html, &base).into_iter().collect::<Vec<_>>(),
+        ["https://example.org/?a=1&b=2", "https://lib.rs/crates/r4s"],
+    );
+}
+
+#[test]
+fn find_link_header_endpoint() {
+    assert_eq!(
+        link_header_webmention(
+            "<https://example.org/>; rel=\"me\", </wm?x=1>; rel=\"webmention\""
+        )
+        .as_deref(),
+        Some("/wm?x=1"),
+    );
+    assert_eq!(
+        link_header_webmention("<https://example.org/wm>; rel=webmention")
+            .as_deref(),
+        Some("https://example.org/wm"),
+    );
+    assert_eq!(link_header_webmention("</style.css>; rel=preload"), None);
+}
+
+#[test]
+fn find_html_endpoint() {
+    let html = "<link rel=\"stylesheet\" href=\"/s.css\">\
+                <link href='/webmention' rel='other webmention'>\
+                <link rel=pingback href=/xmlrpc.php>";
+    assert_eq!(
+        html_link(html, "webmention").as_deref(),
+        Some("/webmention")
+    );
+    assert_eq!(html_link(html, "pingback").as_deref(), Some("/xmlrpc.php"));
+    assert_eq!(
+        html_link("<a rel=webmention href=\"\">", "webmention"),
+        Some("".into())
+    );
+    assert_eq!(html_link(html, "me"), None);
+}
+
+#[test]
+fn refuse_local_endpoint() {
+    let mentioner = Mentioner::new(
+        Outgoing::new(false).unwrap(),
+        "https://rasmus.krats.se",
+    )
+    .unwrap();
+    let page = Url::parse("https://example.org/post").unwrap();
+    let endpoint =
+        |href| mentioner.endpoint(&page, href, Endpoint::Webmention);
+    assert!(endpoint("/webmention").is_ok());
+    assert!(endpoint("http://127.0.0.1:8080/webmention").is_err());
+    assert!(endpoint("http://[::1]/xmlrpc.php").is_err());
+    assert!(endpoint("http://169.254.169.254/latest/").is_err());
+
+    // Also when sending to an endpoint that is found some other way.
+    let local =
+        Endpoint::Webmention(Url::parse("http://127.0.0.1:1/").unwrap());
+    let source = Url::parse("https://rasmus.krats.se/2024/x.en").unwrap();
+    let err = mentioner
+        .send_to(&local, &source, "https://example.org/post")
+        .unwrap_err();
+    assert!(err.is::<crate::outgoing::Blocked>(), "{err:?}");
+}
@@ -1,12 +1,21 @@
+pub mod check;
 mod codeblocks;
+pub mod dev;
+mod dryrun;
 mod html;
 mod imgcli;
 mod markdown;
+mod mentions;
 mod summary;
+mod watch;
 
+use self::dryrun::Report;
 use self::markdown::{Body, ContentParser, Ctx};
-use crate::dbopt::DbOpt;
+use self::mentions::Mentioner;
+use self::watch::Watcher;
+use crate::dbopt::{DbOpt, notify_changed};
 use crate::models::{MyLang, year_of_date};
+use crate::outgoing::blocking::Outgoing;
 use crate::schema::assets::dsl as a;
 use crate::schema::metapages::dsl as m;
 use crate::schema::post_tags::dsl as pt;
@@ -19,6 +28,7 @@ use lazy_regex::regex_captures;
 use reqwest::blocking::Client;
 use reqwest::header::CONTENT_TYPE;
 use slug::slugify;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs::{read, read_to_string};
 use std::path::{Path, PathBuf};
@@ -52,44 +62,165 @@ pub struct Args {
     /// Not for use on the production server.
     #[clap(long)]
     include_drafts: bool,
+
+    /// Base url of the site, as the source of sent webmentions.
+    #[clap(long, env = "R4S_BASE")]
+    public_base: Option<String>,
+
+    /// Send webmentions for links in new or updated posts.
+    ///
+    /// Webmentions (or pingbacks) are sent for links to other sites
+    /// in published posts, after all files are read and the server
+    /// is notified of the changes.  This requires the base url.
+    #[clap(long, requires = "public_base", conflicts_with = "dry_run")]
+    send_webmentions: bool,
+
+    /// Allow webmentions to local and private addresses.
+    ///
+    /// Only for testing, with a local webmention receiver.
+    #[clap(long)]
+    allow_local_requests: bool,
+
+    /// Keep running, and read files again when they are changed.
+    ///
+    /// A changed markdown file is read again, and a changed asset is
+    /// reloaded through the posts in the same directory using it.
+    #[clap(long)]
+    watch: bool,
+
+    /// Don't change anything, but report what would be changed.
+    ///
+    /// All files are read and rendered, even if unchanged, and compared
+    /// to the stored content.  Changes are shown as unified diffs of
+    /// the rendered html.  Images are not made public.
+    #[clap(long, conflicts_with = "watch")]
+    dry_run: bool,
 }
 
 impl Args {
     pub fn run(self) -> Result<()> {
-        let web = Client::builder()
-            .user_agent("r4s https://github.com/kaj/r4s")
-            .build()?;
+        let web = web_client()?;
+        let mut img = self.img.clone();
+        img.make_images_public &= !self.dry_run;
         let mut loader = Loader {
             include_drafts: self.include_drafts,
             force: self.force,
-            db: self.db.get_db()?,
-            imgcli: self.img.client(web.clone()),
+            dry_run: self.dry_run.then(Report::default),
+            db: Some(self.db.get_db()?),
+            assets: HashMap::new(),
+            imgcli: img.client(web.clone()),
+            mentioner: self
+                .public_base
+                .filter(|_| self.send_webmentions)
+                .map(|base| {
+                    let web = Outgoing::new(self.allow_local_requests)?;
+                    Mentioner::new(web, &base)
+                })
+                .transpose()?,
+            pending_mentions: Vec::new(),
             web,
         };
-        for path in &self.files {
+        let result = self.files.iter().try_for_each(|path| {
             debug!("Searching path {path:?}");
             if path.is_file() {
                 loader
                     .read_file(path)
-                    .with_context(|| format!("Reading file {path:?}"))?;
+                    .with_context(|| format!("Reading file {path:?}"))
             } else {
                 loader
                     .read_dir(path)
-                    .with_context(|| format!("Reading dir {path:?}"))?;
+                    .with_context(|| format!("Reading dir {path:?}"))
             }
+        });
+        if let Some(report) = &loader.dry_run {
+            report.summary();
+            return result;
+        }
+        // Notify even on failure, as some files may have been read.
+        notify_changed(loader.db()?)?;
+        loader.send_mentions()?;
+        if self.watch {
+            if let Err(e) = result {
+                warn!("{e:?}");
+            }
+            loader.watch(&self.files)
+        } else {
+            result
         }
-        Ok(())
     }
 }
 
 struct Loader {
     include_drafts: bool,
     force: bool,
-    db: PgConnection,
+    /// When doing a dry run, report changes here rather than doing them.
+    dry_run: Option<Report>,
+    /// The database, or `None` when only rendering content.
+    db: Option<PgConnection>,
+    /// Assets by url, kept in memory when there is no database.
+    assets: HashMap<String, (String, Vec<u8>)>,
     web: Client,
     imgcli: ImgClient,
+    mentioner: Option<Mentioner>,
+    /// Posts to send mentions for, once the changes are visible.
+    pending_mentions: Vec<PendingMention>,
+}
+
+/// A published post that is new or updated.
+struct PendingMention {
+    id: i32,
+    url: String,
+    html: String,
 }
 impl Loader {
+    fn db(&mut self) -> Result<&mut PgConnection> {
+        self.db.as_mut().context("No database")
+    }
+
+    fn watch(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let mut watcher = Watcher::new()?;
+        for path in paths {
+            watcher.add(path)?;
+        }
+        info!("Watching for changes.");
+        loop {
+            for path in watcher.wait()? {
+                if let Err(e) = self.reload(&path) {
+                    warn!("Reading {path:?}: {e:?}");
+                }
+            }
+            notify_changed(self.db()?)?;
+            if let Err(e) = self.send_mentions() {
+                warn!("Sending mentions: {e:?}");
+            }
+        }
+    }
+
+    /// Read a changed file again.
+    ///
+    /// A changed asset is reloaded by reading the posts using it.
+    fn reload(&mut self, path: &Path) -> Result<()> {
+        if path.extension().unwrap_or_default() == "md" {
+            return self.read_file(path);
+        }
+        let name = path.file_name().context("No file name")?;
+        let dir = path.parent().context("No directory")?;
+        for entry in dir.read_dir()? {
+            let post = entry?.path();
+            if post.extension().unwrap_or_default() == "md"
+                && !is_dotfile(&post)
+                && uses_asset(&post, name)?
+            {
+                info!("Asset {name:?} changed, reading {post:?}");
+                let force = std::mem::replace(&mut self.force, true);
+                let result = self.read_file(&post);
+                self.force = force;
+                result?;
+            }
+        }
+        Ok(())
+    }
+
     fn read_dir(&mut self, path: &Path) -> Result<()> {
         for entry in path.read_dir()? {
             let entry = entry?;
@@ -109,12 +240,7 @@ impl Loader {
 
     #[tracing::instrument(skip(self))]
     fn read_file(&mut self, path: &Path) -> Result<()> {
-        let (slug, lang) = path
-            .file_stem()
-            .and_then(std::ffi::OsStr::to_str)
-            .context("Bad file name")?
-            .split_once('.')
-            .context("No language in file name")?;
+        let (slug, lang) = slug_and_lang(path)?;
         let contents = read_to_string(path)?;
 
         let ctx = Ctx::new(&contents, slug, lang.parse()?);
@@ -131,9 +257,10 @@ impl Loader {
         let pubdate = post_src.meta().pubdate;
         let update = post_src.meta().update.as_ref().map(|u| u.date);
 
-        if update
-            .or(pubdate)
-            .is_none_or(|d| (Utc::now() - d.to_utc()).num_days() < 200)
+        if self.dry_run.is_none()
+            && update
+                .or(pubdate)
+                .is_none_or(|d| (Utc::now() - d.to_utc()).num_days() < 200)
         {
             diesel::delete(
                 p::posts
@@ -142,7 +269,7 @@ impl Loader {
                     .filter(p::title.like("% \u{1f58b}"))
                     .filter(p::orig_md.ne(&contents)),
             )
-            .execute(&mut self.db)?;
+            .execute(self.db()?)?;
         }
 
         if let Some((id, old_md)) = p::posts
@@ -150,15 +277,14 @@ impl Loader {
             .filter(year_of_date(p::posted_at).eq(&post_src.year))
             .filter(p::slug.eq(slug))
             .filter(p::lang.eq(lang))
-            .first::<(i32, String)>(&mut self.db)
+            .first::<(i32, String)>(self.db()?)
             .optional()?
         {
-            if has_changed(&old_md, &contents) || self.force {
-                info!(
-                    "Post #{} {} exists, but should be updated.",
-                    id,
-                    post_src.get_url(),
-                );
+            if self.dry_run.is_some() {
+                return self.dry_run_post(id, post_src, path);
+            } else if has_changed(&old_md, &contents) || self.force {
+                let url = post_src.get_url().to_string();
+                info!("Post #{id} {url} exists, but should be updated.");
                 post_src.load_assets(path, self)?;
                 let tags = post_src.meta().tags.clone();
                 let post = Body::load(post_src, self)?;
@@ -175,21 +301,29 @@ impl Loader {
                         p::use_leaflet.eq(post.use_leaflet),
                         p::orig_md.eq(&contents),
                     ))
-                    .execute(&mut self.db)
+                    .execute(self.db()?)
                     .with_context(|| format!("Update #{id}"))?;
 
                 if let Some(tags) = &tags {
-                    tag_post(id, tags, &mut self.db)?;
+                    tag_post(id, tags, self.db()?)?;
+                }
+                if pubdate.is_some() {
+                    self.queue_mentions(id, url, post.body);
                 }
             } else {
                 trace!("No change in #{id} {}", post_src.get_url());
             }
         } else {
-            info!("New post {}", post_src.get_url());
+            let url = post_src.get_url().to_string();
+            info!("New post {url}");
 
             post_src.load_assets(path, self)?;
             let tags = post_src.meta().tags.clone();
             let post = Body::load(post_src, self)?;
+            if let Some(report) = &mut self.dry_run {
+                report.new_page(format_args!("post {url}"));
+                return Ok(());
+            }
 
             let post_id = diesel::insert_into(p::posts)
                 .values((
@@ -209,10 +343,81 @@ impl Loader {
                     p::orig_md.eq(&contents),
                 ))
                 .returning(p::id)
-                .get_result::<i32>(&mut self.db)
+                .get_result::<i32>(self.db()?)
                 .context("Insert post")?;
             if let Some(tags) = &tags {
-                tag_post(post_id, tags, &mut self.db)?;
+                tag_post(post_id, tags, self.db()?)?;
+            }
+            if pubdate.is_some() {
+                self.queue_mentions(post_id, url, post.body);
+            }
+        }
+        Ok(())
+    }
+
+    /// Render an existing post and report how it differs from the stored.
+    fn dry_run_post(
+        &mut self,
+        id: i32,
+        post_src: ContentParser,
+        path: &Path,
+    ) -> Result<()> {
+        let url = post_src.get_url().to_string();
+        post_src.load_assets(path, self)?;
+        let tags = post_src.meta().tags.clone();
+        let post = Body::load(post_src, self)?;
+
+        let db = self.db()?;
+        let (title, teaser, content, description) = p::posts
+            .select((p::title, p::teaser, p::content, p::description))
+            .filter(p::id.eq(id))
+            .first::<(String, String, String, String)>(db)?;
+        let tags = match tags {
+            Some(tags) => Some((
+                pt::post_tags
+                    .inner_join(t::tags)
+                    .select(t::name)
+                    .filter(pt::post_id.eq(id))
+                    .load::<String>(db)?,
+                tags.split(',').map(String::from).collect(),
+            )),
+            None => None,
+        };
+        if let Some(report) = &mut self.dry_run {
+            report.page(
+                format_args!("post #{id} {url}"),
+                &[
+                    ("title", &title, &post.title),
+                    ("teaser", &teaser, &post.teaser),
+                    ("content", &content, &post.body),
+                    ("description", &description, &post.summary),
+                ],
+                tags,
+            );
+        }
+        Ok(())
+    }
+
+    /// Remember to send mentions for a post, if sending is enabled.
+    ///
+    /// The mentions are sent by [`Loader::send_mentions`], since the
+    /// receivers will fetch the post to verify the mention, and should
+    /// not get a cached page from before the change.
+    fn queue_mentions(&mut self, id: i32, url: String, html: String) {
+        if self.mentioner.is_some() {
+            self.pending_mentions.push(PendingMention { id, url, html });
+        }
+    }
+
+    /// Send the queued mentions.
+    ///
+    /// Call this after `notify_changed`, so the changes are visible.
+    fn send_mentions(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending_mentions);
+        if let Some(mentioner) = &self.mentioner {
+            let db = self.db.as_mut().context("No database")?;
+            for post in pending {
+                mentioner.send(post.id, &post.url, &post.html, db)?;
             }
         }
         Ok(())
@@ -229,18 +434,32 @@ impl Loader {
         if let Some(tags) = &src.meta().tags {
             bail!("Meta pages should not have tags, got {tags:?}");
         }
-        if let Some((id, old_md)) = m::metapages
-            .select((m::id, m::orig_md))
+        if let Some((id, old_md, old_title, old_body)) = m::metapages
+            .select((m::id, m::orig_md, m::title, m::content))
             .filter(m::slug.eq(slug))
             .filter(m::lang.eq(lang))
-            .first::<(i32, String)>(&mut self.db)
+            .first::<(i32, String, String, String)>(self.db()?)
             .optional()?
         {
-            if has_changed(&old_md, contents) || self.force {
+            if self.dry_run.is_some()
+                || has_changed(&old_md, contents)
+                || self.force
+            {
                 src.load_assets(path, self)?;
                 let title = src.load_title(self)?;
                 let body = src.into_html(self)?;
 
+                if let Some(report) = &mut self.dry_run {
+                    report.page(
+                        format_args!("metapage #{id} /{slug}.{lang}"),
+                        &[
+                            ("title", &old_title, &title),
+                            ("content", &old_body, &body),
+                        ],
+                        None,
+                    );
+                    return Ok(());
+                }
                 diesel::update(m::metapages)
                     .set((
                         m::title.eq(&title),
@@ -248,7 +467,7 @@ impl Loader {
                         m::orig_md.eq(contents),
                     ))
                     .filter(m::id.eq(id))
-                    .execute(&mut self.db)
+                    .execute(self.db()?)
                     .context("Upadte metapage")?;
                 info!("Updated metadata page /{}.{}", slug, lang);
             }
@@ -256,6 +475,10 @@ impl Loader {
             src.load_assets(path, self)?;
             let title = src.load_title(self)?;
             let body = src.into_html(self)?;
+            if let Some(report) = &mut self.dry_run {
+                report.new_page(format_args!("metapage /{slug}.{lang}"));
+                return Ok(());
+            }
 
             diesel::insert_into(m::metapages)
                 .values((
@@ -265,7 +488,7 @@ impl Loader {
                     m::content.eq(&body),
                     m::orig_md.eq(&contents),
                 ))
-                .execute(&mut self.db)
+                .execute(self.db()?)
                 .context("Insert metapage")?;
             info!("Created metapage /{}.{}: {}", slug, lang, title);
         }
@@ -278,9 +501,7 @@ impl Loader {
         spec: &str,
         year: i16,
     ) -> Result<(String, String)> {
-        let (_all, name, _, mime) =
-            regex_captures!(r"^([\w_\.-]+)\s+(\{([\w-]+/[\w-]+)\})$", spec)
-                .context("Bad asset spec")?;
+        let (name, mime) = asset_spec(spec)?;
         let path = path.parent().unwrap_or_else(|| Path::new(".")).join(name);
         let content =
             read(&path).with_context(|| path.display().to_string())?;
@@ -305,14 +526,24 @@ impl Loader {
         mime: &str,
         content: &[u8],
     ) -> Result<String> {
+        let url = format!("/s/{year}/{name}");
+        let Some(db) = &mut self.db else {
+            self.assets
+                .insert(url.clone(), (mime.into(), content.into()));
+            return Ok(url);
+        };
         if let Some((id, old_mime, old_content)) = a::assets
             .select((a::id, a::mime, a::content))
             .filter(a::year.eq(year))
             .filter(a::name.eq(name))
-            .first::<(i32, String, Vec<u8>)>(&mut self.db)
+            .first::<(i32, String, Vec<u8>)>(db)
             .optional()?
         {
             if mime != old_mime || content != old_content {
+                if let Some(report) = &mut self.dry_run {
+                    report.asset(Some(id), &url);
+                    return Ok(url);
+                }
                 println!("Content #{id} ({name}) updating");
                 diesel::update(a::assets)
                     .filter(a::id.eq(id))
@@ -322,11 +553,13 @@ impl Loader {
                         a::mime.eq(mime),
                         a::content.eq(&content),
                     ))
-                    .execute(&mut self.db)
+                    .execute(db)
                     .with_context(|| {
                         format!("Update asset #{id} {year}/{name}")
                     })?;
             }
+        } else if let Some(report) = &mut self.dry_run {
+            report.asset(None, &url);
         } else {
             diesel::insert_into(a::assets)
                 .values((
@@ -335,11 +568,66 @@ impl Loader {
                     a::mime.eq(mime),
                     a::content.eq(content),
                 ))
-                .execute(&mut self.db)
+                .execute(db)
                 .with_context(|| format!("Create asset {year}/{name}"))?;
         }
-        Ok(format!("/s/{year}/{name}"))
+        Ok(url)
+    }
+}
+
+/// The file name and mime type of a `res:` spec like `f.txt {text/plain}`.
+fn asset_spec(spec: &str) -> Result<(&str, &str)> {
+    let (_all, name, _, mime) =
+        regex_captures!(r"^([\w_\.-]+)\s+(\{([\w-]+/[\w-]+)\})$", spec)
+            .context("Bad asset spec")?;
+    Ok((name, mime))
+}
+
+/// Add all markdown files in `path` to `files`.
+///
+/// If `path` is a file, it is added as is.
+fn md_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.into());
+        return Ok(());
+    }
+    for entry in path.read_dir()? {
+        let path = entry?.path();
+        if is_dotfile(&path) {
+            continue;
+        } else if path.is_dir() {
+            md_files(&path, files)?;
+        } else if path.extension().unwrap_or_default() == "md" {
+            files.push(path);
+        }
     }
+    Ok(())
+}
+
+fn web_client() -> Result<Client> {
+    Ok(Client::builder()
+        .user_agent("r4s https://github.com/kaj/r4s")
+        .build()?)
+}
+
+fn slug_and_lang(path: &Path) -> Result<(&str, &str)> {
+    path.file_stem()
+        .and_then(std::ffi::OsStr::to_str)
+        .context("Bad file name")?
+        .split_once('.')
+        .context("No language in file name")
+}
+
+/// True if the markdown file `post` has the asset `name`.
+fn uses_asset(post: &Path, name: &std::ffi::OsStr) -> Result<bool> {
+    let (slug, lang) = slug_and_lang(post)?;
+    let contents = read_to_string(post)?;
+    let ctx = Ctx::new(&contents, slug, lang.parse()?);
+    let post_src = ctx.parser()?;
+    Ok(post_src
+        .meta()
+        .files()
+        .any(|spec| spec.split_whitespace().next() == name.to_str()))
 }
 
 fn is_dotfile(path: &Path) -> bool {
@@ -508,7 +796,7 @@ fn wikilink(text: &str, lang: &str, disambig: &str) -> (String, String) {
 }
 
 #[derive(Clone, clap::Parser)]
-struct ImgClientOpt {
+pub struct ImgClientOpt {
     /// Base url for rphotos image api client.
     #[clap(long = "image-base", env = "IMG_URL")]
     base: String,
@@ -0,0 +1,156 @@
+//! Watch content directories for changed files, using inotify.
+use super::is_dotfile;
+use anyhow::{Context, Result};
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::fd::OwnedFd;
+use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Time to wait for more changes after a change.
+///
+/// Editors may write a file in several steps, and it is better to read
+/// it once when done.
+const SETTLE: Timespec = Timespec {
+    tv_sec: 0,
+    tv_nsec: 200_000_000,
+};
+
+pub struct Watcher {
+    fd: OwnedFd,
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    pub fn new() -> Result<Self> {
+        Ok(Watcher {
+            fd: inotify::init(CreateFlags::CLOEXEC)
+                .context("Initialize inotify")?,
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// Watch `path` and all directories below it.
+    ///
+    /// If `path` is a file, the directory containing it is watched.
+    pub fn add(&mut self, path: &Path) -> Result<()> {
+        if path.is_dir() {
+            self.add_dir(path)?;
+            for entry in path.read_dir()? {
+                let path = entry?.path();
+                if path.is_dir() && !is_dotfile(&path) {
+                    self.add(&path)?;
+                }
+            }
+            Ok(())
+        } else {
+            self.add_dir(path.parent().unwrap_or(Path::new(".")))
+        }
+    }
+
+    fn add_dir(&mut self, dir: &Path) -> Result<()> {
+        let wd = inotify::add_watch(
+            &self.fd,
+            dir,
+            WatchFlags::CLOSE_WRITE
+                | WatchFlags::MOVED_TO
+                | WatchFlags::CREATE
+                | WatchFlags::ONLYDIR,
+        )
+        .with_context(|| format!("Watch {dir:?}"))?;
+        debug!("Watching {dir:?}");
+        self.dirs.insert(wd, dir.into());
+        Ok(())
+    }
+
+    /// Wait until some files are changed, and get them.
+    ///
+    /// Files in new directories are included, and the new directories
+    /// are watched as well.
+    pub fn wait(&mut self) -> Result<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        loop {
+            let timeout = (!changed.is_empty()).then_some(&SETTLE);
+            let mut fds = [PollFd::new(&self.fd, PollFlags::IN)];
+            if poll(&mut fds, timeout)? == 0 {
+                return Ok(changed);
+            }
+            let mut events = Vec::new();
+            let mut reader = inotify::Reader::new(&self.fd, &mut buf);
+            loop {
+                let event = reader.next()?;
+                events.push((
+                    event.wd(),
+                    event.events(),
+                    event.file_name().map(|n| {
+                        PathBuf::from(OsStr::from_bytes(n.to_bytes()))
+                    }),
+                ));
+                if reader.is_buffer_empty() {
+                    break;
+                }
+            }
+            for (wd, flags, name) in events {
+                if flags.contains(ReadFlags::QUEUE_OVERFLOW) {
+                    warn!("Too many changes, some may be missed.");
+                }
+                let (Some(dir), Some(name)) = (self.dirs.get(&wd), name)
+                else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if is_dotfile(&path) || path.to_string_lossy().ends_with('~')
+                {
+                    continue;
+                }
+                if flags.contains(ReadFlags::ISDIR) {
+                    self.add(&path)?;
+                    add_files(&path, &mut changed)?;
+                } else if flags
+                    .intersects(ReadFlags::CLOSE_WRITE | ReadFlags::MOVED_TO)
+                {
+                    changed.insert(path);
+                }
+            }
+        }
+    }
+}
+
+/// Add all files in `dir` and its subdirectories to `files`.
+fn add_files(dir: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if is_dotfile(&path) {
+            continue;
+        } else if path.is_dir() {
+            add_files(&path, files)?;
+        } else {
+            files.insert(path);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn watch_changes() {
+    let dir = std::env::temp_dir()
+        .join(format!("r4s-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut watcher = Watcher::new().unwrap();
+    watcher.add(&dir).unwrap();
+    std::fs::write(dir.join("post.en.md"), "# Post").unwrap();
+    std::fs::write(dir.join(".post.en.md.swp"), "x").unwrap();
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub").join("a.txt"), "a").unwrap();
+    let changed = watcher.wait().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        changed.into_iter().collect::<Vec<_>>(),
+        [dir.join("post.en.md"), dir.join("sub").join("a.txt")],
+    );
+}
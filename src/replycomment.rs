@@ -0,0 +1,167 @@
+//! Write author replies to comments.
+use crate::dbopt::{DbOpt, notify_changed};
+use crate::models::{Comment, PostComment, PostLink, safe_md2html};
+use crate::schema::comments::dsl as c;
+use crate::schema::posts::dsl as p;
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use diesel::prelude::*;
+use ipnetwork::IpNetwork;
+use std::io::Write as _;
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::Command;
+
+#[derive(Parser)]
+pub struct Args {
+    #[clap(flatten)]
+    db: DbOpt,
+
+    #[clap(flatten)]
+    author: AuthorOpt,
+
+    /// Id of the comment to reply to.
+    id: i32,
+}
+
+impl Args {
+    pub fn run(self) -> Result<()> {
+        let mut db = self.db.get_db()?;
+        let comment = c::comments
+            .inner_join(p::posts)
+            .select((Comment::as_select(), PostLink::as_select()))
+            .filter(c::id.eq(self.id))
+            .filter(c::is_public)
+            .first::<PostComment>(&mut db)
+            .optional()?
+            .with_context(|| {
+                format!("No public comment {}, moderate it first?", self.id)
+            })?;
+        if !self.author.reply(&comment, &mut db)? {
+            println!("Empty reply, nothing posted.");
+        }
+        Ok(())
+    }
+}
+
+/// How the site owner is presented in author comments.
+#[derive(Parser)]
+pub struct AuthorOpt {
+    /// Name to sign author replies with.
+    #[clap(long, env = "R4S_AUTHOR_NAME", default_value = "Rasmus Kaj")]
+    author_name: String,
+
+    /// Email for author replies, used only for the gravatar.
+    #[clap(long, env = "R4S_AUTHOR_EMAIL", default_value = "")]
+    author_email: String,
+
+    /// Url to link author replies to.
+    #[clap(long, env = "R4S_AUTHOR_URL")]
+    author_url: Option<String>,
+}
+
+impl AuthorOpt {
+    /// Write a reply to `comment` in an editor and post it.
+    ///
+    /// Returns false if the reply was empty, and nothing was posted.
+    pub fn reply(
+        &self,
+        comment: &PostComment,
+        db: &mut PgConnection,
+    ) -> Result<bool> {
+        let raw_md = c::comments
+            .select(c::raw_md)
+            .filter(c::id.eq(comment.id))
+            .first::<String>(db)?;
+        let reply = edit(&reply_template(comment, &raw_md))?;
+        if reply.is_empty() {
+            return Ok(false);
+        }
+        let id = diesel::insert_into(c::comments)
+            .values((
+                c::post_id.eq(comment.post_id),
+                c::content.eq(safe_md2html(&reply)),
+                c::name.eq(&self.author_name),
+                c::email.eq(&self.author_email),
+                self.author_url.as_ref().map(|u| c::url.eq(u)),
+                c::from_host
+                    .eq(IpNetwork::from(IpAddr::from(Ipv4Addr::LOCALHOST))),
+                c::raw_md.eq(&reply),
+                c::is_public.eq(true),
+                c::parent_id.eq(comment.id),
+                c::by_author.eq(true),
+            ))
+            .returning(c::id)
+            .get_result::<i32>(db)?;
+        notify_changed(db)?;
+        println!("Posted reply {id} to {}", comment.url());
+        Ok(true)
+    }
+}
+
+/// Marks the end of the reply in the file to edit.
+const CUT: &str = "# ------------------------ >8 ------------------------";
+
+fn reply_template(comment: &PostComment, raw_md: &str) -> String {
+    let mut text = format!(
+        "\n{CUT}\n# Write your reply above the line.  \
+         Everything below it is ignored.\n\
+         # An empty reply is not posted.\n#\n\
+         # {} on {:?} wrote:\n#\n",
+        comment.name,
+        comment.post_title(),
+    );
+    for line in raw_md.lines() {
+        text.push_str("# > ");
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+/// Let the user edit `template` in their editor.
+///
+/// Returns the text before the [`CUT`] line, trimmed.
+fn edit(template: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    // A new file, that only the user can read and write.
+    let mut file = tempfile::Builder::new()
+        .prefix("r4s-reply-")
+        .suffix(".md")
+        .tempfile()?;
+    file.write_all(template.as_bytes())?;
+    file.flush()?;
+    let path = file.path();
+    // Like git, run the editor through the shell to allow arguments.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to run {editor:?}"))?;
+    // Read by path, since the editor may replace the file.
+    let text = std::fs::read_to_string(path);
+    file.close()?;
+    if !status.success() {
+        bail!("Editor {editor:?} failed: {status}");
+    }
+    Ok(strip_template(&text?))
+}
+
+fn strip_template(text: &str) -> String {
+    let text = text.split_once(CUT).map(|(reply, _)| reply).unwrap_or(text);
+    text.trim().into()
+}
+
+#[test]
+fn strip_reply() {
+    let text = format!("\nThanks!\n\nSee you.\n\n{CUT}\n# > Hello\n");
+    assert_eq!(strip_template(&text), "Thanks!\n\nSee you.");
+}
+
+#[test]
+fn strip_empty_reply() {
+    assert_eq!(strip_template(&format!("\n \n{CUT}\n# > Hi\n")), "");
+}
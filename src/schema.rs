@@ -1,5 +1,24 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    ap_deliveries (post_id, lang, inbox) {
+        post_id -> Int4,
+        lang -> Varchar,
+        inbox -> Varchar,
+        delivered_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    ap_followers (id) {
+        id -> Int4,
+        lang -> Varchar,
+        actor -> Varchar,
+        inbox -> Varchar,
+        followed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     assets (id) {
         id -> Int4,
@@ -24,6 +43,11 @@ diesel::table! {
         raw_md -> Text,
         is_public -> Bool,
         is_spam -> Bool,
+        parent_id -> Nullable<Int4>,
+        by_author -> Bool,
+        origin -> Nullable<Varchar>,
+        avatar -> Nullable<Varchar>,
+        origin_actor -> Nullable<Varchar>,
     }
 }
 
@@ -65,6 +89,23 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sent_mentions (post_id, target) {
+        post_id -> Int4,
+        target -> Varchar,
+        endpoint -> Nullable<Varchar>,
+        sent_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    spam_tokens (token) {
+        token -> Varchar,
+        n_spam -> Int4,
+        n_ham -> Int4,
+    }
+}
+
 diesel::table! {
     tags (id) {
         id -> Int4,
@@ -73,10 +114,36 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webmentions (id) {
+        id -> Int4,
+        post_id -> Int4,
+        source -> Varchar,
+        title -> Nullable<Varchar>,
+        received_at -> Timestamptz,
+        from_host -> Inet,
+        is_public -> Bool,
+        is_spam -> Bool,
+    }
+}
+
+diesel::joinable!(ap_deliveries -> posts (post_id));
 diesel::joinable!(comments -> posts (post_id));
 diesel::joinable!(post_tags -> posts (post_id));
 diesel::joinable!(post_tags -> tags (tag_id));
+diesel::joinable!(sent_mentions -> posts (post_id));
+diesel::joinable!(webmentions -> posts (post_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
-    assets, comments, metapages, post_tags, posts, tags,
+    ap_deliveries,
+    ap_followers,
+    assets,
+    comments,
+    metapages,
+    post_tags,
+    posts,
+    sent_mentions,
+    spam_tokens,
+    tags,
+    webmentions,
 );
@@ -0,0 +1,975 @@
+//! A minimal [ActivityPub](https://www.w3.org/TR/activitypub/) server.
+//!
+//! Each language of the site is an actor, found by webfinger as
+//! `acct:{lang}@{host}`, that can be followed from the fediverse.
+//! New posts are delivered to the followers of the actor.
+mod reply;
+mod signature;
+
+pub use self::signature::{Key, KeyError};
+
+use self::signature::{Signature, SignatureError};
+use super::error::{ViewError, ViewResult};
+use super::language::MYLANGS;
+use super::{App, Result, TrustedProxies, fl, goh, response};
+use crate::models::{MyLang, Teaser};
+use crate::outgoing::Blocked;
+use crate::schema::ap_deliveries::dsl as d;
+use crate::schema::ap_followers::dsl as f;
+use bytes::Bytes;
+use chrono::{TimeDelta, Utc};
+use clap::Parser;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use lazy_regex::regex_replace_all;
+use reqwest::Url;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+use warp::filters::{BoxedFilter, body, header, path::FullPath};
+use warp::http::HeaderMap;
+use warp::path::{end, param, path};
+use warp::reply::Response;
+use warp::{Filter, Reply, post, query};
+
+#[derive(Clone, Parser)]
+pub struct ApOpt {
+    /// PEM file with the private key of the ActivityPub actors.
+    ///
+    /// The site can only be followed from the fediverse if this is
+    /// given.  Create a key with
+    /// `openssl genpkey -algorithm rsa -pkeyopt rsa_keygen_bits:2048`.
+    #[clap(long, env = "R4S_AP_KEY")]
+    ap_key: Option<PathBuf>,
+}
+
+impl ApOpt {
+    pub fn key(&self) -> Result<Option<Key>, KeyError> {
+        self.ap_key.as_deref().map(Key::load).transpose()
+    }
+}
+
+const ACTIVITY_JSON: &str = "application/activity+json";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+pub fn webfinger_route(s: BoxedFilter<(App,)>) -> BoxedFilter<(impl Reply,)> {
+    path("webfinger")
+        .and(end())
+        .and(goh())
+        .and(query())
+        .and(s)
+        .then(webfinger)
+        .boxed()
+}
+
+pub fn routes(
+    proxies: TrustedProxies,
+    s: BoxedFilter<(App,)>,
+) -> BoxedFilter<(impl Reply,)> {
+    let actor = param().and(end()).and(goh()).and(s.clone()).then(actor);
+    let outbox = param()
+        .and(path("outbox"))
+        .and(end())
+        .and(goh())
+        .and(s.clone())
+        .then(outbox);
+    let followers = param()
+        .and(path("followers"))
+        .and(end())
+        .and(goh())
+        .and(s.clone())
+        .then(followers);
+    let article = param()
+        .and(path("post"))
+        .and(param())
+        .and(end())
+        .and(goh())
+        .and(s.clone())
+        .then(article);
+    let inbox = param()
+        .and(path("inbox"))
+        .and(end())
+        .and(post())
+        .and(proxies.filter())
+        .and(warp::path::full())
+        .and(header::headers_cloned())
+        .and(body::content_length_limit(1 << 20))
+        .and(body::bytes())
+        .and(s)
+        .then(inbox);
+    actor
+        .or(outbox)
+        .unify()
+        .or(followers)
+        .unify()
+        .or(article)
+        .unify()
+        .or(inbox)
+        .unify()
+        .boxed()
+}
+
+#[derive(Debug, Deserialize)]
+struct WebfingerQuery {
+    resource: String,
+}
+
+#[instrument]
+async fn webfinger(query: WebfingerQuery, app: App) -> Result<Response> {
+    app.ap.as_ref().ok_or(ViewError::NotFound)?;
+    let base = Url::parse(&app.base).or_ise()?;
+    let lang =
+        resource_lang(&query.resource, &base).ok_or(ViewError::NotFound)?;
+    let actor = actor_id(&app.base, lang);
+    json_response(
+        "application/jrd+json",
+        &json!({
+            "subject": format!("acct:{lang}@{}", authority(&base)),
+            "aliases": [actor],
+            "links": [
+                {"rel": "self", "type": ACTIVITY_JSON, "href": actor},
+                {
+                    "rel": "http://webfinger.net/rel/profile-page",
+                    "type": "text/html",
+                    "href": format!("{}/{lang}", app.base),
+                },
+            ],
+        }),
+    )
+}
+
+/// The language of the actor that a webfinger `resource` refers to.
+///
+/// The resource is either an `acct:` uri or the url of the actor.
+fn resource_lang(resource: &str, base: &Url) -> Option<MyLang> {
+    if let Some(acct) = resource.strip_prefix("acct:") {
+        let (user, host) = acct.rsplit_once('@')?;
+        if host.eq_ignore_ascii_case(&authority(base)) {
+            user.parse().ok()
+        } else {
+            None
+        }
+    } else {
+        let url = Url::parse(resource).ok()?;
+        if url.origin() != base.origin() {
+            return None;
+        }
+        url.path().strip_prefix("/ap/")?.parse().ok()
+    }
+}
+
+/// The host and (non-default) port of `url`.
+fn authority(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.into(),
+    }
+}
+
+fn actor_id(base: &str, lang: MyLang) -> String {
+    format!("{base}/ap/{lang}")
+}
+
+#[instrument]
+async fn actor(lang: MyLang, app: App) -> Result<Response> {
+    let key = app.ap.as_ref().ok_or(ViewError::NotFound)?;
+    let fluent = lang.fluent();
+    let id = actor_id(&app.base, lang);
+    json_response(
+        ACTIVITY_JSON,
+        &json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                "https://w3id.org/security/v1",
+            ],
+            "id": id,
+            "type": "Person",
+            "preferredUsername": lang.as_ref(),
+            // The site name has zero-width spaces for line breaking.
+            "name": fl!(fluent, "sitename").replace('\u{200b}', ""),
+            "summary": fl!(fluent, "tagline"),
+            "url": format!("{}/{lang}", app.base),
+            "inbox": format!("{id}/inbox"),
+            "outbox": format!("{id}/outbox"),
+            "followers": format!("{id}/followers"),
+            "manuallyApprovesFollowers": false,
+            "discoverable": true,
+            "publicKey": {
+                "id": format!("{id}#main-key"),
+                "owner": id,
+                "publicKeyPem": key.public_pem(),
+            },
+        }),
+    )
+}
+
+#[instrument]
+async fn outbox(lang: MyLang, app: App) -> Result<Response> {
+    app.ap.as_ref().ok_or(ViewError::NotFound)?;
+    let mut db = app.db().await?;
+    let posts = Teaser::recent(lang.as_ref(), 20, 0, &mut db).await?;
+    let id = actor_id(&app.base, lang);
+    json_response(
+        ACTIVITY_JSON,
+        &json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": format!("{id}/outbox"),
+            "type": "OrderedCollection",
+            "totalItems": posts.len(),
+            "orderedItems": posts
+                .iter()
+                .map(|post| create(&app.base, lang, post))
+                .collect::<Vec<_>>(),
+        }),
+    )
+}
+
+#[instrument]
+async fn followers(lang: MyLang, app: App) -> Result<Response> {
+    app.ap.as_ref().ok_or(ViewError::NotFound)?;
+    let mut db = app.db().await?;
+    let n = f::ap_followers
+        .filter(f::lang.eq(lang.as_ref()))
+        .count()
+        .get_result::<i64>(&mut db)
+        .await?;
+    json_response(
+        ACTIVITY_JSON,
+        &json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": format!("{}/followers", actor_id(&app.base, lang)),
+            "type": "OrderedCollection",
+            "totalItems": n,
+        }),
+    )
+}
+
+#[instrument]
+async fn article(lang: MyLang, id: i32, app: App) -> Result<Response> {
+    app.ap.as_ref().ok_or(ViewError::NotFound)?;
+    let mut db = app.db().await?;
+    let post = Teaser::by_id(id, lang.as_ref(), &mut db)
+        .await?
+        .ok_or(ViewError::NotFound)?;
+    let mut doc = article_object(&app.base, lang, &post);
+    doc["@context"] = "https://www.w3.org/ns/activitystreams".into();
+    json_response(ACTIVITY_JSON, &doc)
+}
+
+/// The ActivityPub object for a post, by the `lang` actor.
+fn article_object(base: &str, lang: MyLang, post: &Teaser) -> Value {
+    let actor = actor_id(base, lang);
+    let url = format!("{base}{}", post.url());
+    json!({
+        "id": format!("{actor}/post/{}", post.id),
+        "type": "Article",
+        "attributedTo": actor,
+        "name": post.title,
+        "content": format!(
+            "{}\n<p><a href='{url}'>{}</a></p>",
+            absolute_links(&post.content, base),
+            post.readmore(),
+        ),
+        "url": url,
+        "published": post.posted_at.raw().to_rfc3339(),
+        "updated": post.updated_at.raw().to_rfc3339(),
+        "to": [PUBLIC],
+        "cc": [format!("{actor}/followers")],
+    })
+}
+
+/// Make site-relative links and images in `html` absolute.
+fn absolute_links(html: &str, base: &str) -> String {
+    regex_replace_all!(
+        r#"\b(href|src)=(["'])/([^/])"#,
+        html,
+        |_, attr, quote, first| format!("{attr}={quote}{base}/{first}"),
+    )
+    .into_owned()
+}
+
+/// The activity of creating a post, by the `lang` actor.
+fn create(base: &str, lang: MyLang, post: &Teaser) -> Value {
+    let object = article_object(base, lang, post);
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#create", object["id"].as_str().unwrap_or_default()),
+        "type": "Create",
+        "actor": object["attributedTo"],
+        "published": object["published"],
+        "to": object["to"],
+        "cc": object["cc"],
+        "object": object,
+    })
+}
+
+fn json_response(
+    content_type: &'static str,
+    doc: &Value,
+) -> Result<Response> {
+    response()
+        .header(CONTENT_TYPE, content_type)
+        .body(doc.to_string().into())
+        .or_ise()
+}
+
+/// An incoming activity, with the fields needed to handle it.
+#[derive(Debug, Deserialize)]
+struct Activity {
+    #[serde(rename = "type")]
+    kind: String,
+    actor: String,
+    #[serde(default)]
+    object: Value,
+}
+
+#[instrument(skip(headers, body))]
+async fn inbox(
+    lang: MyLang,
+    ip: IpAddr,
+    path: FullPath,
+    headers: HeaderMap,
+    body: Bytes,
+    app: App,
+) -> Result<Response> {
+    let key = app.ap.as_ref().ok_or(ViewError::NotFound)?;
+    let raw = serde_json::from_slice::<Value>(&body)
+        .map_err(|e| ViewError::BadRequest(format!("Bad json: {e}")))?;
+    let activity = serde_json::from_value::<Activity>(raw.clone())
+        .map_err(|e| ViewError::BadRequest(format!("Bad activity: {e}")))?;
+    if activity.kind == "Delete" && activity.object == activity.actor {
+        // A deleted actor, that can't be verified as the key is gone.
+        // Any follow will be removed when delivery fails.
+        return accepted();
+    }
+    let signer = verify(&app, key, lang, path.as_str(), &headers, &body)
+        .await
+        .map_err(|e| {
+            info!(actor = activity.actor, "Bad signature: {e}");
+            ViewError::BadRequest(e.to_string())
+        })?;
+    if signer.id != activity.actor {
+        return Err(ViewError::BadRequest("Signer is not the actor".into()));
+    }
+    let actor = actor_id(&app.base, lang);
+    match activity.kind.as_str() {
+        "Follow" => {
+            if activity.object != actor.as_str() {
+                return Err(ViewError::BadRequest("Unknown actor".into()));
+            }
+            let mut db = app.db().await?;
+            let id = diesel::insert_into(f::ap_followers)
+                .values((
+                    f::lang.eq(lang.as_ref()),
+                    f::actor.eq(&signer.id),
+                    f::inbox.eq(signer.delivery_inbox()),
+                ))
+                .on_conflict((f::lang, f::actor))
+                .do_update()
+                .set(f::inbox.eq(signer.delivery_inbox()))
+                .returning(f::id)
+                .get_result::<i32>(&mut db)
+                .await?;
+            info!(id, follower = signer.id, "New follower.");
+            let accept = json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": format!("{actor}#accept-{id}"),
+                "type": "Accept",
+                "actor": actor,
+                "object": raw,
+            });
+            tokio::spawn(async move {
+                let key = app.ap.as_ref().unwrap();
+                if let Err(e) =
+                    post_signed(&app, key, lang, &signer.inbox, &accept).await
+                {
+                    warn!(follower = signer.id, "Failed to accept: {e}");
+                }
+            });
+        }
+        "Undo" if activity.object["type"] == "Follow" => {
+            let mut db = app.db().await?;
+            diesel::delete(f::ap_followers)
+                .filter(f::lang.eq(lang.as_ref()))
+                .filter(f::actor.eq(&signer.id))
+                .execute(&mut db)
+                .await?;
+            info!(follower = signer.id, "Unfollowed.");
+        }
+        "Create" => {
+            reply::create(&app, ip, &signer, &activity.object).await?
+        }
+        "Delete" => reply::delete(&app, &signer, &activity.object).await?,
+        kind => info!(kind, actor = signer.id, "Ignoring activity."),
+    }
+    accepted()
+}
+
+fn accepted() -> Result<Response> {
+    response()
+        .status(reqwest::StatusCode::ACCEPTED)
+        .body("".into())
+        .or_ise()
+}
+
+/// An actor on another server.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteActor {
+    id: String,
+    inbox: String,
+    #[serde(default)]
+    endpoints: Endpoints,
+    public_key: Option<PublicKey>,
+    name: Option<String>,
+    preferred_username: Option<String>,
+    #[serde(default)]
+    url: Value,
+    #[serde(default)]
+    icon: Value,
+}
+
+impl RemoteActor {
+    /// The display name of the actor.
+    fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .filter(|n| !n.trim().is_empty())
+            .or(self.preferred_username.as_deref())
+            .unwrap_or(&self.id)
+    }
+    /// The profile page of the actor.
+    ///
+    /// The id is used if there is no http(s) profile url.  The id is
+    /// known to be a http(s) url, since the actor was fetched by it.
+    fn profile_url(&self) -> &str {
+        link_href(&self.url)
+            .filter(|u| is_web_url(u))
+            .unwrap_or(&self.id)
+    }
+    /// The url of the avatar image of the actor, if any.
+    fn avatar(&self) -> Option<&str> {
+        link_href(&self.icon).filter(|u| is_web_url(u))
+    }
+
+    /// The inbox to deliver public posts to.
+    fn delivery_inbox(&self) -> &str {
+        self.endpoints
+            .shared_inbox
+            .as_deref()
+            .unwrap_or(&self.inbox)
+    }
+}
+
+/// The url of a link, that may be a plain url, a link or image
+/// object, or a list of those.
+fn link_href(link: &Value) -> Option<&str> {
+    match link {
+        Value::String(url) => Some(url),
+        Value::Array(list) => list.iter().find_map(link_href),
+        Value::Object(obj) => obj
+            .get("href")
+            .or_else(|| obj.get("url"))
+            .and_then(link_href),
+        _ => None,
+    }
+}
+
+/// True if `url` is a http or https url, safe to link to.
+fn is_web_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|u| matches!(u.scheme(), "http" | "https"))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoints {
+    shared_inbox: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicKey {
+    id: String,
+    owner: String,
+    /// May be left out in the actor, when the key is a separate document.
+    public_key_pem: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ApError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Blocked(#[from] Blocked),
+    #[error(transparent)]
+    Key(#[from] KeyError),
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+    #[error("Bad remote document: {0}")]
+    Document(String),
+}
+
+/// Verify the signature of an inbox request, and get the signer.
+///
+/// The key is fetched from its `keyId`, which is either the actor
+/// document (with a fragment) or a document of its own.  Any fetched
+/// document must have the id it was fetched by, the actor must be on
+/// the same origin as the key, and the actor must list the key.
+async fn verify(
+    app: &App,
+    key: &Key,
+    lang: MyLang,
+    path: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<RemoteActor, ApError> {
+    let (signature, signed) =
+        Signature::check("POST", path, headers, Some(body))?;
+    let key_url = remote_url(app, &signature.key_id)?;
+    let doc = fetch(app, key, lang, &key_url).await?;
+    let (actor, key_doc) =
+        match serde_json::from_value::<RemoteActor>(doc.clone()) {
+            Ok(actor) => {
+                check_id(&actor.id, &key_url)?;
+                (actor, None)
+            }
+            Err(_) => {
+                // The key is a document of its own, get the owner.
+                let key_doc = serde_json::from_value::<PublicKey>(doc)
+                    .map_err(|e| ApError::Document(e.to_string()))?;
+                check_id(&key_doc.id, &key_url)?;
+                let owner = remote_url(app, &key_doc.owner)?;
+                let actor = fetch(app, key, lang, &owner).await?;
+                let actor = serde_json::from_value::<RemoteActor>(actor)
+                    .map_err(|e| ApError::Document(e.to_string()))?;
+                check_id(&actor.id, &owner)?;
+                (actor, Some(key_doc))
+            }
+        };
+    let pem = actor_key(&key_url, &signature.key_id, &actor, key_doc)?;
+    signature.verify(&signed, &pem)?;
+    Ok(actor)
+}
+
+/// Check that a document fetched from `url` has `id`.
+///
+/// The fragment of the url is not part of the request, so it is
+/// ignored.
+fn check_id(id: &str, url: &Url) -> Result<(), ApError> {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    let same = Url::parse(id).is_ok_and(|mut id| {
+        id.set_fragment(None);
+        id == url
+    });
+    if same {
+        Ok(())
+    } else {
+        Err(ApError::Document(format!("Got {id:?} from {url}")))
+    }
+}
+
+/// The public key pem of `key_id`, if it is a key of `actor`.
+///
+/// The key is taken from the separate `key_doc` if there is one,
+/// otherwise from the actor document.
+fn actor_key(
+    key_url: &Url,
+    key_id: &str,
+    actor: &RemoteActor,
+    key_doc: Option<PublicKey>,
+) -> Result<String, ApError> {
+    let actor_url = Url::parse(&actor.id)
+        .map_err(|e| ApError::Document(e.to_string()))?;
+    if actor_url.origin() != key_url.origin() {
+        return Err(ApError::Document(
+            "Key and actor differ in origin".into(),
+        ));
+    }
+    let listed = actor
+        .public_key
+        .as_ref()
+        .filter(|k| k.id == key_id && k.owner == actor.id)
+        .ok_or_else(|| ApError::Document("No matching key".into()))?;
+    key_doc
+        .filter(|k| k.id == key_id && k.owner == actor.id)
+        .and_then(|k| k.public_key_pem)
+        .or_else(|| listed.public_key_pem.clone())
+        .ok_or_else(|| ApError::Document("No public key".into()))
+}
+
+/// Get a json document, signed as the `lang` actor.
+///
+/// The `url` should be checked by [`remote_url`].
+async fn fetch(
+    app: &App,
+    key: &Key,
+    lang: MyLang,
+    url: &Url,
+) -> Result<Value, ApError> {
+    let key_id = format!("{}#main-key", actor_id(&app.base, lang));
+    let mut request = app.http.get(url)?.header(ACCEPT, ACTIVITY_JSON);
+    for (name, value) in key.sign_request(&key_id, "GET", url, None)? {
+        request = request.header(name, value);
+    }
+    Ok(request.send().await?.error_for_status()?.json().await?)
+}
+
+/// Parse a url from another server, that may be requested.
+///
+/// The url must be https, and to a global address.
+fn remote_url(app: &App, url: &str) -> Result<Url, ApError> {
+    let url =
+        Url::parse(url).map_err(|e| ApError::Document(e.to_string()))?;
+    app.http.check_https(&url)?;
+    Ok(url)
+}
+
+/// Post an activity to an inbox, signed as the `lang` actor.
+async fn post_signed(
+    app: &App,
+    key: &Key,
+    lang: MyLang,
+    inbox: &str,
+    activity: &Value,
+) -> Result<(), ApError> {
+    let url = remote_url(app, inbox)?;
+    let body = activity.to_string();
+    let key_id = format!("{}#main-key", actor_id(&app.base, lang));
+    let mut request =
+        app.http.post(&url)?.header(CONTENT_TYPE, ACTIVITY_JSON);
+    for (name, value) in
+        key.sign_request(&key_id, "POST", &url, Some(body.as_bytes()))?
+    {
+        request = request.header(name, value);
+    }
+    request.body(body).send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Deliver new posts to the followers, every few minutes.
+pub async fn deliver_posts(app: App) {
+    if app.ap.is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(300));
+    loop {
+        interval.tick().await;
+        for lang in MYLANGS {
+            if let Err(e) = deliver_new(&app, lang).await {
+                warn!(%lang, "Failed to deliver posts: {e:?}");
+            }
+        }
+    }
+}
+
+/// Deliver posts from the last two days that are not delivered yet.
+///
+/// Deliveries are recorded per inbox, and only when successful, so a
+/// failed delivery is tried again next time.  No database connection
+/// is held while posting to the inboxes.
+async fn deliver_new(app: &App, lang: MyLang) -> Result<()> {
+    let Some(key) = &app.ap else { return Ok(()) };
+    let mut pending = Vec::new();
+    {
+        let mut db = app.db().await?;
+        let now = Utc::now();
+        let posts = Teaser::posted_between(
+            now - TimeDelta::days(2),
+            now,
+            lang.as_ref(),
+            &mut db,
+        )
+        .await?;
+        let delivered = d::ap_deliveries
+            .select((d::post_id, d::inbox))
+            .filter(d::lang.eq(lang.as_ref()))
+            .filter(d::post_id.eq_any(posts.iter().map(|p| p.id)))
+            .load::<(i32, String)>(&mut db)
+            .await?
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        let inboxes = f::ap_followers
+            .select(f::inbox)
+            .filter(f::lang.eq(lang.as_ref()))
+            .distinct()
+            .load::<String>(&mut db)
+            .await?;
+        for post in &posts {
+            let inboxes = inboxes
+                .iter()
+                .filter(|i| !delivered.contains(&(post.id, i.to_string())))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !inboxes.is_empty() {
+                pending.push((
+                    post.id,
+                    create(&app.base, lang, post),
+                    inboxes,
+                ));
+            }
+        }
+    }
+    for (post_id, activity, inboxes) in pending {
+        for inbox in inboxes {
+            match post_signed(app, key, lang, &inbox, &activity).await {
+                Ok(()) => {
+                    info!(post = post_id, inbox, "Delivered post.");
+                    let mut db = app.db().await?;
+                    diesel::insert_into(d::ap_deliveries)
+                        .values((
+                            d::post_id.eq(post_id),
+                            d::lang.eq(lang.as_ref()),
+                            d::inbox.eq(&inbox),
+                        ))
+                        .on_conflict_do_nothing()
+                        .execute(&mut db)
+                        .await?;
+                }
+                Err(e) => {
+                    warn!(post = post_id, inbox, "Delivery failed: {e}")
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn make_links_absolute() {
+    assert_eq!(
+        absolute_links(
+            "<a href=\"/2024/hello.en\">x</a> <img src='/s/a.png'> \
+             <a href=\"//example.org/\">y</a>",
+            "https://example.org",
+        ),
+        "<a href=\"https://example.org/2024/hello.en\">x</a> \
+         <img src='https://example.org/s/a.png'> \
+         <a href=\"//example.org/\">y</a>",
+    );
+}
+
+#[test]
+fn remote_links() {
+    let actor = serde_json::from_value::<RemoteActor>(json!({
+        "id": "https://example.com/users/x",
+        "inbox": "https://example.com/users/x/inbox",
+        "preferredUsername": "x",
+        "name": "",
+        "url": [{"type": "Link", "href": "https://example.com/@x"}],
+        "icon": {"type": "Image", "url": "https://example.com/x.png"},
+    }))
+    .unwrap();
+    assert_eq!(actor.name(), "x");
+    assert_eq!(actor.profile_url(), "https://example.com/@x");
+    assert_eq!(actor.avatar(), Some("https://example.com/x.png"));
+    assert_eq!(actor.delivery_inbox(), "https://example.com/users/x/inbox");
+
+    let actor = serde_json::from_value::<RemoteActor>(json!({
+        "id": "https://example.com/users/x",
+        "inbox": "https://example.com/users/x/inbox",
+        "url": "javascript:alert(1)",
+        "icon": {"type": "Image", "url": "data:image/svg+xml,<svg/>"},
+    }))
+    .unwrap();
+    assert_eq!(actor.profile_url(), "https://example.com/users/x");
+    assert_eq!(actor.avatar(), None);
+}
+
+#[test]
+fn webfinger_resource() {
+    let base = Url::parse("https://example.org").unwrap();
+    let lang = |resource| resource_lang(resource, &base);
+    assert_eq!(lang("acct:sv@example.org"), Some(MyLang::Sv));
+    assert_eq!(lang("acct:en@EXAMPLE.org"), Some(MyLang::En));
+    assert_eq!(lang("acct:en@example.com"), None);
+    assert_eq!(lang("acct:fr@example.org"), None);
+    assert_eq!(lang("https://example.org/ap/en"), Some(MyLang::En));
+    assert_eq!(lang("https://example.com/ap/en"), None);
+
+    let base = Url::parse("http://localhost:8765").unwrap();
+    assert_eq!(
+        resource_lang("acct:en@localhost:8765", &base),
+        Some(MyLang::En)
+    );
+}
+
+#[test]
+fn fetched_document_id() {
+    let url = Url::parse("https://example.com/users/x#main-key").unwrap();
+    assert!(check_id("https://example.com/users/x", &url).is_ok());
+    assert!(check_id("https://example.com/users/x#other", &url).is_ok());
+    assert!(check_id("https://example.com/users/y", &url).is_err());
+    assert!(check_id("https://example.org/users/x", &url).is_err());
+    assert!(check_id("not a url", &url).is_err());
+}
+
+#[test]
+fn key_of_actor() {
+    let actor = serde_json::from_value::<RemoteActor>(json!({
+        "id": "https://example.com/users/x",
+        "inbox": "https://example.com/users/x/inbox",
+        "publicKey": {
+            "id": "https://example.com/users/x#main-key",
+            "owner": "https://example.com/users/x",
+            "publicKeyPem": "pem",
+        },
+    }))
+    .unwrap();
+    let key_id = "https://example.com/users/x#main-key";
+    let url = Url::parse(key_id).unwrap();
+    assert_eq!(actor_key(&url, key_id, &actor, None).unwrap(), "pem");
+
+    // A key on another origin can't be claimed by the actor.
+    let key_id = "https://evil.example/users/x#main-key";
+    let url = Url::parse(key_id).unwrap();
+    assert!(actor_key(&url, key_id, &actor, None).is_err());
+
+    // A separate key document must be listed by the owner.
+    let key_id = "https://example.com/users/x/other-key";
+    let url = Url::parse(key_id).unwrap();
+    let key_doc = PublicKey {
+        id: key_id.into(),
+        owner: "https://example.com/users/x".into(),
+        public_key_pem: Some("other pem".into()),
+    };
+    assert!(actor_key(&url, key_id, &actor, Some(key_doc)).is_err());
+
+    let key_id = "https://example.com/users/x/main-key";
+    let url = Url::parse(key_id).unwrap();
+    let actor = serde_json::from_value::<RemoteActor>(json!({
+        "id": "https://example.com/users/x",
+        "inbox": "https://example.com/users/x/inbox",
+        "publicKey": {"id": key_id, "owner": "https://example.com/users/x"},
+    }))
+    .unwrap();
+    let key_doc = PublicKey {
+        id: key_id.into(),
+        owner: "https://example.com/users/x".into(),
+        public_key_pem: Some("key pem".into()),
+    };
+    assert_eq!(
+        actor_key(&url, key_id, &actor, Some(key_doc)).unwrap(),
+        "key pem"
+    );
+}
+
+/// Run the ActivityPub routes of a new instance on a local port.
+///
+/// Returns the app, and a receiver of the status of each response
+/// from the inbox.
+#[cfg(test)]
+async fn local_instance() -> (
+    App,
+    tokio::sync::mpsc::UnboundedReceiver<warp::http::StatusCode>,
+) {
+    let listener =
+        tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let key = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(key.path(), signature::test_key_pem()).unwrap();
+    let args = super::Args::parse_from([
+        "r4s",
+        "--public-base",
+        &base,
+        "--csrf-secret",
+        "0123456789abcdef0123456789abcdef",
+        "--ap-key",
+        key.path().to_str().unwrap(),
+        "--allow-local-requests",
+    ]);
+    let app = super::AppData::new(&args).unwrap();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let s = warp::any().map({
+        let app = app.clone();
+        move || app.clone()
+    });
+    let routes = path("ap")
+        .and(routes(TrustedProxies::default(), s.boxed()))
+        .with(warp::log::custom(move |info| {
+            if info.path().ends_with("/inbox") {
+                let _ = tx.send(info.status());
+            }
+        }));
+    tokio::spawn(warp::serve(routes).incoming(listener).run());
+    (app, rx)
+}
+
+#[cfg(test)]
+async fn is_follower(app: &App, lang: MyLang, actor: &str) -> bool {
+    let mut db = app.db().await.unwrap();
+    diesel::select(diesel::dsl::exists(
+        f::ap_followers
+            .filter(f::lang.eq(lang.as_ref()))
+            .filter(f::actor.eq(actor)),
+    ))
+    .get_result(&mut db)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "needs a database in DATABASE_URL"]
+async fn federation_between_local_instances() {
+    use warp::http::StatusCode;
+    let (a, mut a_inbox) = local_instance().await;
+    let (b, mut b_inbox) = local_instance().await;
+    let lang = MyLang::En;
+    let b_key = b.ap.as_ref().unwrap();
+    let a_actor = actor_id(&a.base, lang);
+    let b_actor = actor_id(&b.base, lang);
+    let a_inbox_url = format!("{a_actor}/inbox");
+
+    // B follows A, A verifies the signature of B, and B verifies the
+    // signature of the Accept from A.
+    let follow = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{b_actor}#follow"),
+        "type": "Follow",
+        "actor": b_actor,
+        "object": a_actor,
+    });
+    post_signed(&b, b_key, lang, &a_inbox_url, &follow)
+        .await
+        .unwrap();
+    assert_eq!(a_inbox.recv().await, Some(StatusCode::ACCEPTED));
+    assert!(is_follower(&a, lang, &b_actor).await);
+    assert_eq!(b_inbox.recv().await, Some(StatusCode::ACCEPTED));
+
+    // B can't follow A in the name of another actor.
+    let b_sv_actor = actor_id(&b.base, MyLang::Sv);
+    let forged = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{b_sv_actor}#follow"),
+        "type": "Follow",
+        "actor": b_sv_actor,
+        "object": a_actor,
+    });
+    assert!(
+        post_signed(&b, b_key, lang, &a_inbox_url, &forged)
+            .await
+            .is_err()
+    );
+    assert_eq!(a_inbox.recv().await, Some(StatusCode::BAD_REQUEST));
+    assert!(!is_follower(&a, lang, &b_sv_actor).await);
+
+    // B unfollows A.
+    let undo = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{b_actor}#undo"),
+        "type": "Undo",
+        "actor": b_actor,
+        "object": follow,
+    });
+    post_signed(&b, b_key, lang, &a_inbox_url, &undo)
+        .await
+        .unwrap();
+    assert_eq!(a_inbox.recv().await, Some(StatusCode::ACCEPTED));
+    assert!(!is_follower(&a, lang, &b_actor).await);
+}
@@ -0,0 +1,318 @@
+//! Replies from the fediverse, stored as comments.
+//!
+//! A public note in reply to a post, or to another such reply, becomes
+//! a comment waiting for moderation.  The id of the note is kept as the
+//! origin of the comment.
+use super::{PUBLIC, RemoteActor, actor_id};
+use crate::models::{DateTime, PostLink, safe_md2html, unescape_html};
+use crate::schema::comments::dsl as c;
+use crate::schema::posts::{self, dsl as p};
+use crate::server::admin::moderation_links;
+use crate::server::language::MYLANGS;
+use crate::server::{App, Result};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use ipnetwork::IpNetwork;
+use lazy_regex::regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::IpAddr;
+use tracing::info;
+
+/// A note, as in the object of a `Create` activity.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Note {
+    id: String,
+    attributed_to: Value,
+    #[serde(default)]
+    in_reply_to: Value,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    to: Value,
+    #[serde(default)]
+    cc: Value,
+}
+
+/// Store a reply from `actor` as a comment, if it is one.
+pub(super) async fn create(
+    app: &App,
+    ip: IpAddr,
+    actor: &RemoteActor,
+    object: &Value,
+) -> Result<()> {
+    let Ok(note) = serde_json::from_value::<Note>(object.clone()) else {
+        info!(actor = actor.id, "Ignoring create of non-note.");
+        return Ok(());
+    };
+    if id_of(&note.attributed_to) != Some(&actor.id) {
+        info!(note = note.id, "Ignoring note by someone else.");
+        return Ok(());
+    }
+    if !is_public(&note.to) && !is_public(&note.cc) {
+        info!(note = note.id, "Ignoring non-public note.");
+        return Ok(());
+    }
+    let Some(in_reply_to) = id_of(&note.in_reply_to) else {
+        info!(note = note.id, "Ignoring note that is not a reply.");
+        return Ok(());
+    };
+    let mut db = app.db().await?;
+    let Some((post, parent)) = replied(app, in_reply_to, &mut db).await?
+    else {
+        info!(note = note.id, in_reply_to, "Ignoring reply to other.");
+        return Ok(());
+    };
+    let updated = posts::table
+        .select(p::updated_at)
+        .filter(p::id.eq(post.id))
+        .first::<DateTime>(&mut db)
+        .await?;
+    if updated.old_age().is_some() {
+        info!(post = post.url(), "Ignoring reply to old post.");
+        return Ok(());
+    }
+
+    let raw_md = html_to_md(&note.content);
+    let id = diesel::insert_into(c::comments)
+        .values((
+            c::post_id.eq(post.id),
+            c::content.eq(safe_md2html(&raw_md)),
+            c::name.eq(actor.name()),
+            c::email.eq(""),
+            c::url.eq(actor.profile_url()),
+            c::from_host.eq(IpNetwork::from(ip)),
+            c::raw_md.eq(&raw_md),
+            c::is_public.eq(false),
+            c::parent_id.eq(parent),
+            c::origin.eq(&note.id),
+            c::origin_actor.eq(&actor.id),
+            c::avatar.eq(actor.avatar()),
+        ))
+        .on_conflict_do_nothing()
+        .returning(c::id)
+        .get_result::<i32>(&mut db)
+        .await
+        .optional()?;
+    let Some(id) = id else {
+        info!(note = note.id, "Reply already known.");
+        return Ok(());
+    };
+    info!(id, note = note.id, "Reply waiting for moderation.");
+    if let Some(mailer) = &app.mailer {
+        let (subject, body) = notification(
+            actor,
+            &note.id,
+            &raw_md,
+            &post,
+            &app.base,
+            &moderation_links(app, id),
+        );
+        mailer.spawn_send(subject, body);
+    }
+    Ok(())
+}
+
+/// Remove a reply that is deleted by its author.
+///
+/// Only the actor that wrote the reply can delete it.
+pub(super) async fn delete(
+    app: &App,
+    actor: &RemoteActor,
+    object: &Value,
+) -> Result<()> {
+    let Some(origin) = id_of(object) else {
+        return Ok(());
+    };
+    let mut db = app.db().await?;
+    let public = diesel::delete(c::comments)
+        .filter(c::origin.eq(origin))
+        .filter(c::origin_actor.eq(&actor.id))
+        .returning(c::is_public)
+        .get_results::<bool>(&mut db)
+        .await?;
+    if public.is_empty() {
+        info!(origin, actor = actor.id, "No reply by actor to delete.");
+    } else {
+        info!(origin, "Reply deleted.");
+    }
+    if public.contains(&true) {
+        app.pages.clear();
+    }
+    Ok(())
+}
+
+/// The post, and parent comment if any, that `in_reply_to` refers to.
+///
+/// A reply is either to one of our post objects, or to a comment that
+/// came from the fediverse.
+async fn replied(
+    app: &App,
+    in_reply_to: &str,
+    db: &mut crate::dbopt::Connection,
+) -> Result<Option<(PostLink, Option<i32>)>> {
+    let post_id = MYLANGS.iter().find_map(|lang| {
+        in_reply_to
+            .strip_prefix(&actor_id(&app.base, *lang))?
+            .strip_prefix("/post/")?
+            .parse::<i32>()
+            .ok()
+    });
+    let (post_id, parent) = if let Some(post_id) = post_id {
+        (post_id, None)
+    } else if let Some((id, post_id)) = c::comments
+        .select((c::id, c::post_id))
+        .filter(c::origin.eq(in_reply_to))
+        .first::<(i32, i32)>(db)
+        .await
+        .optional()?
+    {
+        (post_id, Some(id))
+    } else {
+        return Ok(None);
+    };
+    Ok(PostLink::all()
+        .filter(p::id.eq(post_id))
+        .first(db)
+        .await
+        .optional()?
+        .map(|post| (post, parent)))
+}
+
+/// The id of an object that may be given as just its id.
+fn id_of(object: &Value) -> Option<&str> {
+    object.as_str().or_else(|| object.get("id")?.as_str())
+}
+
+/// True if the `to` or `cc` addressing includes the public.
+fn is_public(addressing: &Value) -> bool {
+    let public = |v: &Value| {
+        matches!(v.as_str(), Some(PUBLIC | "as:Public" | "Public"))
+    };
+    match addressing {
+        Value::Array(list) => list.iter().any(public),
+        v => public(v),
+    }
+}
+
+/// Convert the html content of a note to markdown.
+///
+/// Mentions and hashtags are kept as text, other links become
+/// autolinks, and all other markup is removed.
+fn html_to_md(html: &str) -> String {
+    let mut md = String::new();
+    // Inside a link that is written as an autolink.
+    let mut in_autolink = false;
+    for token in regex!(r"<[^>]*>|[^<]+").find_iter(html) {
+        let token = token.as_str();
+        let Some(tag) = token.strip_prefix('<') else {
+            if !in_autolink {
+                md.push_str(&escape_md(&unescape_html(token)));
+            }
+            continue;
+        };
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .find(|s| !s.is_empty())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match (tag.starts_with('/'), name.as_str()) {
+            (false, "br") => md.push_str("\\\n"),
+            (true, "p") => md.push_str("\n\n"),
+            (false, "a") => {
+                let href =
+                    regex!(r#"(?i)\bhref\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+                        .captures(tag)
+                        .and_then(|c| c.get(1).or(c.get(2)))
+                        .map(|h| unescape_html(h.as_str()))
+                        .filter(|h| {
+                            regex!(r"^https?://[^\s<>]+$").is_match(h)
+                        });
+                let is_tag = regex!(
+                    r#"(?i)\bclass\s*=\s*["'][^"']*\b(mention|hashtag)\b"#
+                )
+                .is_match(tag);
+                if let Some(href) = href.filter(|_| !is_tag) {
+                    md.push('<');
+                    md.push_str(&href);
+                    md.push('>');
+                    in_autolink = true;
+                }
+            }
+            (true, "a") => in_autolink = false,
+            _ => (),
+        }
+    }
+    md.trim().to_string()
+}
+
+/// Escape characters that would be markup in markdown.
+fn escape_md(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if matches!(ch, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#')
+        {
+            result.push('\\');
+        }
+        result.push(ch);
+    }
+    result
+}
+
+/// The subject and body of a mail about a reply from the fediverse.
+fn notification(
+    actor: &RemoteActor,
+    origin: &str,
+    raw_md: &str,
+    post: &PostLink,
+    base: &str,
+    moderate: &str,
+) -> (String, String) {
+    let subject = format!("New fediverse reply to {:?}", post.title);
+    let body = format!(
+        "{name} <{actor}> replied to {title:?}.\n\
+         The reply is waiting for moderation.\n\n\
+         {raw_md}\n\n\
+         Original: {origin}\n\n\
+         The post: {base}{post_url}\n\
+         {moderate}",
+        name = actor.name(),
+        actor = actor.id,
+        title = post.title,
+        post_url = post.url(),
+    );
+    (subject, body)
+}
+
+#[test]
+fn note_to_markdown() {
+    let html = "<p><span class=\"h-card\">\
+                <a href=\"https://example.org/@rkaj\" \
+                class=\"u-url mention\">@<span>rkaj</span></a></span> \
+                Nice *post*! See \
+                <a href=\"https://example.com/a?b=1&amp;c=2\" \
+                rel=\"nofollow\"><span class=\"invisible\">https://</span>\
+                <span>example.com/a?b=1&amp;c=2</span></a></p>\
+                <p>Line one<br />line two &lt;3 \
+                <a href=\"https://example.org/tags/rust\" \
+                class=\"mention hashtag\">#<span>rust</span></a></p>";
+    assert_eq!(
+        html_to_md(html),
+        "@rkaj Nice \\*post\\*! See <https://example.com/a?b=1&c=2>\n\n\
+         Line one\\\nline two \\<3 \\#rust",
+    );
+}
+
+#[test]
+fn public_addressing() {
+    assert!(is_public(&serde_json::json!([
+        "https://example.org/users/x/followers",
+        PUBLIC,
+    ])));
+    assert!(is_public(&serde_json::json!("as:Public")));
+    assert!(!is_public(&serde_json::json!([
+        "https://example.org/ap/en"
+    ])));
+    assert!(!is_public(&Value::Null));
+}
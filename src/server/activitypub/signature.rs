@@ -0,0 +1,355 @@
+//! HTTP signatures, as used by ActivityPub servers.
+//!
+//! This is the `rsa-sha256` variant of draft-cavage-http-signatures,
+//! which is what Mastodon and most other servers use.
+use aws_lc_rs::digest::{SHA256, digest};
+use aws_lc_rs::encoding::AsDer;
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{
+    KeyPair, RSA_PKCS1_2048_8192_SHA256, RSA_PKCS1_SHA256, RsaKeyPair,
+    UnparsedPublicKey,
+};
+use base64::prelude::*;
+use chrono::{TimeDelta, Utc};
+use std::collections::HashMap;
+use std::path::Path;
+use warp::http::HeaderMap;
+
+/// The private key of the site actors.
+pub struct Key {
+    pair: RsaKeyPair,
+    public_pem: String,
+}
+
+impl Key {
+    /// Load a private key from a PEM file.
+    pub fn load(path: &Path) -> Result<Key, KeyError> {
+        let pem = std::fs::read_to_string(path)
+            .map_err(|e| KeyError(format!("{path:?}: {e}")))?;
+        Key::from_pem(&pem)
+    }
+
+    /// Read a PKCS#8 (or PKCS#1) PEM encoded RSA private key.
+    pub fn from_pem(pem: &str) -> Result<Key, KeyError> {
+        let pair = if let Some(der) = pem_der(pem, "PRIVATE KEY") {
+            RsaKeyPair::from_pkcs8(&der)
+        } else if let Some(der) = pem_der(pem, "RSA PRIVATE KEY") {
+            RsaKeyPair::from_der(&der)
+        } else {
+            return Err(KeyError("No private key found".into()));
+        }
+        .map_err(|e| KeyError(e.to_string()))?;
+        let public = pair
+            .public_key()
+            .as_der()
+            .map_err(|_| KeyError("Bad public key".into()))?;
+        let public_pem = to_pem(public.as_ref(), "PUBLIC KEY");
+        Ok(Key { pair, public_pem })
+    }
+
+    pub fn public_pem(&self) -> &str {
+        &self.public_pem
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<String, KeyError> {
+        let mut signature = vec![0; self.pair.public_modulus_len()];
+        self.pair
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                data,
+                &mut signature,
+            )
+            .map_err(|_| KeyError("Signing failed".into()))?;
+        Ok(BASE64_STANDARD.encode(signature))
+    }
+
+    /// The headers to sign a request with.
+    ///
+    /// The `host`, `date`, `digest` (if there is a `body`), and
+    /// `signature` headers are returned.
+    pub fn sign_request(
+        &self,
+        key_id: &str,
+        method: &str,
+        url: &reqwest::Url,
+        body: Option<&[u8]>,
+    ) -> Result<Vec<(&'static str, String)>, KeyError> {
+        let host = super::authority(url);
+        // Only the method is lowercase, the path is as requested.
+        let method = method.to_lowercase();
+        let target = match url.query() {
+            Some(query) => format!("{method} {}?{query}", url.path()),
+            None => format!("{method} {}", url.path()),
+        };
+        let mut headers = vec![
+            ("host", host),
+            (
+                "date",
+                Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            ),
+        ];
+        if let Some(body) = body {
+            headers.push(("digest", body_digest(body)));
+        }
+        let mut names = "(request-target)".to_string();
+        let mut signed = format!("(request-target): {target}");
+        for (name, value) in &headers {
+            names.push(' ');
+            names.push_str(name);
+            signed.push_str(&format!("\n{name}: {value}"));
+        }
+        let signature = self.sign(signed.as_bytes())?;
+        headers.push((
+            "signature",
+            format!(
+                "keyId=\"{key_id}\",algorithm=\"rsa-sha256\",\
+                 headers=\"{names}\",signature=\"{signature}\""
+            ),
+        ));
+        Ok(headers)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Bad ActivityPub key: {0}")]
+pub struct KeyError(String);
+
+/// The signature of an incoming request.
+#[derive(Debug)]
+pub struct Signature {
+    pub key_id: String,
+    headers: Vec<String>,
+    signature: Vec<u8>,
+}
+
+impl Signature {
+    /// Parse and check the signature header of a request.
+    ///
+    /// The signature must cover the request target, the host, and a
+    /// recent date.  If there is a body, it must cover a correct digest
+    /// of it.
+    pub fn check(
+        method: &str,
+        path: &str,
+        headers: &HeaderMap,
+        body: Option<&[u8]>,
+    ) -> Result<(Signature, String), SignatureError> {
+        let header = headers
+            .get("signature")
+            .and_then(|h| h.to_str().ok())
+            .ok_or(SignatureError::Missing)?;
+        let params = parse_params(header);
+        let signature = Signature {
+            key_id: params
+                .get("keyId")
+                .ok_or(SignatureError::Bad("keyId"))?
+                .to_string(),
+            headers: params
+                .get("headers")
+                .unwrap_or(&"date")
+                .split_whitespace()
+                .map(str::to_lowercase)
+                .collect(),
+            signature: params
+                .get("signature")
+                .and_then(|s| BASE64_STANDARD.decode(s).ok())
+                .ok_or(SignatureError::Bad("signature"))?,
+        };
+        let mut required = vec!["(request-target)", "host", "date"];
+        if let Some(body) = body {
+            required.push("digest");
+            let digest = header_value(headers, "digest");
+            if !digest
+                .split(',')
+                .any(|d| d.trim() == body_digest(body).as_str())
+            {
+                return Err(SignatureError::Bad("digest"));
+            }
+        }
+        if let Some(missing) = required
+            .iter()
+            .find(|r| !signature.headers.iter().any(|h| h == *r))
+        {
+            return Err(SignatureError::Uncovered(missing.to_string()));
+        }
+        let date = chrono::DateTime::parse_from_rfc2822(&header_value(
+            headers, "date",
+        ))
+        .map_err(|_| SignatureError::Bad("date"))?;
+        if (Utc::now() - date.to_utc()).abs() > TimeDelta::hours(12) {
+            return Err(SignatureError::Bad("date"));
+        }
+        let signed = signature
+            .headers
+            .iter()
+            .map(|name| {
+                if name == "(request-target)" {
+                    format!("{name}: {} {path}", method.to_lowercase())
+                } else {
+                    format!("{name}: {}", header_value(headers, name))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok((signature, signed))
+    }
+
+    /// Verify this signature of `signed` with a PEM public key.
+    pub fn verify(
+        &self,
+        signed: &str,
+        public_pem: &str,
+    ) -> Result<(), SignatureError> {
+        let der = pem_der(public_pem, "PUBLIC KEY")
+            .or_else(|| pem_der(public_pem, "RSA PUBLIC KEY"))
+            .ok_or(SignatureError::Bad("public key"))?;
+        UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, der)
+            .verify(signed.as_bytes(), &self.signature)
+            .map_err(|_| SignatureError::Invalid)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("Missing signature")]
+    Missing,
+    #[error("Bad {0} in signature")]
+    Bad(&'static str),
+    #[error("Signature does not cover {0}")]
+    Uncovered(String),
+    #[error("Invalid signature")]
+    Invalid,
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parse `key="value"` parameters, separated by commas.
+fn parse_params(header: &str) -> HashMap<&str, &str> {
+    header
+        .split(',')
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((key.trim(), value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+fn body_digest(body: &[u8]) -> String {
+    let digest = digest(&SHA256, body);
+    format!("SHA-256={}", BASE64_STANDARD.encode(digest.as_ref()))
+}
+
+/// The DER content of the first `label` block in `pem`.
+fn pem_der(pem: &str, label: &str) -> Option<Vec<u8>> {
+    let begin = format!("-----BEGIN {label}-----");
+    let end = format!("-----END {label}-----");
+    let (_, rest) = pem.split_once(&begin)?;
+    let (data, _) = rest.split_once(&end)?;
+    let data = data.split_whitespace().collect::<String>();
+    BASE64_STANDARD.decode(data).ok()
+}
+
+fn to_pem(der: &[u8], label: &str) -> String {
+    let data = BASE64_STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in data.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// A new private key, in PEM format.
+#[cfg(test)]
+pub fn test_key_pem() -> String {
+    let pair =
+        RsaKeyPair::generate(aws_lc_rs::rsa::KeySize::Rsa2048).unwrap();
+    let der =
+        AsDer::<aws_lc_rs::encoding::Pkcs8V1Der>::as_der(&pair).unwrap();
+    to_pem(der.as_ref(), "PRIVATE KEY")
+}
+
+#[cfg(test)]
+fn test_key() -> Key {
+    Key::from_pem(&test_key_pem()).unwrap()
+}
+
+#[cfg(test)]
+fn to_header_map(headers: Vec<(&'static str, String)>) -> HeaderMap {
+    headers
+        .into_iter()
+        .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+        .collect()
+}
+
+#[test]
+fn sign_and_verify() {
+    let key = test_key();
+    let url = reqwest::Url::parse("https://example.org/ap/en/inbox").unwrap();
+    let body = br#"{"type":"Follow"}"#;
+    let headers = key
+        .sign_request(
+            "https://x.example/u#main-key",
+            "POST",
+            &url,
+            Some(body),
+        )
+        .unwrap();
+    let headers = to_header_map(headers);
+    let (signature, signed) =
+        Signature::check("POST", "/ap/en/inbox", &headers, Some(body))
+            .unwrap();
+    assert_eq!(signature.key_id, "https://x.example/u#main-key");
+    assert!(signature.verify(&signed, key.public_pem()).is_ok());
+
+    let (_, signed) =
+        Signature::check("POST", "/ap/sv/inbox", &headers, Some(body))
+            .unwrap();
+    assert!(signature.verify(&signed, key.public_pem()).is_err());
+    assert!(
+        Signature::check("POST", "/ap/en/inbox", &headers, Some(b"{}"))
+            .is_err()
+    );
+}
+
+#[test]
+fn require_covered_headers() {
+    let key = test_key();
+    let url = reqwest::Url::parse("https://example.org/ap/en/inbox").unwrap();
+    let headers = key
+        .sign_request("https://x.example/u#main-key", "POST", &url, None)
+        .unwrap();
+    let headers = to_header_map(headers);
+    assert!(matches!(
+        Signature::check("POST", "/ap/en/inbox", &headers, Some(b"{}")),
+        Err(SignatureError::Bad("digest")),
+    ));
+}
+
+#[test]
+fn sign_mixed_case_path() {
+    let key = test_key();
+    let url = reqwest::Url::parse("https://example.org/users/Kaj/inbox?A=B")
+        .unwrap();
+    let headers = key
+        .sign_request("https://x.example/u#main-key", "POST", &url, None)
+        .unwrap();
+    let headers = to_header_map(headers);
+    let (signature, signed) =
+        Signature::check("POST", "/users/Kaj/inbox?A=B", &headers, None)
+            .unwrap();
+    assert!(signature.verify(&signed, key.public_pem()).is_ok());
+    let (_, signed) =
+        Signature::check("POST", "/users/kaj/inbox?a=b", &headers, None)
+            .unwrap();
+    assert!(signature.verify(&signed, key.public_pem()).is_err());
+}
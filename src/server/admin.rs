@@ -0,0 +1,391 @@
+//! Admin pages, currently for moderating comments and webmentions.
+//!
+//! The admin pages are protected by http basic auth with a single
+//! configured credential.  If no credential is configured, the admin
+//! pages does not exist.
+//!
+//! Pending comments can also be moderated by signed links, as sent in
+//! notification mail, without the credential.
+use super::templates::{self, RenderRucte};
+use super::{App, AppData, Result, ViewError, csrf, goh, response};
+use crate::models::{
+    MentionModeration, ModComment, ModMention, Moderation, safe_md2html,
+};
+use crate::schema::comments::dsl as c;
+use crate::schema::webmentions::dsl as wm;
+use base64::prelude::*;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use std::str::FromStr;
+use tracing::instrument;
+use warp::filters::{BoxedFilter, body, cookie, header};
+use warp::http::header::{CACHE_CONTROL, SET_COOKIE};
+use warp::path::{end, param, path};
+use warp::reply::Response;
+use warp::{Filter, Reply, post, query};
+
+pub fn routes(s: BoxedFilter<(App,)>) -> BoxedFilter<(impl Reply,)> {
+    let auth = || header::optional::<String>("authorization");
+    let list = path("comments")
+        .and(end())
+        .and(goh())
+        .and(auth())
+        .and(s.clone())
+        .then(comments);
+    let moderate = path("comments")
+        .and(param())
+        .and(end())
+        .and(post())
+        .and(auth())
+        .and(cookie::cookie(csrf::ADMIN_COOKIE))
+        .and(body::form())
+        .and(s.clone())
+        .then(moderate);
+    let moderate_mention = path("mentions")
+        .and(param())
+        .and(end())
+        .and(post())
+        .and(auth())
+        .and(cookie::cookie(csrf::ADMIN_COOKIE))
+        .and(body::form())
+        .and(s.clone())
+        .then(moderate_mention);
+    let confirm_link = path("comments")
+        .and(param())
+        .and(param())
+        .and(end())
+        .and(goh())
+        .and(query())
+        .and(s.clone())
+        .then(confirm_link);
+    let moderate_link = path("comments")
+        .and(param())
+        .and(param())
+        .and(end())
+        .and(post())
+        .and(body::form())
+        .and(s)
+        .then(moderate_link);
+    list.or(moderate)
+        .unify()
+        .or(moderate_mention)
+        .unify()
+        .or(confirm_link)
+        .unify()
+        .or(moderate_link)
+        .unify()
+        .boxed()
+}
+
+#[instrument]
+async fn comments(auth: Option<String>, app: App) -> Result<Response> {
+    check_auth(auth.as_deref(), &app)?;
+    let mut db = app.db().await?;
+    let queue = ModComment::queue(&mut db).await?;
+    let public = ModComment::public(20, &mut db).await?;
+    let mentions = ModMention::queue(&mut db).await?;
+    let (token, cookie) = app.csrf.generate_pair()?;
+    Ok(response()
+        .header(SET_COOKIE, csrf::admin_cookie_header(&cookie))
+        .header(CACHE_CONTROL, "no-store")
+        .html(|o| {
+            templates::admin_comments_html(
+                o,
+                &queue,
+                &public,
+                &mentions,
+                &token.b64_string(),
+            )
+        })?)
+}
+
+#[instrument(skip(form))]
+async fn moderate(
+    id: i32,
+    auth: Option<String>,
+    csrf_cookie: String,
+    form: ModerateForm,
+    app: App,
+) -> Result<Response> {
+    check_auth(auth.as_deref(), &app)?;
+    app.csrf.verify(&form.csrftoken, &csrf_cookie)?;
+    let mut db = app.db().await?;
+    let comment = c::comments.filter(c::id.eq(id));
+    let changed = match form.action {
+        Action::Delete => diesel::delete(comment).execute(&mut db).await?,
+        Action::Spam => {
+            diesel::update(comment)
+                .set(Moderation::new(true))
+                .execute(&mut db)
+                .await?
+        }
+        Action::Save | Action::Approve => {
+            diesel::update(comment)
+                .set((
+                    c::content.eq(safe_md2html(&form.raw_md)),
+                    c::raw_md.eq(&form.raw_md),
+                    (form.action == Action::Approve)
+                        .then(|| Moderation::new(false)),
+                ))
+                .execute(&mut db)
+                .await?
+        }
+    };
+    if changed == 0 {
+        return Err(ViewError::NotFound);
+    }
+    tracing::info!(id, action = ?form.action, "Moderated comment.");
+    app.pages.clear();
+    Ok(super::found(&format!("/admin/comments#c{id:x}")))
+}
+
+#[instrument(skip(form))]
+async fn moderate_mention(
+    id: i32,
+    auth: Option<String>,
+    csrf_cookie: String,
+    form: ModerateForm,
+    app: App,
+) -> Result<Response> {
+    check_auth(auth.as_deref(), &app)?;
+    app.csrf.verify(&form.csrftoken, &csrf_cookie)?;
+    let mut db = app.db().await?;
+    let mention = wm::webmentions.filter(wm::id.eq(id));
+    let changed = match form.action {
+        Action::Delete => diesel::delete(mention).execute(&mut db).await?,
+        Action::Spam | Action::Approve => {
+            diesel::update(mention)
+                .set(MentionModeration::new(form.action == Action::Spam))
+                .execute(&mut db)
+                .await?
+        }
+        Action::Save => {
+            return Err(ViewError::BadRequest("Nothing to save".into()));
+        }
+    };
+    if changed == 0 {
+        return Err(ViewError::NotFound);
+    }
+    tracing::info!(id, action = ?form.action, "Moderated webmention.");
+    app.pages.clear();
+    Ok(super::found("/admin/comments#mentions"))
+}
+
+/// Show a pending comment, with a button to do the action of a link.
+///
+/// The action is not done directly by the link, since links in mail
+/// may be followed by link scanners.
+#[instrument]
+async fn confirm_link(
+    id: i32,
+    action: LinkAction,
+    signed: Signed,
+    app: App,
+) -> Result<Response> {
+    app.csrf.verify_signed(&action.message(id), &signed.sig)?;
+    let mut db = app.db().await?;
+    let comment = ModComment::pending(id, &mut db)
+        .await?
+        .ok_or(ViewError::NotFound)?;
+    Ok(response().header(CACHE_CONTROL, "no-store").html(|o| {
+        templates::admin_confirm_html(
+            o,
+            &comment,
+            action.label(),
+            &signed.sig,
+        )
+    })?)
+}
+
+#[instrument]
+async fn moderate_link(
+    id: i32,
+    action: LinkAction,
+    signed: Signed,
+    app: App,
+) -> Result<Response> {
+    app.csrf.verify_signed(&action.message(id), &signed.sig)?;
+    let mut db = app.db().await?;
+    let comment = ModComment::pending(id, &mut db)
+        .await?
+        .ok_or(ViewError::NotFound)?;
+    diesel::update(c::comments.filter(c::id.eq(id)))
+        .set(Moderation::new(action == LinkAction::Spam))
+        .execute(&mut db)
+        .await?;
+    tracing::info!(id, ?action, "Moderated comment by link.");
+    app.pages.clear();
+    Ok(match action {
+        LinkAction::Approve => super::found(&comment.url()),
+        LinkAction::Spam => super::found(&comment.p().url()),
+    })
+}
+
+/// Links for moderating comment `id`, to include in a mail.
+pub fn moderation_links(app: &AppData, id: i32) -> String {
+    let mut links = format!(
+        "Approve: {}\nSpam: {}\n",
+        signed_link(app, id, LinkAction::Approve),
+        signed_link(app, id, LinkAction::Spam),
+    );
+    if app.admin.is_some() {
+        links.push_str(&format!(
+            "Moderate: {}/admin/comments#c{id:x}\n",
+            app.base,
+        ));
+    }
+    links
+}
+
+/// The url of a signed link to do `action` on comment `id`.
+fn signed_link(app: &AppData, id: i32, action: LinkAction) -> String {
+    format!(
+        "{}/admin/comments/{id}/{}?sig={}",
+        app.base,
+        action.name(),
+        app.csrf.sign(&action.message(id)),
+    )
+}
+
+fn check_auth(auth: Option<&str>, app: &App) -> Result<()> {
+    app.admin.as_ref().ok_or(ViewError::NotFound)?.check(auth)
+}
+
+#[derive(Debug, Deserialize)]
+struct ModerateForm {
+    csrftoken: String,
+    action: Action,
+    #[serde(default)]
+    raw_md: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    /// Save edited markdown and make the comment public.
+    Approve,
+    /// Mark the comment as spam.
+    Spam,
+    /// Delete the comment.
+    Delete,
+    /// Save edited markdown without changing the status.
+    Save,
+}
+
+/// A moderation action that can be done by a signed link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LinkAction {
+    Approve,
+    Spam,
+}
+
+impl LinkAction {
+    fn name(self) -> &'static str {
+        match self {
+            LinkAction::Approve => "approve",
+            LinkAction::Spam => "spam",
+        }
+    }
+    fn label(self) -> &'static str {
+        match self {
+            LinkAction::Approve => "Approve",
+            LinkAction::Spam => "Mark as spam",
+        }
+    }
+    /// The signed message for this action on comment `id`.
+    fn message(self, id: i32) -> String {
+        format!("{} comment {id}", self.name())
+    }
+}
+
+impl FromStr for LinkAction {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "approve" => Ok(LinkAction::Approve),
+            "spam" => Ok(LinkAction::Spam),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Signed {
+    sig: String,
+}
+
+/// A user name and password for the admin pages.
+#[derive(Clone)]
+pub struct Credential {
+    user_pass: String,
+}
+
+impl Credential {
+    /// Check the value of an `authorization` header.
+    fn check(&self, header: Option<&str>) -> Result<()> {
+        let given = header
+            .and_then(|h| h.strip_prefix("Basic "))
+            .and_then(|b| BASE64_STANDARD.decode(b.trim()).ok())
+            .unwrap_or_default();
+        if constant_time_eq(&given, self.user_pass.as_bytes()) {
+            Ok(())
+        } else {
+            if header.is_some() {
+                tracing::warn!("Bad admin credentials.");
+            }
+            Err(ViewError::Unauthorized)
+        }
+    }
+}
+
+impl FromStr for Credential {
+    type Err = BadCredential;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((user, pass)) if !user.is_empty() && !pass.is_empty() => {
+                Ok(Credential {
+                    user_pass: s.into(),
+                })
+            }
+            _ => Err(BadCredential),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Bad admin credential, expected user:password")]
+pub struct BadCredential;
+
+/// Compare without short-circuiting, to not leak a prefix by timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+fn basic(user_pass: &str) -> String {
+    format!("Basic {}", BASE64_STANDARD.encode(user_pass))
+}
+
+#[test]
+fn credential_ok() {
+    let cred = "admin:s3cret".parse::<Credential>().unwrap();
+    assert!(cred.check(Some(&basic("admin:s3cret"))).is_ok());
+}
+
+#[test]
+fn credential_bad() {
+    let cred = "admin:s3cret".parse::<Credential>().unwrap();
+    assert!(cred.check(None).is_err());
+    assert!(cred.check(Some(&basic("admin:s3cre"))).is_err());
+    assert!(cred.check(Some(&basic("admin:s3cret2"))).is_err());
+    assert!(cred.check(Some("Bearer admin:s3cret")).is_err());
+}
+
+#[test]
+fn credential_parse() {
+    assert!("admin".parse::<Credential>().is_err());
+    assert!(":pass".parse::<Credential>().is_err());
+    assert!("admin:".parse::<Credential>().is_err());
+}
@@ -0,0 +1,198 @@
+//! Archive pages for a month or a single day, such as `/2023/05/en`
+//! or `/2023/05/17/en`, and the archive calendar for a year.
+use super::pager::Pager;
+use super::templates::{self, RenderRucte};
+use super::{App, Asides, Result, ViewError, ViewResult, goh, response};
+use crate::dbopt::Connection;
+use crate::models::{MyLang, Teaser, has_lang, year_of_date};
+use crate::schema::posts::dsl as p;
+use chrono::{Datelike, Months, NaiveDate, NaiveTime, Utc};
+use diesel::dsl::not;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use i18n_embed_fl::fl;
+use tracing::instrument;
+use warp::filters::BoxedFilter;
+use warp::http::Uri;
+use warp::path::{end, param};
+use warp::reply::Response;
+use warp::{Filter, Reply, redirect};
+
+pub fn routes(
+    lang_filt: BoxedFilter<(MyLang,)>,
+    s: BoxedFilter<(App,)>,
+) -> BoxedFilter<(impl Reply,)> {
+    let month = param()
+        .and(param())
+        .and(param())
+        .and(end())
+        .and(goh())
+        .and(s.clone())
+        .then(|year, month, lang, app| archive(year, month, None, lang, app));
+    let day = param()
+        .and(param())
+        .and(param())
+        .and(param())
+        .and(end())
+        .and(goh())
+        .and(s)
+        .then(|year, month, day, lang, app| {
+            archive(year, month, Some(day), lang, app)
+        });
+    let no_lang = param()
+        .and(param())
+        .and(end())
+        .and(goh())
+        .and(lang_filt)
+        .map(|year: i16, month: u8, lang| {
+            format!("/{year}/{month:02}/{lang}")
+                .parse::<Uri>()
+                .or_ise()
+                .map(redirect::see_other)
+        });
+    month.or(day).unify().or(no_lang).boxed()
+}
+
+#[instrument]
+async fn archive(
+    year: i16,
+    month: u8,
+    day: Option<u8>,
+    lang: MyLang,
+    app: App,
+) -> Result<Response> {
+    let start = NaiveDate::from_ymd_opt(
+        year.into(),
+        month.into(),
+        day.unwrap_or(1).into(),
+    )
+    .ok_or(ViewError::NotFound)?;
+    let end = match day {
+        Some(_) => start.succ_opt(),
+        None => start.checked_add_months(Months::new(1)),
+    }
+    .ok_or(ViewError::NotFound)?;
+
+    let mut db = app.db().await?;
+    let posts = Teaser::posted_between(
+        start.and_time(NaiveTime::MIN).and_utc(),
+        end.and_time(NaiveTime::MIN).and_utc(),
+        lang.as_ref(),
+        &mut db,
+    )
+    .await?;
+    if posts.is_empty() {
+        return Err(ViewError::NotFound);
+    }
+    let calendar = Calendar::load(year, Some(month), lang, &mut db).await?;
+
+    let p_year = year_of_date(p::posted_at);
+    let years = p::posts
+        .select(p_year)
+        .distinct()
+        .order(p_year)
+        .load(&mut db)
+        .await?;
+
+    let fluent = lang.fluent();
+    let month_name = fl!(fluent, "month-name", m = month);
+    let h1 = match day {
+        Some(day) => fl!(
+            fluent,
+            "posts-day",
+            year = year,
+            month = month_name,
+            day = day
+        ),
+        None => fl!(fluent, "posts-month", year = year, month = month_name),
+    };
+    let path = match day {
+        Some(day) => format!("/{year}/{month:02}/{day:02}"),
+        None => format!("/{year}/{month:02}"),
+    };
+    let other_langs = lang.other(|_, lang, name| {
+        format!(
+            "<a href='{path}/{lang}' hreflang='{lang}' lang='{lang}' rel='alternate'>{name}</a>",
+        )});
+
+    Ok(response().html(|o| {
+        templates::posts_html(
+            o,
+            fluent,
+            &h1,
+            &posts,
+            &Pager::default(),
+            &Asides {
+                calendar: Some(&calendar),
+                years: &years,
+                ..Asides::default()
+            },
+            &other_langs,
+        )
+    })?)
+}
+
+/// Number of posts per month of a year.
+#[derive(Debug)]
+pub struct Calendar {
+    year: i16,
+    lang: MyLang,
+    counts: [u32; 12],
+    current: Option<u8>,
+}
+
+/// A month in a [`Calendar`].
+pub struct Month {
+    pub num: u8,
+    pub posts: u32,
+    pub url: Option<String>,
+    pub current: bool,
+}
+
+impl Calendar {
+    /// Count the posts per month of `year`, in `lang`.
+    ///
+    /// The `current` month, if any, is marked in the calendar.
+    pub async fn load(
+        year: i16,
+        current: Option<u8>,
+        lang: MyLang,
+        db: &mut Connection,
+    ) -> Result<Self> {
+        let l = lang.as_ref();
+        let dates = p::posts
+            .select(p::posted_at)
+            .filter(year_of_date(p::posted_at).eq(year))
+            .filter(p::lang.eq(l).or(not(has_lang(
+                year_of_date(p::posted_at),
+                p::slug,
+                l,
+            ))))
+            .load::<chrono::DateTime<Utc>>(db)
+            .await?;
+        let mut counts = [0; 12];
+        for date in dates {
+            counts[date.month0() as usize] += 1;
+        }
+        Ok(Calendar {
+            year,
+            lang,
+            counts,
+            current,
+        })
+    }
+
+    pub fn year(&self) -> i16 {
+        self.year
+    }
+
+    pub fn months(&self) -> impl Iterator<Item = Month> + '_ {
+        (1..).zip(self.counts).map(|(num, posts)| Month {
+            num,
+            posts,
+            url: (posts > 0)
+                .then(|| format!("/{}/{num:02}/{}", self.year, self.lang)),
+            current: self.current == Some(num),
+        })
+    }
+}
@@ -1,3 +1,5 @@
+use super::compress::{Encoding, accept_encoding};
+use super::conditional::{Conditions, Validator, conditions};
 use super::{App, Result, ViewError, ViewResult, goh, response};
 use crate::schema::assets::dsl as a;
 use bytes::Bytes;
@@ -21,30 +23,51 @@ pub fn routes(s: BoxedFilter<(App,)>) -> BoxedFilter<(impl Reply,)> {
         .and(param())
         .and(end())
         .and(goh())
+        .and(conditions())
         .and(s)
         .then(asset_file)
-        .or(tail().and(goh()).map(static_file))
+        .or(tail()
+            .and(goh())
+            .and(conditions())
+            .and(accept_encoding())
+            .map(static_file))
         .unify()
         .boxed()
 }
 
 #[instrument]
-async fn asset_file(year: i16, name: String, app: App) -> Result<Response> {
-    use chrono::{Duration, Utc};
+async fn asset_file(
+    year: i16,
+    name: String,
+    conditions: Conditions,
+    app: App,
+) -> Result<Response> {
+    use chrono::{DateTime, Duration, Utc};
     use warp::http::header::{CONTENT_TYPE, EXPIRES};
     let mut db = app.db().await?;
     let far_expires = Utc::now() + Duration::days(180);
 
-    let (mime, content) = a::assets
-        .select((a::mime, a::content))
+    // Check the validators before loading the actual content.
+    let (id, mime, updated) = a::assets
+        .select((a::id, a::mime, a::updated_at))
         .filter(a::year.eq(year))
         .filter(a::name.eq(name))
-        .first::<(String, Vec<u8>)>(&mut db)
+        .first::<(i32, String, DateTime<Utc>)>(&mut db)
         .await
         .optional()?
         .ok_or(ViewError::NotFound)?;
+    let validator = Validator::new(&app, updated, id);
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
+    let content = a::assets
+        .select(a::content)
+        .filter(a::id.eq(id))
+        .first::<Vec<u8>>(&mut db)
+        .await?;
 
-    response()
+    validator
+        .headers(response())
         .header(CONTENT_TYPE, mime)
         .header(EXPIRES, far_expires.to_rfc2822())
         .body(content.into())
@@ -54,17 +77,40 @@ async fn asset_file(year: i16, name: String, app: App) -> Result<Response> {
 /// Handler for static files.
 /// Create a response from the file data with a correct content type
 /// and a far expires header (or a 404 if the file does not exist).
+///
+/// Compressible files are compressed at build time, so the encoding is
+/// handled here rather than by the general response compression.
 #[instrument]
-fn static_file(name: Tail) -> Result<Response> {
+pub(super) fn static_file(
+    name: Tail,
+    conditions: Conditions,
+    encoding: Encoding,
+) -> Result<Response> {
     use super::templates::statics::StaticFile;
     use chrono::{Duration, Utc};
     use warp::http::header::{CONTENT_TYPE, EXPIRES};
     let data = StaticFile::get(name.as_str()).ok_or(ViewError::NotFound)?;
+    let compressed = encoding.precompressed(data.name);
+    let (encoding, content) = match compressed {
+        Some(compressed) => (encoding, compressed),
+        None => (Encoding::Identity, data.content),
+    };
+    // The name contains a hash of the content (or a version, for the
+    // files of third-party packages), so there is no need to hash it.
+    let validator = Validator::fixed((data.name, encoding));
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
     let far_expires = Utc::now() + Duration::days(180);
-    response()
+    let mut response = validator
+        .headers(response())
         .header(CONTENT_TYPE, data.mime.as_ref())
         .header(EXPIRES, far_expires.to_rfc2822())
         // TODO: Remove `bytes` dep when seanmonstar/warp#1144 is released.
-        .body(Bytes::from(data.content).into())
-        .or_ise()
+        .body(Bytes::from(content).into())
+        .or_ise()?;
+    if Encoding::Brotli.precompressed(data.name).is_some() {
+        encoding.headers(response.headers_mut());
+    }
+    Ok(response)
 }
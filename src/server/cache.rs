@@ -0,0 +1,181 @@
+//! An in-process cache of rendered pages.
+//!
+//! The cache is cleared when published content changes.  Changes
+//! made by other processes (such as `read-files` or
+//! `moderate-comments`) are signalled through postgres `NOTIFY` on
+//! the [`CHANGED`] channel.
+use super::conditional::Validator;
+use crate::dbopt::{CHANGED, DbOpt};
+use crate::models::DateTime;
+use diesel_async::RunQueryDsl;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Placeholder for the per-response csrf token in cached pages.
+pub const CSRF_MARK: &str = "\u{1}csrftoken\u{1}";
+
+#[derive(Debug, Default)]
+pub struct PageCache {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    generation: u64,
+    pages: HashMap<String, Arc<CachedPage>>,
+}
+
+/// A rendered page.
+#[derive(Debug)]
+pub struct CachedPage {
+    /// The page content, split where csrf tokens go, if any.
+    parts: Vec<String>,
+    /// When the post was updated, and its age when rendered.
+    ///
+    /// The content of a post page depends on its age, which changes
+    /// with time rather than on updates.
+    updated: DateTime,
+    age: Option<i64>,
+    pub validator: Option<Validator>,
+}
+
+impl CachedPage {
+    pub fn new(
+        html: String,
+        updated: DateTime,
+        validator: Option<Validator>,
+    ) -> Self {
+        let parts = html.split(CSRF_MARK).map(String::from).collect();
+        CachedPage {
+            parts,
+            updated,
+            age: updated.old_age(),
+            validator,
+        }
+    }
+
+    /// False if the post has aged since this page was rendered.
+    pub fn is_current(&self) -> bool {
+        self.updated.old_age() == self.age
+    }
+
+    /// True if this page contains a form that needs a csrf token.
+    pub fn needs_csrf(&self) -> bool {
+        self.parts.len() > 1
+    }
+
+    /// Get the page content, with the csrf `token` inserted.
+    pub fn html(&self, token: &str) -> String {
+        self.parts.join(token)
+    }
+}
+
+impl PageCache {
+    /// Get a cached page, if any.
+    ///
+    /// Also returns the current generation, to use when storing a
+    /// page rendered after a cache miss.
+    pub fn get(&self, url: &str) -> (Option<Arc<CachedPage>>, u64) {
+        let inner = self.lock();
+        (inner.pages.get(url).cloned(), inner.generation)
+    }
+
+    /// Store a page rendered from data loaded in `generation`.
+    ///
+    /// If the cache was cleared since then, the data may be stale and
+    /// the page is not stored.
+    pub fn put(&self, url: String, generation: u64, page: Arc<CachedPage>) {
+        let mut inner = self.lock();
+        if inner.generation == generation {
+            inner.pages.insert(url, page);
+        }
+    }
+
+    /// Forget all cached pages.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.pages.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Listen for change notifications from the database, clearing
+    /// the cache for each.
+    ///
+    /// This runs forever, reconnecting if the connection is lost.
+    /// The cache is cleared on reconnect, as notifications may have
+    /// been missed.
+    pub async fn listen(self: Arc<Self>, db: DbOpt) {
+        loop {
+            let err = self.listen_once(&db).await;
+            warn!("Change listener failed: {err}");
+            self.clear();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    }
+
+    async fn listen_once(&self, db: &DbOpt) -> anyhow::Error {
+        let mut db = match db.get_async_db().await {
+            Ok(db) => db,
+            Err(e) => return e.into(),
+        };
+        let listen = format!("LISTEN {CHANGED}");
+        if let Err(e) = diesel::sql_query(listen).execute(&mut db).await {
+            return e.into();
+        }
+        info!("Listening for changes on {CHANGED:?}.");
+        self.clear();
+        let mut notifications = std::pin::pin!(db.notifications_stream());
+        while let Some(notification) = notifications.next().await {
+            match notification {
+                Ok(n) if n.channel == CHANGED => {
+                    debug!(payload = n.payload, "Content changed.");
+                    self.clear();
+                }
+                Ok(n) => debug!(channel = n.channel, "Ignoring notification"),
+                Err(e) => return e.into(),
+            }
+        }
+        anyhow::anyhow!("Notifications ended")
+    }
+}
+
+#[test]
+fn page_with_csrf() {
+    let page = CachedPage::new(
+        format!("<input value=\"{CSRF_MARK}\">"),
+        "2024-03-01T11:00:00Z".parse().unwrap(),
+        None,
+    );
+    assert!(page.needs_csrf());
+    assert_eq!(page.html("t0k3n"), "<input value=\"t0k3n\">");
+}
+#[test]
+fn page_with_many_csrf() {
+    let page = CachedPage::new(
+        format!("<input value=\"{CSRF_MARK}\"><input value=\"{CSRF_MARK}\">"),
+        "2024-03-01T11:00:00Z".parse().unwrap(),
+        None,
+    );
+    assert!(page.needs_csrf());
+    assert_eq!(
+        page.html("t0k3n"),
+        "<input value=\"t0k3n\"><input value=\"t0k3n\">",
+    );
+}
+#[test]
+fn page_without_csrf() {
+    let page = CachedPage::new(
+        "<p>Hello</p>".into(),
+        "2010-03-01T11:00:00Z".parse().unwrap(),
+        None,
+    );
+    assert!(!page.needs_csrf());
+    assert_eq!(page.html("t0k3n"), "<p>Hello</p>");
+}
@@ -0,0 +1,225 @@
+//! Find the address of the client, also behind reverse proxies.
+use clap::ValueEnum;
+use ipnetwork::IpNetwork;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use warp::Filter;
+use warp::filters::{BoxedFilter, addr, header};
+use warp::http::HeaderMap;
+use warp::http::header::FORWARDED;
+
+/// Networks of proxies trusted to tell the real client address.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    nets: Arc<[IpNetwork]>,
+    header: ProxyHeader,
+}
+
+/// The header a trusted proxy uses to tell the client address.
+///
+/// Only the header actually set by the proxy can be trusted, as any
+/// other header is passed on as the client sent it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProxyHeader {
+    /// The de facto standard `X-Forwarded-For` header.
+    #[default]
+    XForwardedFor,
+    /// The RFC 7239 `Forwarded` header.
+    Forwarded,
+}
+
+impl TrustedProxies {
+    pub fn new(
+        mut nets: Vec<IpNetwork>,
+        local: bool,
+        header: ProxyHeader,
+    ) -> Self {
+        if local {
+            nets.push(IpNetwork::from(IpAddr::from(Ipv4Addr::LOCALHOST)));
+            nets.push(IpNetwork::from(IpAddr::from(Ipv6Addr::LOCALHOST)));
+        }
+        TrustedProxies {
+            nets: nets.into(),
+            header,
+        }
+    }
+
+    /// A filter extracting the client address of a request.
+    pub fn filter(self) -> BoxedFilter<(IpAddr,)> {
+        addr::remote()
+            .and(header::headers_cloned())
+            .map(move |peer: Option<SocketAddr>, headers: HeaderMap| {
+                let peer = peer.map(|p| p.ip()).unwrap_or_else(|| {
+                    tracing::warn!("No remote addr, assuming localhost.");
+                    Ipv4Addr::LOCALHOST.into()
+                });
+                self.client(peer, &headers)
+            })
+            .boxed()
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|net| net.contains(ip))
+    }
+
+    /// The client address of a request from `peer`.
+    ///
+    /// The forwarding header is only believed as far as it is added
+    /// by trusted proxies.  Only the configured header is used, the
+    /// other one may come from the client.
+    fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        let hops = match self.header {
+            ProxyHeader::Forwarded => headers
+                .get_all(FORWARDED)
+                .iter()
+                .flat_map(|h| h.to_str().ok().map(forwarded_for))
+                .flatten()
+                .collect::<Vec<_>>(),
+            ProxyHeader::XForwardedFor => headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .flat_map(|h| {
+                    h.to_str().ok().map(|h| h.split(',').map(parse_node))
+                })
+                .flatten()
+                .collect(),
+        };
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            match hop {
+                Some(ip) => client = ip,
+                None => {
+                    tracing::info!(%client, "Bad or hidden forwarded hop.");
+                    break;
+                }
+            }
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The `for` nodes of a RFC 7239 `Forwarded` header value, in order.
+///
+/// There is one node for each element, so an element without a `for`
+/// pair gives None rather than being skipped.
+fn forwarded_for(value: &str) -> Vec<Option<IpAddr>> {
+    value
+        .split(',')
+        .map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value))
+            })?
+        })
+        .collect()
+}
+
+/// Parse a node, as in a `Forwarded` or `X-Forwarded-For` header.
+///
+/// The address may be quoted and may have a port.  Obfuscated and
+/// unknown nodes give None.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(v6) = node.strip_prefix('[') {
+        return v6.split_once(']')?.0.parse().ok();
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|s| s.ip()))
+}
+
+#[cfg(test)]
+fn proxies(header: ProxyHeader) -> TrustedProxies {
+    TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()], true, header)
+}
+
+#[cfg(test)]
+fn headers(list: &[(&'static str, &str)]) -> HeaderMap {
+    list.iter()
+        .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+        .collect()
+}
+
+#[test]
+fn untrusted_peer() {
+    let peer = IpAddr::from([192, 0, 2, 7]);
+    let h = headers(&[("x-forwarded-for", "198.51.100.1")]);
+    assert_eq!(proxies(ProxyHeader::XForwardedFor).client(peer, &h), peer);
+}
+
+#[test]
+fn x_forwarded_for_hops() {
+    let h = headers(&[
+        ("x-forwarded-for", "1.2.3.4, 198.51.100.1"),
+        ("x-forwarded-for", "10.1.1.1"),
+    ]);
+    let client = proxies(ProxyHeader::XForwardedFor)
+        .client(IpAddr::from([127, 0, 0, 1]), &h);
+    assert_eq!(client, IpAddr::from([198, 51, 100, 1]));
+}
+
+#[test]
+fn forwarded_header() {
+    let h = headers(&[
+        ("x-forwarded-for", "1.2.3.4"),
+        (
+            "forwarded",
+            "for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\"",
+        ),
+        ("forwarded", "for=10.0.0.2:80;by=10.0.0.1"),
+    ]);
+    let client = proxies(ProxyHeader::Forwarded)
+        .client(IpAddr::from([10, 0, 0, 1]), &h);
+    assert_eq!(client, "2001:db8:cafe::17".parse::<IpAddr>().unwrap());
+}
+
+#[test]
+fn hidden_hop() {
+    let h = headers(&[("forwarded", "for=192.0.2.60, for=_hidden")]);
+    let client = proxies(ProxyHeader::Forwarded)
+        .client(IpAddr::from([10, 0, 0, 1]), &h);
+    assert_eq!(client, IpAddr::from([10, 0, 0, 1]));
+}
+
+#[test]
+fn spoofed_forwarded_ignored() {
+    // The proxy only appends x-forwarded-for, so a forwarded header
+    // comes from the client.
+    let h = headers(&[
+        ("forwarded", "for=1.2.3.4"),
+        ("x-forwarded-for", "198.51.100.1"),
+    ]);
+    let client = proxies(ProxyHeader::XForwardedFor)
+        .client(IpAddr::from([127, 0, 0, 1]), &h);
+    assert_eq!(client, IpAddr::from([198, 51, 100, 1]));
+}
+
+#[test]
+fn spoofed_x_forwarded_for_ignored() {
+    let h = headers(&[("x-forwarded-for", "1.2.3.4")]);
+    let peer = IpAddr::from([10, 0, 0, 1]);
+    assert_eq!(proxies(ProxyHeader::Forwarded).client(peer, &h), peer);
+}
+
+#[test]
+fn forwarded_element_without_for() {
+    assert_eq!(
+        forwarded_for(
+            "for=192.0.2.60, proto=https;by=10.0.0.1, for=10.0.0.2"
+        ),
+        [
+            Some(IpAddr::from([192, 0, 2, 60])),
+            None,
+            Some(IpAddr::from([10, 0, 0, 2])),
+        ],
+    );
+}
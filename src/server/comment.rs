@@ -1,28 +1,36 @@
-use super::error::ViewError;
-use super::{App, Result};
-use crate::models::{DateTime, PostLink, safe_md2html};
+use super::admin::moderation_links;
+use super::error::{ViewError, ViewResult};
+use super::language::accept_lang;
+use super::prelude::*;
+use super::templates::{self, ToHtml};
+use super::{App, Result, TrustedProxies};
+use crate::models::{DateTime, MyLang, PostLink, Thread, safe_md2html};
 use crate::schema::comments::dsl as c;
 use crate::schema::posts::{self, dsl as p};
+use crate::spam::{Bayes, Incoming, Verdict};
+use chrono::{TimeDelta, Utc};
 use diesel::dsl::count_star;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use ipnetwork::IpNetwork;
 use reqwest::Url;
 use serde::Deserialize;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
+use std::time::Duration;
 use tracing::instrument;
-use warp::filters::{BoxedFilter, cookie, header};
+use warp::filters::{BoxedFilter, cookie};
 use warp::path::end;
 use warp::reply::Response;
 use warp::{self, Filter, Reply, body, post};
 
 pub fn route(
-    proxied: bool,
+    proxies: TrustedProxies,
     s: BoxedFilter<(App,)>,
 ) -> BoxedFilter<(impl Reply,)> {
     end()
         .and(post())
-        .and(remote_addr_filter(proxied))
+        .and(proxies.filter())
+        .and(accept_lang())
         .and(cookie::cookie("CSRF"))
         .and(body::form())
         .and(s)
@@ -33,11 +41,13 @@ pub fn route(
 #[instrument]
 async fn postcomment(
     ip: IpAddr,
+    lang: MyLang,
     csrf_cookie: String,
     form: CommentForm,
     app: App,
 ) -> Result<Response> {
-    app.csrf.verify(&form.csrftoken, &csrf_cookie)?;
+    app.comment_limit.check(ip, lang)?;
+    let form_age = app.csrf.verify(&form.csrftoken, &csrf_cookie)?;
     let mut db = app.db().await?;
 
     let (post, updated) = posts::table
@@ -53,6 +63,20 @@ async fn postcomment(
         ));
     }
 
+    if let Some(parent) = form.parent {
+        let parent_post = c::comments
+            .select(c::post_id)
+            .filter(c::id.eq(parent))
+            .filter(c::is_public)
+            .first::<i32>(&mut db)
+            .await
+            .optional()?;
+        if parent_post != Some(form.post) {
+            tracing::info!(parent, "Reject reply to bad comment.");
+            return Err(ViewError::BadRequest("Bad reply".into()));
+        }
+    }
+
     let url = form
         .url
         .as_ref()
@@ -84,7 +108,29 @@ async fn postcomment(
         tracing::info!("There are {} simliar spam posts.  Reject.", spam);
         return Err(ViewError::BadRequest("This seems like spam".into()));
     }
-    let public = public > 0;
+
+    let recent_from_ip = c::comments
+        .select(count_star())
+        .filter(c::from_host.eq(IpNetwork::from(ip)))
+        .filter(c::posted_at.gt(Utc::now() - TimeDelta::hours(1)))
+        .first::<i64>(&mut db)
+        .await?;
+    let spam_probability = app
+        .bayes
+        .read()
+        .map_err(|_| ViewError::Err("Poisoned bayes".into()))?
+        .probability(&form.comment);
+    let score = app.spam.score(&Incoming {
+        raw_md: &form.comment,
+        honeypot: &form.subject,
+        form_age,
+        recent_from_ip,
+        spam_probability,
+    });
+    let verdict = score.verdict();
+    tracing::info!(%score, ?verdict, "Spam score.");
+    let spam = verdict == Verdict::Spam;
+    let public = verdict == Verdict::Ok && public > 0;
 
     let (id, public) = diesel::insert_into(c::comments)
         .values((
@@ -96,15 +142,75 @@ async fn postcomment(
             c::from_host.eq(IpNetwork::from(ip)),
             c::raw_md.eq(&form.comment),
             c::is_public.eq(public),
+            c::is_spam.eq(spam),
+            c::parent_id.eq(form.parent),
         ))
         .returning((c::id, c::is_public))
         .get_result::<(i32, bool)>(&mut db)
         .await?;
 
     tracing::info!("Comment accepted.  Public? {}", public);
+    if public {
+        app.pages.clear();
+    } else if spam {
+        // Don't bother anyone, or tell the spammer.
+    } else if let Some(mailer) = &app.mailer {
+        let (subject, body) = notification(
+            &form,
+            url.as_ref(),
+            ip,
+            &post,
+            &app.base,
+            &moderation_links(&app, id),
+        );
+        mailer.spawn_send(subject, body);
+    }
     Ok(my_found(&post, public, id))
 }
 
+/// Load the bayes spam classifier stored by `train-spam`.
+///
+/// The classifier is reloaded every hour, to get any retrained model.
+pub async fn load_bayes(app: App) {
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        let bayes = match app.db().await {
+            Ok(mut db) => Bayes::load_async(&mut db).await.or_ise(),
+            Err(e) => Err(e.into()),
+        };
+        match bayes {
+            Ok(bayes) => {
+                if let Ok(mut model) = app.bayes.write() {
+                    *model = bayes;
+                }
+            }
+            Err(e) => tracing::error!("Failed to load spam filter: {e:?}"),
+        }
+    }
+}
+
+/// Replies to a comment, rendered as nested comments.
+///
+/// The comment template can not call itself directly, as ructe
+/// templates are generic over the output type.
+pub struct Replies<'a>(
+    pub &'a FluentLanguageLoader,
+    pub &'a [Thread],
+    pub bool,
+    pub &'a str,
+);
+
+impl ToHtml for Replies<'_> {
+    fn to_html(&self, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let Replies(fluent, replies, open, csrf) = *self;
+        for reply in replies {
+            templates::comment_html(&mut *out, fluent, reply, open, csrf)?;
+        }
+        Ok(())
+    }
+}
+
 pub fn my_found(post: &PostLink, public: bool, comment: i32) -> Response {
     let url = post.url();
     super::found(&if public {
@@ -114,6 +220,72 @@ pub fn my_found(post: &PostLink, public: bool, comment: i32) -> Response {
     })
 }
 
+/// The subject and body of a mail about a comment awaiting moderation.
+fn notification(
+    form: &CommentForm,
+    url: Option<&Url>,
+    ip: IpAddr,
+    post: &PostLink,
+    base: &str,
+    moderate: &str,
+) -> (String, String) {
+    let subject = format!("New comment on {:?}", post.title);
+    let url = url.map(Url::as_str).unwrap_or("-");
+    let body = format!(
+        "{name} <{email}> commented on {title:?}.\n\
+         The comment is waiting for moderation.\n\n\
+         {comment}\n\n\
+         Url: {url}\n\
+         From: {ip}\n\n\
+         The post: {base}{post_url}\n\
+         {moderate}",
+        name = form.name,
+        email = form.email,
+        title = post.title,
+        comment = form.comment.trim_end(),
+        post_url = post.url(),
+    );
+    (subject, body)
+}
+
+#[test]
+fn test_notification() {
+    use crate::models::Slug;
+    let form = CommentForm {
+        post: 17,
+        comment: "Nice post!\n".into(),
+        name: "Jane".into(),
+        email: "jane@example.com".into(),
+        url: None,
+        parent: None,
+        subject: String::new(),
+        csrftoken: "x".into(),
+    };
+    let post = PostLink {
+        id: 17,
+        year: 2024,
+        slug: "hello".parse::<Slug>().unwrap(),
+        lang: MyLang::default(),
+        title: "Hello".into(),
+    };
+    let base = "https://example.org";
+    let ip = IpAddr::from([10, 0, 0, 1]);
+    let links =
+        "Approve: https://example.org/admin/comments/42/approve?sig=x\n";
+    let (subject, body) = notification(&form, None, ip, &post, base, links);
+    assert_eq!(subject, "New comment on \"Hello\"");
+    assert_eq!(
+        body,
+        "Jane <jane@example.com> commented on \"Hello\".\n\
+         The comment is waiting for moderation.\n\n\
+         Nice post!\n\n\
+         Url: -\n\
+         From: 10.0.0.1\n\n\
+         The post: https://example.org/2024/hello.en\n\
+         Approve: https://example.org/admin/comments/42/approve?sig=x\n",
+    );
+}
+
 #[derive(Debug, Deserialize)]
 struct CommentForm {
     post: i32,
@@ -121,6 +293,11 @@ struct CommentForm {
     name: String,
     email: String,
     url: Option<String>,
+    /// The comment this is a reply to, if any.
+    parent: Option<i32>,
+    /// A honeypot, hidden from humans.
+    #[serde(default)]
+    subject: String,
     csrftoken: String,
 }
 
@@ -129,16 +306,3 @@ impl CommentForm {
         safe_md2html(&self.comment)
     }
 }
-
-fn remote_addr_filter(proxied: bool) -> BoxedFilter<(IpAddr,)> {
-    if proxied {
-        header::header("x-forwarded-for").boxed()
-    } else {
-        warp::filters::any::any()
-            .map(|| {
-                tracing::warn!("Executed remote_addr_filter without proxy.");
-                IpAddr::from(Ipv4Addr::from([127, 0, 0, 1]))
-            })
-            .boxed()
-    }
-}
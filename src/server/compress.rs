@@ -0,0 +1,188 @@
+//! Compression of responses, negotiated by `Accept-Encoding`.
+use super::error::ViewResult;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use http_body_util::BodyExt;
+use std::io::Write;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::http::header::{
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderValue,
+    VARY,
+};
+use warp::reply::Response;
+use warp::{Filter, Reply, header};
+
+include!(concat!(env!("OUT_DIR"), "/precompressed.rs"));
+
+const ACCEPTS: &str = "accept-encoding";
+
+/// A content encoding to use for a response.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    #[default]
+    Identity,
+}
+
+impl Encoding {
+    /// Select the preferred supported encoding from an
+    /// `Accept-Encoding` header value.
+    ///
+    /// Brotli is preferred over gzip when the client has no preference.
+    fn select(accept: &str) -> Self {
+        let mut best = (Encoding::Identity, 0.0);
+        for item in accept.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+                .unwrap_or(0.0);
+            let encoding = match name {
+                "br" => Encoding::Brotli,
+                "gzip" | "x-gzip" => Encoding::Gzip,
+                "*" => Encoding::Brotli,
+                _ => continue,
+            };
+            if q <= 0.0 {
+                continue;
+            }
+            if q > best.1 || (q == best.1 && encoding < best.0) {
+                best = (encoding, q);
+            }
+        }
+        best.0
+    }
+
+    fn name(self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Identity => None,
+        }
+    }
+
+    /// Get the build-time compressed version of a static file.
+    pub fn precompressed(self, name: &str) -> Option<&'static [u8]> {
+        let pos = PRECOMPRESSED.binary_search_by_key(&name, |s| s.0).ok()?;
+        let (_, br, gz) = PRECOMPRESSED[pos];
+        match self {
+            Encoding::Brotli => Some(br),
+            Encoding::Gzip => Some(gz),
+            Encoding::Identity => None,
+        }
+    }
+
+    /// Add the content encoding and vary headers to a response.
+    pub fn headers(self, headers: &mut HeaderMap) {
+        headers.append(VARY, HeaderValue::from_static(ACCEPTS));
+        if let Some(name) = self.name() {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(name));
+            headers.remove(CONTENT_LENGTH);
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut buf = Vec::new();
+                brotli::CompressorWriter::new(&mut buf, 4096, 5, 22)
+                    .write_all(data)?;
+                Ok(buf)
+            }
+            Encoding::Gzip => {
+                let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+                gz.write_all(data)?;
+                gz.finish()
+            }
+            Encoding::Identity => Ok(data.to_vec()),
+        }
+    }
+}
+
+/// A filter extracting the preferred encoding of the request.
+pub fn accept_encoding() -> BoxedFilter<(Encoding,)> {
+    header::optional("accept-encoding")
+        .map(|accept: Option<String>| {
+            accept.as_deref().map(Encoding::select).unwrap_or_default()
+        })
+        .boxed()
+}
+
+/// Compress a reply if the content type is compressible and the
+/// client accepts it.
+///
+/// Replies that already has a content encoding, or that varies by
+/// accepted encoding, are left as is.
+pub async fn compress(encoding: Encoding, reply: impl Reply) -> Response {
+    let response = reply.into_response();
+    if response.status() != StatusCode::OK
+        || response.headers().contains_key(CONTENT_ENCODING)
+        || response
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .any(|v| v == ACCEPTS)
+        || !response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .is_some_and(is_compressible)
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    if encoding == Encoding::Identity {
+        encoding.headers(&mut parts.headers);
+        return Response::from_parts(parts, body);
+    }
+    let data = match body.collect().await.or_ise() {
+        Ok(data) => data.to_bytes(),
+        Err(e) => return e.into_response(),
+    };
+    // Compressing very short content is not worth it.
+    let (encoding, data) = if data.len() < 1000 {
+        (Encoding::Identity, data)
+    } else {
+        match encoding.compress(&data).or_ise() {
+            Ok(compressed) => (encoding, compressed.into()),
+            Err(e) => return e.into_response(),
+        }
+    };
+    encoding.headers(&mut parts.headers);
+    Response::from_parts(parts, data.into())
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(
+            mime,
+            "application/javascript" | "application/json" | "application/xml"
+        )
+}
+
+#[test]
+fn select_prefers_brotli() {
+    assert_eq!(Encoding::select("gzip, deflate, br"), Encoding::Brotli);
+}
+#[test]
+fn select_by_quality() {
+    assert_eq!(Encoding::select("br;q=0.5, gzip"), Encoding::Gzip);
+}
+#[test]
+fn select_refused() {
+    assert_eq!(Encoding::select("br;q=0, deflate"), Encoding::Identity);
+}
+#[test]
+fn compressible_types() {
+    assert!(is_compressible("text/html; charset=utf-8"));
+    assert!(is_compressible("application/atom+xml"));
+    assert!(!is_compressible("image/png"));
+}
@@ -0,0 +1,163 @@
+//! Support for conditional GET requests.
+//!
+//! Handlers create a [`Validator`] from the data a response depends
+//! on.  If the client already has a matching version, a `304 Not
+//! Modified` response is sent instead of the full content.
+use super::{AppData, response};
+use chrono::{DateTime, SubsecRound, Utc};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::http::header::{ETAG, LAST_MODIFIED};
+use warp::http::response::Builder;
+use warp::reply::Response;
+use warp::{Filter, header};
+
+/// The conditional headers of a request.
+#[derive(Debug, Default)]
+pub struct Conditions {
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+}
+
+/// A filter extracting the conditional headers of a request.
+pub fn conditions() -> BoxedFilter<(Conditions,)> {
+    header::optional("if-none-match")
+        .and(header::optional("if-modified-since"))
+        .map(|if_none_match, if_modified_since| Conditions {
+            if_none_match,
+            if_modified_since,
+        })
+        .boxed()
+}
+
+/// `ETag` and `Last-Modified` values for a response.
+#[derive(Debug)]
+pub struct Validator {
+    etag: String,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl Validator {
+    /// Validators for a response depending on data last modified at
+    /// `modified`, and on anything else that is in `key`.
+    ///
+    /// The start time of the server is included, since rendering may
+    /// change between versions.
+    pub fn new(
+        app: &AppData,
+        modified: DateTime<Utc>,
+        key: impl Hash,
+    ) -> Self {
+        let modified = modified.max(app.started).trunc_subsecs(0);
+        Validator {
+            etag: format!(
+                "W/\"{:x}-{:x}-{:x}\"",
+                app.started.timestamp(),
+                modified.timestamp(),
+                hash(key),
+            ),
+            modified: Some(modified),
+        }
+    }
+
+    /// Validator for content that is fully identified by `key`, such
+    /// as the content of a static file.
+    pub fn fixed(key: impl Hash) -> Self {
+        Validator {
+            etag: format!("\"{:x}\"", hash(key)),
+            modified: None,
+        }
+    }
+
+    /// Get a `304 Not Modified` response if the `conditions` matches
+    /// this validator.
+    pub fn not_modified(&self, conditions: &Conditions) -> Option<Response> {
+        if self.is_fresh(conditions) {
+            Some(
+                self.headers(response().status(StatusCode::NOT_MODIFIED))
+                    .body(Default::default())
+                    .unwrap_or_default(),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn is_fresh(&self, conditions: &Conditions) -> bool {
+        if let Some(none_match) = &conditions.if_none_match {
+            // If-None-Match takes precedence, and uses weak comparison.
+            let mine = self.etag.trim_start_matches("W/");
+            none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == mine)
+        } else if let (Some(since), Some(modified)) =
+            (&conditions.if_modified_since, self.modified)
+        {
+            DateTime::parse_from_rfc2822(since)
+                .is_ok_and(|since| modified <= since)
+        } else {
+            false
+        }
+    }
+
+    /// Add the validator headers to a response builder.
+    pub fn headers(&self, builder: Builder) -> Builder {
+        let builder = builder.header(ETAG, &self.etag);
+        if let Some(modified) = self.modified {
+            builder.header(
+                LAST_MODIFIED,
+                modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            )
+        } else {
+            builder
+        }
+    }
+}
+
+fn hash(key: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+fn test_validator() -> Validator {
+    Validator {
+        etag: "W/\"1-2-x\"".into(),
+        modified: Some("2024-03-01T11:00:00Z".parse().unwrap()),
+    }
+}
+#[test]
+fn fresh_by_etag() {
+    let cond = Conditions {
+        if_none_match: Some("\"0-0-y\", \"1-2-x\"".into()),
+        if_modified_since: None,
+    };
+    assert!(test_validator().is_fresh(&cond));
+}
+#[test]
+fn stale_by_etag_despite_date() {
+    let cond = Conditions {
+        if_none_match: Some("W/\"1-2-y\"".into()),
+        if_modified_since: Some("Fri, 01 Mar 2024 11:00:00 GMT".into()),
+    };
+    assert!(!test_validator().is_fresh(&cond));
+}
+#[test]
+fn fresh_by_date() {
+    let cond = Conditions {
+        if_none_match: None,
+        if_modified_since: Some("Fri, 01 Mar 2024 11:00:00 GMT".into()),
+    };
+    assert!(test_validator().is_fresh(&cond));
+}
+#[test]
+fn stale_by_date() {
+    let cond = Conditions {
+        if_none_match: None,
+        if_modified_since: Some("Fri, 01 Mar 2024 10:59:59 GMT".into()),
+    };
+    assert!(!test_validator().is_fresh(&cond));
+}
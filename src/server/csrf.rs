@@ -1,7 +1,9 @@
 use super::{Result, ViewError, ViewResult};
+use aws_lc_rs::hmac;
 use base64::prelude::*;
 use csrf::{AesGcmCsrfProtection, CsrfCookie, CsrfProtection, CsrfToken};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Clone)]
 pub struct Secret {
@@ -26,14 +28,47 @@ pub struct BadLengthSecret(usize);
 /// Server data for csrf handling.
 pub struct Server {
     prot: AesGcmCsrfProtection,
+    /// Key for signing links, that are used without a cookie.
+    links: hmac::Key,
 }
 impl Server {
     pub fn from_key(key: &Secret) -> Self {
         Self {
             prot: AesGcmCsrfProtection::from_key(key.secret),
+            links: hmac::Key::new(hmac::HMAC_SHA256, &key.secret),
         }
     }
-    pub fn verify(&self, token: &str, cookie: &str) -> Result<()> {
+    /// Sign a `message`, such as the action of a link in a mail.
+    pub fn sign(&self, message: &str) -> String {
+        let tag = hmac::sign(&self.links, message.as_bytes());
+        BASE64_URL_SAFE_NO_PAD.encode(tag)
+    }
+    /// Verify a `signature` created by [`Server::sign`].
+    pub fn verify_signed(
+        &self,
+        message: &str,
+        signature: &str,
+    ) -> Result<()> {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .ok()
+            .and_then(|tag| {
+                hmac::verify(&self.links, message.as_bytes(), &tag).ok()
+            })
+            .ok_or_else(|| {
+                tracing::info!("Bad signature for {message:?}.");
+                ViewError::BadRequest("Bad signature".into())
+            })
+    }
+    /// Verify a token and cookie pair.
+    ///
+    /// On success, returns the time since the pair was generated, if
+    /// known.
+    pub fn verify(
+        &self,
+        token: &str,
+        cookie: &str,
+    ) -> Result<Option<Duration>> {
         fn fail<E: std::fmt::Display>(e: E) -> ViewError {
             tracing::info!("Csrf verification error: {}", e);
             ViewError::BadRequest("CSRF Verification Failed".into())
@@ -42,10 +77,69 @@ impl Server {
         let cookie = BASE64_STANDARD.decode(cookie).map_err(fail)?;
         let token = self.prot.parse_token(&token).map_err(fail)?;
         let cookie = self.prot.parse_cookie(&cookie).map_err(fail)?;
-        self.prot.verify_token_pair(&token, &cookie).map_err(fail)
+        self.prot.verify_token_pair(&token, &cookie).map_err(fail)?;
+        let generated = token.value()[..8].try_into().map(u64::from_be_bytes);
+        Ok(generated.ok().and_then(|generated| {
+            let age = now_millis().checked_sub(generated)?;
+            // Tokens from before timestamps was added are random.
+            (age <= TTL * 1000).then(|| Duration::from_millis(age))
+        }))
     }
+    /// Generate a token and cookie pair.
+    ///
+    /// The (encrypted) value of the pair contains the current time,
+    /// so the age of a form can be known when it is posted.
     pub fn generate_pair(&self) -> Result<(CsrfToken, CsrfCookie)> {
-        let ttl = 4 * 3600;
-        self.prot.generate_token_pair(None, ttl).or_ise()
+        let mut value = [0; 64];
+        self.prot.random_bytes(&mut value).or_ise()?;
+        value[..8].copy_from_slice(&now_millis().to_be_bytes());
+        self.prot
+            .generate_token_pair(Some(&value), TTL.try_into().or_ise()?)
+            .or_ise()
     }
 }
+
+/// Time to live for csrf tokens, in seconds.
+const TTL: u64 = 4 * 3600;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The name of the csrf cookie for the admin pages.
+///
+/// It is separate from the cookie of the public pages, so viewing a
+/// post does not invalidate a pending moderation form.
+pub const ADMIN_COOKIE: &str = "ADMIN_CSRF";
+
+/// The value of a `set-cookie` header for a csrf cookie.
+pub fn cookie_header(cookie: &CsrfCookie) -> String {
+    header_value("CSRF", "/", cookie)
+}
+
+/// The value of a `set-cookie` header for an admin csrf cookie.
+pub fn admin_cookie_header(cookie: &CsrfCookie) -> String {
+    header_value(ADMIN_COOKIE, "/admin", cookie)
+}
+
+fn header_value(name: &str, path: &str, cookie: &CsrfCookie) -> String {
+    format!(
+        "{name}={}; SameSite=Strict; Path={path}; Secure; HttpOnly",
+        cookie.b64_string()
+    )
+}
+
+#[test]
+fn signed_message() {
+    let server = Server::from_key(
+        &"0123456789abcdef0123456789abcdef".parse().unwrap(),
+    );
+    let sig = server.sign("approve comment 17");
+    assert!(server.verify_signed("approve comment 17", &sig).is_ok());
+    assert!(server.verify_signed("approve comment 18", &sig).is_err());
+    assert!(server.verify_signed("spam comment 17", &sig).is_err());
+    assert!(server.verify_signed("approve comment 17", "x").is_err());
+}
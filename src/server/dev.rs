@@ -0,0 +1,340 @@
+//! A server for previewing content, rendered directly from markdown.
+//!
+//! Drafts are included, nothing is written to the database, and the
+//! pages reload themselves when any content file is changed.
+use super::assets::static_file;
+use super::compress::accept_encoding;
+use super::conditional::conditions;
+use super::pager::Pager;
+use super::templates::{self, RenderRucte};
+use super::{FatalError, PostPage, Result, SlugAndLang, ViewError};
+use super::{ViewResult, error, goh, response};
+use crate::models::MyLang;
+use crate::readfiles::ImgClientOpt;
+use crate::readfiles::dev::{Page, Renderer, watch};
+use clap::Parser;
+use pulldown_cmark_escape::escape_html;
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch as channel;
+use tracing::{error, info};
+use warp::http::StatusCode;
+use warp::http::header::CONTENT_TYPE;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+#[derive(Parser)]
+pub struct Args {
+    #[clap(flatten)]
+    img: ImgClientOpt,
+
+    /// Adress to listen on
+    #[clap(long, default_value = "127.0.0.1:8765")]
+    bind: SocketAddr,
+
+    /// The paths to read content from.
+    #[clap(value_parser, required = true)]
+    files: Vec<PathBuf>,
+}
+
+impl Args {
+    pub async fn run(self) -> anyhow::Result<()> {
+        use warp::path::{end, param, path, tail};
+        let acceptor = TcpListener::bind(self.bind)
+            .await
+            .map_err(|e| FatalError::Bind(self.bind, e))?;
+        // The renderer uses a blocking http client, that can't be created
+        // (or dropped) in async context.
+        let (img, files) = (self.img.clone(), self.files.clone());
+        let renderer =
+            tokio::task::spawn_blocking(move || Renderer::new(&img, files))
+                .await??;
+        let (sender, _) = channel::channel(0);
+        let dev = Arc::new(DevData {
+            renderer: Mutex::new(renderer),
+            changes: sender,
+        });
+        let watched = dev.clone();
+        let files = self.files.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = watch(&files, || {
+                watched.changes.send_modify(|generation| *generation += 1)
+            }) {
+                error!("Watching failed: {e:?}");
+            }
+        });
+        let s = warp::any().map(move || dev.clone()).boxed();
+        let s = move || s.clone();
+
+        let routes = warp::any()
+            .and(path("s"))
+            .and(
+                param()
+                    .and(param())
+                    .and(end())
+                    .and(goh())
+                    .and(s())
+                    .then(asset)
+                    .or(tail()
+                        .and(goh())
+                        .and(conditions())
+                        .and(accept_encoding())
+                        .map(static_file))
+                    .unify(),
+            )
+            .or(path("-")
+                .and(path("changes"))
+                .and(param())
+                .and(end())
+                .and(s())
+                .then(changes))
+            .or(path("-")
+                .and(path("teaser"))
+                .and(param())
+                .and(param())
+                .and(end())
+                .and(goh())
+                .and(s())
+                .then(teaser))
+            .or(end().and(goh()).and(s()).then(index))
+            .or(param()
+                .and(param())
+                .and(end())
+                .and(goh())
+                .and(s())
+                .then(page))
+            .or(param()
+                .and(end())
+                .and(goh())
+                .and(s())
+                .then(|slug, dev| page(0, slug, dev)))
+            .recover(error::for_rejection);
+
+        if let Ok(addr) = acceptor.local_addr() {
+            info!("Previewing on http://{addr}/");
+        }
+        warp::serve(routes).incoming(acceptor).run().await;
+        Ok(())
+    }
+}
+
+struct DevData {
+    renderer: Mutex<Renderer>,
+    /// Incremented each time some content is changed.
+    changes: channel::Sender<u64>,
+}
+type Dev = Arc<DevData>;
+
+impl DevData {
+    /// Run `f` with the renderer, on a thread where blocking is ok.
+    async fn with_renderer<T: Send + 'static>(
+        self: &Dev,
+        f: impl FnOnce(&mut Renderer) -> T + Send + 'static,
+    ) -> Result<T> {
+        let dev = self.clone();
+        tokio::task::spawn_blocking(move || {
+            // A panic while rendering some content should not stop the
+            // preview of other (or fixed) content.
+            let mut renderer =
+                dev.renderer.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut renderer)
+        })
+        .await
+        .or_ise()
+    }
+}
+
+async fn index(dev: Dev) -> Result<Response> {
+    let generation = *dev.changes.borrow();
+    let sources = dev.with_renderer(|r| r.sources()).await?;
+    let mut content = String::new();
+    match sources {
+        Ok(sources) => {
+            content.push_str("<ul>\n");
+            for source in sources {
+                content.push_str("<li><a href='");
+                escape_html(&mut content, &source.url).or_ise()?;
+                content.push_str("'>");
+                escape_html(&mut content, &source.url).or_ise()?;
+                content.push_str("</a> ");
+                if source.is_post {
+                    content.push_str("(<a href='/-/teaser");
+                    escape_html(&mut content, &source.url).or_ise()?;
+                    content.push_str("'>teaser</a>) ");
+                }
+                escape_html(&mut content, &source.path.to_string_lossy())
+                    .or_ise()?;
+                if source.is_draft {
+                    content.push_str(" \u{1f58b}");
+                }
+                content.push_str("</li>\n");
+            }
+            content.push_str("</ul>\n");
+        }
+        Err(e) => error_content(&mut content, &e)?,
+    }
+    let lang = MyLang::default();
+    html_page(StatusCode::OK, lang, "Preview", &content, generation)
+}
+
+/// A post or meta page, like on the real server.
+///
+/// Meta pages have the year 0.
+async fn page(year: i16, slug: SlugAndLang, dev: Dev) -> Result<Response> {
+    let generation = *dev.changes.borrow();
+    let lang = slug.lang;
+    match render(year, slug, &dev).await? {
+        Ok(Page::Meta {
+            lang,
+            title,
+            content,
+        }) => html_page(StatusCode::OK, lang, &title, &content, generation),
+        Ok(Page::Post { post, teaser }) => {
+            let mut html = Vec::new();
+            templates::post_html(
+                &mut html,
+                post.lang.fluent(),
+                &post,
+                &PostPage {
+                    canonical_url: &post.url(),
+                    tags: teaser.tags(),
+                    bad_comment: false,
+                    csrf: "",
+                    comments: &[],
+                    mentions: &[],
+                    other_langs: &[],
+                    similar: &[],
+                },
+            )
+            .or_ise()?;
+            reloading(StatusCode::OK, html, generation)
+        }
+        Err(e) => error_page(lang, &e, generation),
+    }
+}
+
+/// The teaser of a post, as on the front page.
+async fn teaser(year: i16, slug: SlugAndLang, dev: Dev) -> Result<Response> {
+    let generation = *dev.changes.borrow();
+    let lang = slug.lang;
+    match render(year, slug, &dev).await? {
+        Ok(Page::Post { teaser, .. }) => {
+            let mut html = Vec::new();
+            templates::frontpage_html(
+                &mut html,
+                lang.fluent(),
+                &[*teaser],
+                &Pager::default(),
+                &[],
+                &[],
+                &[],
+            )
+            .or_ise()?;
+            reloading(StatusCode::OK, html, generation)
+        }
+        Ok(Page::Meta { .. }) => Err(ViewError::NotFound),
+        Err(e) => error_page(lang, &e, generation),
+    }
+}
+
+/// Render a page, or not found if there is no such page.
+async fn render(
+    year: i16,
+    slug: SlugAndLang,
+    dev: &Dev,
+) -> Result<anyhow::Result<Page>> {
+    dev.with_renderer(move |r| r.render(year, slug.slug.as_ref(), slug.lang))
+        .await?
+        .transpose()
+        .ok_or(ViewError::NotFound)
+}
+
+fn error_page(
+    lang: MyLang,
+    e: &anyhow::Error,
+    generation: u64,
+) -> Result<Response> {
+    let mut content = String::new();
+    error_content(&mut content, e)?;
+    html_page(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        lang,
+        "Failed to render",
+        &content,
+        generation,
+    )
+}
+
+/// An asset stored when rendering a page.
+async fn asset(year: i16, name: String, dev: Dev) -> Result<Response> {
+    let (mime, content) = dev
+        .with_renderer(move |r| {
+            r.asset(year, &name)
+                .map(|(mime, content)| (mime.to_string(), content.to_vec()))
+        })
+        .await?
+        .ok_or(ViewError::NotFound)?;
+    response()
+        .header(CONTENT_TYPE, mime)
+        .body(content.into())
+        .or_ise()
+}
+
+/// Wait (for a while) until the content generation is not `seen`.
+///
+/// The response is the current generation.
+async fn changes(seen: u64, dev: Dev) -> Result<Response> {
+    let mut changes = dev.changes.subscribe();
+    let changed = changes.wait_for(|generation| *generation != seen);
+    let _ = tokio::time::timeout(Duration::from_secs(50), changed).await;
+    let current = *changes.borrow();
+    Ok(current.to_string().into_response())
+}
+
+fn error_content(out: &mut String, e: &anyhow::Error) -> Result<()> {
+    out.push_str("<pre>");
+    escape_html(&mut *out, &format!("{e:#}")).or_ise()?;
+    out.push_str("</pre>\n");
+    Ok(())
+}
+
+/// Render a page, with a script that reloads it on changes.
+fn html_page(
+    status: StatusCode,
+    lang: MyLang,
+    title: &str,
+    content: &str,
+    generation: u64,
+) -> Result<Response> {
+    let mut html = Vec::new();
+    templates::page_html(&mut html, lang.fluent(), title, content, &[])
+        .or_ise()?;
+    reloading(status, html, generation)
+}
+
+/// A response of `html`, with a script that reloads it on changes.
+fn reloading(
+    status: StatusCode,
+    html: Vec<u8>,
+    generation: u64,
+) -> Result<Response> {
+    let html = String::from_utf8(html).or_ise()?;
+    let script = format!(
+        "<script>(function poll(g) {{\
+         fetch('/-/changes/' + g).then(r => r.text())\
+         .then(n => n == g ? poll(g) : location.reload())\
+         .catch(() => setTimeout(() => poll(g), 2000));\
+         }})({generation});</script>\n"
+    );
+    let html = match html.rfind("</body>") {
+        Some(i) => format!("{}{script}{}", &html[..i], &html[i..]),
+        None => html + &script,
+    };
+    Ok(response()
+        .status(status)
+        .html(|o| o.write_all(html.as_bytes()))?)
+}
@@ -2,7 +2,10 @@ use super::response;
 use super::templates::{self, RenderError, RenderRucte};
 use crate::models::MyLang;
 use diesel_async::pooled_connection::deadpool::PoolError;
+use i18n_embed_fl::fl;
+use std::time::Duration;
 use tracing::{Level, event};
+use warp::http::header::{HeaderValue, RETRY_AFTER, WWW_AUTHENTICATE};
 use warp::http::status::StatusCode;
 use warp::reply::Response;
 use warp::{self, Rejection, Reply};
@@ -13,6 +16,11 @@ pub enum ViewError {
     NotFound,
     /// 400
     BadRequest(String),
+    /// 401, asking for http basic auth.
+    Unauthorized,
+    /// 429, with the time until the client may try again, and the
+    /// language to tell it in.
+    TooManyRequests(Duration, MyLang),
     /// 503
     ServiceUnavailable,
     /// 500
@@ -48,6 +56,34 @@ impl Reply for ViewError {
             ViewError::BadRequest(msg) => {
                 error_response(StatusCode::BAD_REQUEST, &msg, "Sorry.")
             }
+            ViewError::Unauthorized => {
+                let mut response = error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "Unauthorized",
+                    "You need to log in to view this page.",
+                );
+                response.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static("Basic realm=\"r4s admin\""),
+                );
+                response
+            }
+            ViewError::TooManyRequests(retry_after, lang) => {
+                let fluent = lang.fluent();
+                let secs = retry_after.as_secs() + 1;
+                let mut response = error_response_in(
+                    lang,
+                    StatusCode::TOO_MANY_REQUESTS,
+                    &fl!(fluent, "too-many"),
+                    &fl!(
+                        fluent,
+                        "too-many-detail",
+                        minutes = secs.div_ceil(60)
+                    ),
+                );
+                response.headers_mut().insert(RETRY_AFTER, secs.into());
+                response
+            }
             ViewError::ServiceUnavailable => error_response(
                 StatusCode::SERVICE_UNAVAILABLE,
                 "Server exhausted",
@@ -67,7 +103,16 @@ impl Reply for ViewError {
 }
 
 fn error_response(code: StatusCode, message: &str, detail: &str) -> Response {
-    let fluent = MyLang::default().fluent();
+    error_response_in(MyLang::default(), code, message, detail)
+}
+
+fn error_response_in(
+    lang: MyLang,
+    code: StatusCode,
+    message: &str,
+    detail: &str,
+) -> Response {
+    let fluent = lang.fluent();
     response()
         .status(code)
         .html(|o| templates::error_html(o, fluent, code, message, detail))
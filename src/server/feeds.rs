@@ -1,25 +1,46 @@
+use super::conditional::{Conditions, Validator, conditions};
 use super::error::{ViewError, ViewResult};
-use super::{App, Result, fl, response};
-use crate::models::{MyLang, Slug, Tag, Teaser};
+use super::{App, Result, SlugAndLang, fl, response};
+use crate::models::{DateTime, MyLang, PostComment, PostLink, Slug, Tag};
+use crate::models::{Teaser, year_of_date};
+use crate::schema::posts::dsl as p;
 use atom_syndication::*;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
 use std::str::FromStr;
 use tracing::instrument;
 use warp::filters::BoxedFilter;
 use warp::http::header::CONTENT_TYPE;
 use warp::path::{end, param};
+use warp::reply::Response;
 use warp::{self, Filter, Reply};
 
 pub fn routes(s: BoxedFilter<(App,)>) -> BoxedFilter<(impl Reply,)> {
-    param().and(end()).and(s).then(do_feed).boxed()
+    let posts = param()
+        .and(end())
+        .and(conditions())
+        .and(s.clone())
+        .then(do_feed);
+    let comments = param()
+        .and(end())
+        .and(conditions())
+        .and(s)
+        .then(comments_feed);
+    posts.or(comments).unify().boxed()
 }
 
 #[instrument]
-async fn do_feed(args: FeedArgs, app: App) -> Result<impl Reply> {
+async fn do_feed(
+    args: FeedArgs,
+    conditions: Conditions,
+    app: App,
+) -> Result<Response> {
     let mut db = app.db().await?;
 
-    let tag = if let Some(tag) = args.tag {
+    let tag = if let Some(tag) = &args.tag {
         Some(
-            Tag::by_slug(&tag, &mut db)
+            Tag::by_slug(tag, &mut db)
                 .await?
                 .ok_or(ViewError::NotFound)?,
         )
@@ -27,27 +48,101 @@ async fn do_feed(args: FeedArgs, app: App) -> Result<impl Reply> {
         None
     };
 
-    let fluent = args.lang.fluent();
     let lang = args.lang.as_ref();
     let tag_id = tag.as_ref().map(|t| t.id);
     let posts = if let Some(tag_id) = tag_id {
-        Teaser::tagged(tag_id, lang, 10, &mut db).await?
+        Teaser::tagged(tag_id, lang, 10, 0, &mut db).await?
     } else {
-        Teaser::recent(lang, 10, &mut db).await?
+        Teaser::recent(lang, 10, 0, &mut db).await?
+    };
+    let validator = Validator::new(
+        &app,
+        posts
+            .iter()
+            .map(|p| p.updated_at.raw())
+            .max()
+            .ok_or(ViewError::NotFound)?,
+        (
+            args.file_name(),
+            posts
+                .iter()
+                .map(|p| (p.id, p.n_comments()))
+                .collect::<Vec<_>>(),
+        ),
+    );
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
+    let info = FeedInfo::new(&app.base, &args, tag.as_ref());
+
+    let (content_type, body) = match args.format {
+        FeedFormat::Atom => atom_feed(&info, &posts)?,
+        FeedFormat::Rss => rss_feed(&info, &posts)?,
+        FeedFormat::Json => json_feed(&info, &posts)?,
     };
+    validator
+        .headers(response())
+        .header(CONTENT_TYPE, content_type)
+        .body(body.into())
+        .or_ise()
+}
+
+/// Data common to the feed in all formats.
+struct FeedInfo<'a> {
+    base: &'a str,
+    lang: MyLang,
+    title: String,
+    subtitle: String,
+    /// The html page that the feed corresponds to.
+    home: String,
+    /// The url of the feed itself.
+    url: String,
+}
+
+impl<'a> FeedInfo<'a> {
+    fn new(base: &'a str, args: &FeedArgs, tag: Option<&Tag>) -> Self {
+        let fluent = args.lang.fluent();
+        FeedInfo {
+            base,
+            lang: args.lang,
+            title: if let Some(tag) = tag {
+                fl!(fluent, "taggedhead", tag = tag.name.as_str())
+            } else {
+                fl!(fluent, "sitename")
+            },
+            subtitle: fl!(fluent, "tagline"),
+            home: if let Some(tag) = tag {
+                format!("{base}/tag/{}.{}", tag.slug, args.lang)
+            } else {
+                format!("{base}/")
+            },
+            url: format!("{base}/{}", args.file_name()),
+        }
+    }
+    fn post_url(&self, post: &Teaser) -> String {
+        format!("{}{}", self.base, post.url())
+    }
+    fn post_html(&self, post: &Teaser) -> String {
+        format!(
+            "{}\n<p class='readmore'><a href='{}'>{}</a></p>",
+            post.content,
+            self.post_url(post),
+            post.readmore(),
+        )
+    }
+}
 
+const AUTHOR_NAME: &str = "Rasmus Kaj";
+const AUTHOR_URL: &str = "https://rasmus.krats.se/rkaj";
+
+fn atom_feed(
+    info: &FeedInfo,
+    posts: &[Teaser],
+) -> Result<(&'static str, String)> {
     let feed = FeedBuilder::default()
-        .title(Text::plain(if let Some(ref tag) = tag {
-            fl!(fluent, "taggedhead", tag = tag.name.as_str())
-        } else {
-            fl!(fluent, "sitename")
-        }))
-        .subtitle(Text::plain(fl!(fluent, "tagline")))
-        .id(if let Some(ref tag) = tag {
-            format!("{}/tag/{}.{}", app.base, tag.slug, args.lang)
-        } else {
-            format!("{}/", app.base)
-        })
+        .title(Text::plain(info.title.clone()))
+        .subtitle(Text::plain(info.subtitle.clone()))
+        .id(info.home.clone())
         .updated(
             posts
                 .iter()
@@ -59,7 +154,7 @@ async fn do_feed(args: FeedArgs, app: App) -> Result<impl Reply> {
             posts
                 .iter()
                 .map(|post| {
-                    let url = format!("{}{}", app.base, post.url());
+                    let url = info.post_url(post);
                     EntryBuilder::default()
                         .title(post.title.clone())
                         .id(url.clone())
@@ -68,11 +163,8 @@ async fn do_feed(args: FeedArgs, app: App) -> Result<impl Reply> {
                         )
                         .author(
                             PersonBuilder::default()
-                                .name("Rasmus Kaj")
-                                .uri(Some(
-                                    "https://rasmus.krats.se/rkaj"
-                                        .to_string(),
-                                ))
+                                .name(AUTHOR_NAME)
+                                .uri(Some(AUTHOR_URL.to_string()))
                                 .build(),
                         )
                         .updated(post.updated_at.raw())
@@ -87,12 +179,7 @@ async fn do_feed(args: FeedArgs, app: App) -> Result<impl Reply> {
                                 })
                                 .collect::<Vec<_>>(),
                         )
-                        .summary(Text::html(format!(
-                            "{}\n<p class='readmore'><a href='{}'>{}</a></p>",
-                            post.content,
-                            url,
-                            post.readmore(),
-                        )))
+                        .summary(Text::html(info.post_html(post)))
                         .published(Some(FixedDateTime::from(
                             post.posted_at.raw(),
                         )))
@@ -101,34 +188,376 @@ async fn do_feed(args: FeedArgs, app: App) -> Result<impl Reply> {
                 .collect::<Vec<_>>(),
         )
         .build();
+    Ok(("application/atom+xml", feed.to_string()))
+}
+
+/// Atom feed of the latest comments on all posts.
+#[instrument]
+async fn comments_feed(
+    args: CommentFeedArgs,
+    conditions: Conditions,
+    app: App,
+) -> Result<Response> {
+    let mut db = app.db().await?;
+    let comments = PostComment::for_feed(None, 20, &mut db).await?;
+    let fluent = args.lang.fluent();
+    let updated = comments
+        .iter()
+        .map(|c| c.posted_at.raw())
+        .max()
+        .ok_or(ViewError::NotFound)?;
+    let validator = Validator::new(
+        &app,
+        updated,
+        (
+            args.lang.as_ref(),
+            comments
+                .iter()
+                .map(|c| (c.id, &c.content))
+                .collect::<Vec<_>>(),
+        ),
+    );
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
+    comment_atom(
+        validator,
+        &app.base,
+        fl!(fluent, "comments-feed"),
+        format!("{}/comments-{}.xml", app.base, args.lang),
+        updated,
+        &comments,
+    )
+}
+
+/// Atom feed of the comments on a single post.
+#[instrument]
+pub async fn post_comments_feed(
+    year: i16,
+    slug: SlugAndLang,
+    conditions: Conditions,
+    app: App,
+) -> Result<Response> {
+    let mut db = app.db().await?;
+    let (post, updated) = p::posts
+        .select((PostLink::as_select(), p::updated_at))
+        .filter(year_of_date(p::posted_at).eq(&year))
+        .filter(p::slug.eq(slug.slug.as_ref()))
+        .filter(p::lang.eq(slug.lang.as_ref()))
+        .first::<(PostLink, DateTime)>(&mut db)
+        .await
+        .optional()?
+        .ok_or(ViewError::NotFound)?;
+    let comments = PostComment::for_feed(Some(post.id), 50, &mut db).await?;
+    let updated = comments
+        .iter()
+        .map(|c| c.posted_at.raw())
+        .chain([updated.raw()])
+        .max()
+        .unwrap_or_default();
+    let validator = Validator::new(
+        &app,
+        updated,
+        comments
+            .iter()
+            .map(|c| (c.id, &c.content))
+            .collect::<Vec<_>>(),
+    );
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
+    let fluent = post.lang.fluent();
+    comment_atom(
+        validator,
+        &app.base,
+        fl!(fluent, "comments-on", title = post.title.as_str()),
+        format!("{}{}/comments.xml", app.base, post.url()),
+        updated,
+        &comments,
+    )
+}
+
+fn comment_atom(
+    validator: Validator,
+    base: &str,
+    title: String,
+    id: String,
+    updated: chrono::DateTime<chrono::Utc>,
+    comments: &[PostComment],
+) -> Result<Response> {
+    let feed = FeedBuilder::default()
+        .title(Text::plain(title))
+        .id(id.clone())
+        .link(LinkBuilder::default().href(id).rel("self").build())
+        .updated(updated)
+        .entries(
+            comments
+                .iter()
+                .map(|comment| {
+                    let url = format!("{base}{}", comment.url());
+                    let fluent = comment.p().lang.fluent();
+                    EntryBuilder::default()
+                        .title(fl!(
+                            fluent,
+                            "c-by-on",
+                            name = comment.name(),
+                            title = comment.post_title()
+                        ))
+                        .id(url.clone())
+                        .link(LinkBuilder::default().href(url).build())
+                        .author(
+                            PersonBuilder::default()
+                                .name(comment.name())
+                                .uri(comment.url.clone())
+                                .build(),
+                        )
+                        .updated(comment.posted_at.raw())
+                        .published(Some(FixedDateTime::from(
+                            comment.posted_at.raw(),
+                        )))
+                        .content(Some(
+                            ContentBuilder::default()
+                                .value(Some(comment.content.clone()))
+                                .content_type(Some("html".into()))
+                                .build(),
+                        ))
+                        .build()
+                })
+                .collect::<Vec<_>>(),
+        )
+        .build();
 
-    response()
+    validator
+        .headers(response())
         .header(CONTENT_TYPE, "application/atom+xml")
-        .body(feed.to_string())
+        .body(feed.to_string().into())
         .or_ise()
 }
 
+fn rss_feed(
+    info: &FeedInfo,
+    posts: &[Teaser],
+) -> Result<(&'static str, String)> {
+    use rss::{CategoryBuilder, ChannelBuilder, GuidBuilder, ItemBuilder};
+    let last_build = posts
+        .iter()
+        .map(|p| p.updated_at.raw())
+        .max()
+        .ok_or(ViewError::NotFound)?;
+    let channel = ChannelBuilder::default()
+        .title(info.title.clone())
+        .link(info.home.clone())
+        .description(info.subtitle.clone())
+        .language(Some(info.lang.to_string()))
+        .last_build_date(Some(last_build.to_rfc2822()))
+        .items(
+            posts
+                .iter()
+                .map(|post| {
+                    let url = info.post_url(post);
+                    ItemBuilder::default()
+                        .title(Some(post.title.clone()))
+                        .link(Some(url.clone()))
+                        .guid(Some(
+                            GuidBuilder::default()
+                                .value(url)
+                                .permalink(true)
+                                .build(),
+                        ))
+                        .pub_date(Some(post.posted_at.raw().to_rfc2822()))
+                        .categories(
+                            post.tags()
+                                .iter()
+                                .map(|tag| {
+                                    CategoryBuilder::default()
+                                        .name(tag.name.clone())
+                                        .build()
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .description(Some(info.post_html(post)))
+                        .build()
+                })
+                .collect::<Vec<_>>(),
+        )
+        .build();
+    Ok(("application/rss+xml", channel.to_string()))
+}
+
+fn json_feed(
+    info: &FeedInfo,
+    posts: &[Teaser],
+) -> Result<(&'static str, String)> {
+    if posts.is_empty() {
+        return Err(ViewError::NotFound);
+    }
+    let feed = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &info.title,
+        description: &info.subtitle,
+        home_page_url: &info.home,
+        feed_url: &info.url,
+        language: info.lang.as_ref(),
+        authors: [JsonAuthor {
+            name: AUTHOR_NAME,
+            url: AUTHOR_URL,
+        }],
+        items: posts
+            .iter()
+            .map(|post| {
+                let url = info.post_url(post);
+                JsonItem {
+                    id: url.clone(),
+                    url,
+                    title: &post.title,
+                    content_html: info.post_html(post),
+                    date_published: post.posted_at.raw().to_rfc3339(),
+                    date_modified: post.updated_at.raw().to_rfc3339(),
+                    tags: post
+                        .tags()
+                        .iter()
+                        .map(|t| t.name.as_str())
+                        .collect(),
+                }
+            })
+            .collect(),
+    };
+    Ok((
+        "application/feed+json",
+        serde_json::to_string(&feed).or_ise()?,
+    ))
+}
+
+/// A [JSON Feed](https://jsonfeed.org/version/1.1).
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    description: &'a str,
+    home_page_url: &'a str,
+    feed_url: &'a str,
+    language: &'a str,
+    authors: [JsonAuthor; 1],
+    items: Vec<JsonItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonAuthor {
+    name: &'static str,
+    url: &'static str,
+}
+
+#[derive(Serialize)]
+struct JsonItem<'a> {
+    id: String,
+    url: String,
+    title: &'a str,
+    content_html: String,
+    date_published: String,
+    date_modified: String,
+    tags: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FeedFormat {
+    Atom,
+    Rss,
+    Json,
+}
+
 #[derive(Debug)]
 struct FeedArgs {
+    format: FeedFormat,
     lang: MyLang,
     tag: Option<Slug>,
 }
 
+impl FeedArgs {
+    /// The file name of this feed, as parsed by [`FeedArgs::from_str`].
+    fn file_name(&self) -> String {
+        let (prefix, ext) = match self.format {
+            FeedFormat::Atom => ("atom", "xml"),
+            FeedFormat::Rss => ("rss", "xml"),
+            FeedFormat::Json => ("feed", "json"),
+        };
+        if let Some(tag) = &self.tag {
+            format!("{prefix}-{}-{tag}.{ext}", self.lang)
+        } else {
+            format!("{prefix}-{}.{ext}", self.lang)
+        }
+    }
+}
+
 impl FromStr for FeedArgs {
     type Err = ();
     fn from_str(value: &str) -> Result<Self, Self::Err> {
         use lazy_regex::regex_captures;
-        regex_captures!(r"^atom-([a-z]{2})(-([\w-]+))?.xml$", value)
-            .and_then(|(_, lang, _, tag)| {
-                Some(FeedArgs {
-                    lang: lang.parse().ok()?,
-                    tag: if tag.is_empty() {
-                        None
-                    } else {
-                        Some(tag.parse().ok()?)
-                    },
-                })
+        regex_captures!(
+            r"^(atom|rss|feed)-([a-z]{2})(-([\w-]+))?\.(xml|json)$",
+            value
+        )
+        .and_then(|(_, format, lang, _, tag, ext)| {
+            Some(FeedArgs {
+                format: match (format, ext) {
+                    ("atom", "xml") => FeedFormat::Atom,
+                    ("rss", "xml") => FeedFormat::Rss,
+                    ("feed", "json") => FeedFormat::Json,
+                    _ => return None,
+                },
+                lang: lang.parse().ok()?,
+                tag: if tag.is_empty() {
+                    None
+                } else {
+                    Some(tag.parse().ok()?)
+                },
             })
-            .ok_or(())
+        })
+        .ok_or(())
     }
 }
+
+/// Arguments for the site-wide comments feed, `comments-{lang}.xml`.
+#[derive(Debug)]
+struct CommentFeedArgs {
+    lang: MyLang,
+}
+
+impl FromStr for CommentFeedArgs {
+    type Err = ();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let lang = value
+            .strip_prefix("comments-")
+            .and_then(|v| v.strip_suffix(".xml"))
+            .ok_or(())?;
+        Ok(CommentFeedArgs {
+            lang: lang.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+#[test]
+fn feed_args_atom_tag() {
+    let args = "atom-sv-rust.xml".parse::<FeedArgs>().unwrap();
+    assert_eq!(args.format, FeedFormat::Atom);
+    assert_eq!(args.lang, MyLang::Sv);
+    assert_eq!(args.tag.as_deref(), Some("rust"));
+    assert_eq!(args.file_name(), "atom-sv-rust.xml");
+}
+#[test]
+fn feed_args_json() {
+    let args = "feed-en.json".parse::<FeedArgs>().unwrap();
+    assert_eq!(args.format, FeedFormat::Json);
+    assert_eq!(args.lang, MyLang::En);
+    assert_eq!(args.tag, None);
+}
+#[test]
+fn feed_args_rss() {
+    let args = "rss-en-some-tag.xml".parse::<FeedArgs>().unwrap();
+    assert_eq!(args.format, FeedFormat::Rss);
+    assert_eq!(args.tag.as_deref(), Some("some-tag"));
+}
+#[test]
+fn feed_args_bad_ext() {
+    assert!("feed-en.xml".parse::<FeedArgs>().is_err());
+    assert!("rss-en.json".parse::<FeedArgs>().is_err());
+}
@@ -9,12 +9,13 @@ use std::str::FromStr;
 use std::sync::LazyLock;
 use std::time::Instant;
 use tracing::info;
+use warp::{Filter, Rejection, header};
 
 #[derive(RustEmbed)]
 #[folder = "i18n/"]
 struct Localizations;
 
-static MYLANGS: [MyLang; 2] = [MyLang::En, MyLang::Sv];
+pub static MYLANGS: [MyLang; 2] = [MyLang::En, MyLang::Sv];
 
 #[tracing::instrument]
 fn load(lang: &str) -> Result<FluentLanguageLoader> {
@@ -75,6 +76,17 @@ impl AcceptLang {
     }
 }
 
+/// A filter for the preferred language of the client.
+///
+/// Taken from the accept-language header, with the default language
+/// as fallback.
+pub fn accept_lang()
+-> impl Filter<Extract = (MyLang,), Error = Rejection> + Copy {
+    header::optional("accept-language").map(|l: Option<AcceptLang>| {
+        l.map(AcceptLang::lang).unwrap_or_default()
+    })
+}
+
 impl FromStr for AcceptLang {
     type Err = ();
     fn from_str(value: &str) -> Result<Self, ()> {
@@ -1,26 +1,48 @@
+mod activitypub;
+mod admin;
+mod archive;
 mod assets;
+mod cache;
+mod clientip;
 mod comment;
+mod compress;
+mod conditional;
 mod csrf;
+pub mod dev;
 mod error;
 mod feeds;
 pub mod language;
+mod pager;
 mod prelude;
+mod ratelimit;
+mod search;
+mod sitemap;
 mod tag;
+mod webmention;
 
+use self::archive::Calendar;
+use self::cache::{CSRF_MARK, CachedPage, PageCache};
+use self::clientip::{ProxyHeader, TrustedProxies};
+use self::conditional::{Conditions, Validator, conditions};
 use self::error::{ViewError, ViewResult};
-use self::language::AcceptLang;
+use self::pager::{Pager, PagerQuery};
 use self::prelude::*;
+use self::ratelimit::RateLimiter;
 use self::templates::RenderRucte;
 use crate::PubBaseOpt;
 use crate::dbopt::{Connection, DbOpt, Pool};
+use crate::mailopt::{MailOpt, Mailer};
 use crate::models::{
-    Comment, FullPost, MyLang, PostComment, PostTag, Slug, Tag, Teaser,
-    year_of_date,
+    Comment, FullPost, MetaLink, MyLang, PostComment, PostLink, PostTag,
+    Slug, Tag, Teaser, Thread, Webmention, year_of_date,
 };
+use crate::outgoing::Outgoing;
 use crate::schema::comments::dsl as c;
 use crate::schema::metapages::dsl as m;
 use crate::schema::post_tags::dsl as pt;
 use crate::schema::posts::dsl as p;
+use crate::schema::webmentions::dsl as wm;
+use crate::spam::{Bayes, SpamFilter};
 use clap::Parser;
 use diesel::BelongingToDsl;
 use diesel::associations::HasTable;
@@ -28,11 +50,13 @@ use diesel::dsl::count;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use diesel_async::pooled_connection::deadpool::{BuildError, PoolError};
+use ipnetwork::IpNetwork;
 use serde::Deserialize;
+use std::io::Write as _;
 use std::net::SocketAddr;
 use std::ops::Deref;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::net::TcpListener;
 use tracing::{info, instrument, warn};
 use warp::filters::BoxedFilter;
@@ -41,7 +65,7 @@ use warp::http::header::{CONTENT_SECURITY_POLICY, SERVER, SET_COOKIE};
 use warp::http::header::{HeaderMap, HeaderName, HeaderValue};
 use warp::http::response::Builder;
 use warp::reply::Response;
-use warp::{self, Filter, Reply, header, redirect};
+use warp::{self, Filter, Reply, redirect};
 
 type Result<T, E = ViewError> = std::result::Result<T, E>;
 
@@ -57,16 +81,60 @@ pub struct Args {
     #[clap(flatten)]
     base: PubBaseOpt,
 
+    #[clap(flatten)]
+    mail: MailOpt,
+
+    #[clap(flatten)]
+    ap: activitypub::ApOpt,
+
     /// A 32-byte secret key for csrf generation and verification.
     #[clap(long, env = "CSRF_SECRET", hide_env_values = true)]
     csrf_secret: csrf::Secret,
 
-    /// Use this flag if the server runs behind a proxy.
+    /// Use this flag if the server runs behind a proxy on localhost.
     ///
-    /// Comments will then take their source ip address from the
-    /// `x-forwarded-for` header instead of the connected remote addr.
+    /// This is the same as giving the loopback addresses as trusted
+    /// proxies.
     #[clap(long)]
     is_proxied: bool,
+
+    /// A network of proxies to trust, e.g. `10.0.0.0/8`.
+    ///
+    /// When a request comes from a trusted proxy, the client address
+    /// is taken from the `--proxy-header`, skipping any further
+    /// trusted proxies.  Otherwise the connected remote address is
+    /// used.
+    #[clap(long, env = "TRUSTED_PROXIES", value_delimiter = ',')]
+    trusted_proxy: Vec<IpNetwork>,
+
+    /// The header set by the trusted proxies to tell the client address.
+    ///
+    /// The other header is never used, since a client can send it.
+    #[clap(long, env = "PROXY_HEADER", value_enum, default_value_t)]
+    proxy_header: ProxyHeader,
+
+    /// Number of comments accepted at once from a single address.
+    #[clap(long, default_value_t = 5)]
+    comment_burst: u32,
+
+    /// Number of comments accepted per hour from a single address,
+    /// after the initial burst.
+    #[clap(long, default_value_t = 10)]
+    comment_rate: u32,
+
+    /// Credential for the admin pages, as `user:password`.
+    ///
+    /// The admin pages, at `/admin/comments`, are disabled unless
+    /// this is given.
+    #[clap(long, env = "ADMIN_CREDENTIAL", hide_env_values = true)]
+    admin_credential: Option<admin::Credential>,
+
+    /// Allow webmention and ActivityPub requests to local addresses.
+    ///
+    /// This also allows plain http for ActivityPub.  Only for testing,
+    /// such as federation between local instances.
+    #[clap(long)]
+    allow_local_requests: bool,
 }
 
 impl Args {
@@ -74,15 +142,26 @@ impl Args {
         use warp::path::{end, param, path};
         use warp::query;
         let app = AppData::new(&self)?;
+        tokio::spawn(app.pages.clone().listen(self.db.clone()));
+        tokio::spawn(comment::load_bayes(app.clone()));
+        tokio::spawn(activitypub::deliver_posts(app.clone()));
+        let proxies = TrustedProxies::new(
+            self.trusted_proxy.clone(),
+            self.is_proxied,
+            self.proxy_header,
+        );
         let s = warp::any().map(move || app.clone()).boxed();
         let s = move || s.clone();
-        let lang_filt = header::optional("accept-language").map(
-            |l: Option<AcceptLang>| l.map(|l| l.lang()).unwrap_or_default(),
-        );
+        let lang_filt = language::accept_lang();
 
         let routes = warp::any()
             .and(path("s").and(assets::routes(s())))
-            .or(path("comment").and(comment::route(self.is_proxied, s())))
+            .or(path("comment").and(comment::route(proxies.clone(), s())))
+            .or(path("webmention")
+                .and(webmention::route(proxies.clone(), s())))
+            .or(path("admin").and(admin::routes(s())))
+            .or(path(".well-known").and(activitypub::webfinger_route(s())))
+            .or(path("ap").and(activitypub::routes(proxies, s())))
             .or(end()
                 .and(goh())
                 .and(lang_filt)
@@ -112,9 +191,12 @@ impl Args {
                 .and(s())
                 .then(yearpage)
                 .boxed())
+            .or(archive::routes(lang_filt.boxed(), s()))
             .or(param()
                 .and(end())
+                .and(query())
                 .and(goh())
+                .and(conditions())
                 .and(s())
                 .then(frontpage)
                 .boxed())
@@ -123,9 +205,19 @@ impl Args {
                 .and(end())
                 .and(query())
                 .and(goh())
+                .and(conditions())
                 .and(s())
                 .then(page)
                 .boxed())
+            .or(param()
+                .and(param())
+                .and(path("comments.xml"))
+                .and(end())
+                .and(goh())
+                .and(conditions())
+                .and(s())
+                .then(feeds::post_comments_feed)
+                .boxed())
             .or(param()
                 .and(param())
                 .and(end())
@@ -134,14 +226,25 @@ impl Args {
                 .and(s())
                 .then(page_fallback)
                 .boxed())
+            .or(search::route(s()))
             .or(param()
                 .and(end())
                 .and(goh())
+                .and(conditions())
                 .and(s())
                 .then(metapage)
                 .boxed())
             .or(feeds::routes(s()))
-            .or(path("robots.txt").and(end()).and(goh()).map(robots_txt))
+            .or(path("robots.txt")
+                .and(end())
+                .and(goh())
+                .and(s())
+                .map(robots_txt))
+            .or(path("sitemap.xml")
+                .and(end())
+                .and(goh())
+                .and(s())
+                .then(sitemap::sitemap))
             .or(param()
                 .and(end())
                 .and(lang_filt)
@@ -150,7 +253,9 @@ impl Args {
                 .then(metafallback)
                 .boxed());
 
-        let server = routes
+        let server = compress::accept_encoding()
+            .and(routes)
+            .then(compress::compress)
             .with(warp::reply::with::headers(common_headers()))
             .recover(error::for_rejection);
         let acceptor = TcpListener::bind(self.bind)
@@ -174,6 +279,12 @@ pub(crate) enum FatalError {
     DataPool(#[from] BuildError),
     #[error("Failed to bind {0}: {1:?}")]
     Bind(SocketAddr, std::io::Error),
+    #[error("Failed to configure smtp: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to create http client: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    ApKey(#[from] activitypub::KeyError),
 }
 
 async fn quit_sig() {
@@ -192,6 +303,17 @@ pub struct AppData {
     pool: Pool,
     base: String,
     csrf: csrf::Server,
+    admin: Option<admin::Credential>,
+    mailer: Option<Mailer>,
+    /// Client for requests to urls given by others.
+    http: Outgoing,
+    /// The key of the ActivityPub actors, if enabled.
+    ap: Option<activitypub::Key>,
+    spam: SpamFilter,
+    comment_limit: RateLimiter,
+    bayes: RwLock<Bayes>,
+    started: chrono::DateTime<chrono::Utc>,
+    pages: Arc<PageCache>,
 }
 type App = Arc<AppData>;
 
@@ -203,11 +325,23 @@ impl std::fmt::Debug for AppData {
 }
 
 impl AppData {
-    fn new(args: &Args) -> Result<App, BuildError> {
+    fn new(args: &Args) -> Result<App, FatalError> {
         Ok(Arc::new(AppData {
             pool: args.db.build_pool()?,
             base: args.base.public_base.clone(),
             csrf: csrf::Server::from_key(&args.csrf_secret),
+            admin: args.admin_credential.clone(),
+            mailer: args.mail.mailer()?,
+            http: Outgoing::new(args.allow_local_requests)?,
+            ap: args.ap.key()?,
+            spam: SpamFilter::default(),
+            comment_limit: RateLimiter::new(
+                args.comment_burst,
+                args.comment_rate,
+            ),
+            bayes: Default::default(),
+            started: chrono::Utc::now(),
+            pages: Default::default(),
         }))
     }
     async fn db(&self) -> Result<Connection, PoolError> {
@@ -255,13 +389,48 @@ fn common_headers() -> HeaderMap {
 }
 
 #[instrument]
-async fn frontpage(lang: MyLang, app: App) -> Result<Response> {
+async fn frontpage(
+    lang: MyLang,
+    query: PagerQuery,
+    conditions: Conditions,
+    app: App,
+) -> Result<Response> {
     let mut db = app.db().await?;
-    let limit = 5;
-    let posts = Teaser::recent(lang.as_ref(), limit, &mut db).await?;
+    let per_page = 5;
+    let offset = query.offset(per_page);
+    let mut posts =
+        Teaser::recent(lang.as_ref(), per_page + 1, offset, &mut db).await?;
+    if posts.is_empty() && offset > 0 {
+        return Err(ViewError::NotFound);
+    }
+    let pager = Pager::new(format!("/{lang}"), &query, per_page, &mut posts);
 
     let comments = PostComment::recent(&mut db).await?;
 
+    let validator = Validator::new(
+        &app,
+        posts
+            .iter()
+            .map(|p| p.updated_at)
+            .chain(comments.iter().map(|c| c.posted_at))
+            .map(|d| d.raw())
+            .max()
+            .unwrap_or_default(),
+        (
+            lang.as_ref(),
+            query.page(),
+            pager.next().is_some(),
+            posts
+                .iter()
+                .map(|p| (p.id, p.n_comments()))
+                .collect::<Vec<_>>(),
+            comments.iter().map(|c| c.id).collect::<Vec<_>>(),
+        ),
+    );
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
+
     let year = year_of_date(p::posted_at);
     let years = p::posts
         .select(year)
@@ -275,11 +444,12 @@ async fn frontpage(lang: MyLang, app: App) -> Result<Response> {
             "<a href='/{lang}' hreflang='{lang}' lang='{lang}' rel='alternate'>{name}</a>",
         )});
 
-    Ok(response().html(|o| {
+    Ok(validator.headers(response()).html(|o| {
         templates::frontpage_html(
             o,
             lang.fluent(),
             &posts,
+            &pager,
             &comments,
             &years,
             &other_langs,
@@ -303,6 +473,15 @@ impl FromStr for SlugAndLang {
     }
 }
 
+/// The side parts of a list of posts, shown when not empty.
+#[derive(Default)]
+pub struct Asides<'a> {
+    feed: Option<&'a str>,
+    pages: &'a [MetaLink],
+    calendar: Option<&'a Calendar>,
+    years: &'a [i16],
+}
+
 #[instrument]
 async fn yearpage(year: i16, lang: MyLang, app: App) -> Result<impl Reply> {
     let mut db = app.db().await?;
@@ -311,6 +490,8 @@ async fn yearpage(year: i16, lang: MyLang, app: App) -> Result<impl Reply> {
         return Err(ViewError::NotFound);
     }
 
+    let calendar = Calendar::load(year, None, lang, &mut db).await?;
+
     let p_year = year_of_date(p::posted_at);
     let years = p::posts
         .select(p_year)
@@ -331,9 +512,13 @@ async fn yearpage(year: i16, lang: MyLang, app: App) -> Result<impl Reply> {
             o,
             fluent,
             &h1,
-            None,
             &posts,
-            &years,
+            &Pager::default(),
+            &Asides {
+                calendar: Some(&calendar),
+                years: &years,
+                ..Asides::default()
+            },
             &other_langs,
         )
     })?)
@@ -344,10 +529,71 @@ async fn page(
     year: i16,
     slug: SlugAndLang,
     query: PageQuery,
+    conditions: Conditions,
     app: App,
 ) -> Result<Response> {
-    use crate::models::{PostLink, has_lang};
-    use diesel::dsl::not;
+    let url = format!("/{year}/{}.{}", slug.slug, slug.lang);
+    let (cached, generation) = app.pages.get(&url);
+    let page = match cached.filter(|page| page.is_current()) {
+        Some(page) if query.c.is_none() => page,
+        _ => match render_page(year, slug, query, &app).await? {
+            Ok((page, cacheable)) => {
+                let page = Arc::new(page);
+                if cacheable {
+                    app.pages.put(url, generation, page.clone());
+                }
+                page
+            }
+            Err(redirect) => return Ok(redirect),
+        },
+    };
+
+    let response = match &page.validator {
+        Some(validator) => {
+            if let Some(response) = validator.not_modified(&conditions) {
+                return Ok(response);
+            }
+            validator.headers(response())
+        }
+        None => response(),
+    };
+
+    // Posts that are open for comments has a per-response csrf token.
+    let (response, html) = if page.needs_csrf() {
+        let (token, cookie) = app.csrf.generate_pair()?;
+        let response =
+            response.header(SET_COOKIE, csrf::cookie_header(&cookie));
+        (response, page.html(&token.b64_string()))
+    } else {
+        (response, page.html(""))
+    };
+    Ok(response.html(|o| o.write_all(html.as_bytes()))?)
+}
+
+/// The data of a post page, except the post itself.
+pub struct PostPage<'a> {
+    canonical_url: &'a str,
+    tags: &'a [Tag],
+    bad_comment: bool,
+    csrf: &'a str,
+    comments: &'a [Thread],
+    mentions: &'a [Webmention],
+    other_langs: &'a [String],
+    similar: &'a [PostLink],
+}
+
+/// Load and render a post page.
+///
+/// The result is the page and true if it may be cached, or a redirect
+/// response.
+async fn render_page(
+    year: i16,
+    slug: SlugAndLang,
+    query: PageQuery,
+    app: &AppData,
+) -> Result<Result<(CachedPage, bool), Response>> {
+    use crate::models::has_lang;
+    use diesel::dsl::{max, not};
     let mut db = app.db().await?;
     let fluent = slug.lang.fluent();
     let s1 = slug.clone();
@@ -384,15 +630,46 @@ async fn page(
         .load(&mut db)
         .await?;
 
+    let mentions = Webmention::belonging_to(&post.deref())
+        .select(Webmention::as_select())
+        .filter(wm::is_public)
+        .order_by(wm::received_at.asc())
+        .load(&mut db)
+        .await?;
+
     let bad_comment = match query.c {
         Some(qc) if comments.iter().any(|c| c.id == qc) => {
             let url = format!("/{year}/{}.{}#c{qc:x}", slug.slug, slug.lang);
-            return Ok(found(&url).into_response());
+            return Ok(Err(found(&url)));
         }
         Some(_) => true,
         None => false,
     };
 
+    // Posts that are open for comments has a per-response csrf token,
+    // so only old posts can be validated.
+    let validator = if let Some(age) = post.updated_at.old_age() {
+        // Related posts may change when any post is updated.
+        let modified = p::posts
+            .select(max(p::updated_at))
+            .first::<Option<chrono::DateTime<chrono::Utc>>>(&mut db)
+            .await?
+            .unwrap_or_default();
+        let key = (
+            post.id,
+            age,
+            // The content, since a comment may be edited by a moderator.
+            comments
+                .iter()
+                .map(|c| (c.id, &c.content))
+                .collect::<Vec<_>>(),
+            mentions.iter().map(|m| m.id).collect::<Vec<_>>(),
+        );
+        Some(Validator::new(app, modified, key))
+    } else {
+        None
+    };
+
     let tags = PostTag::belonging_to(post.deref())
         .inner_join(Tag::table())
         .select(Tag::as_select())
@@ -417,30 +694,26 @@ async fn page(
         .load(&mut db)
         .await?;
 
-    let (token, cookie) = app.csrf.generate_pair()?;
-
-    Ok(response()
-        .header(
-            SET_COOKIE,
-            format!(
-                "CSRF={}; SameSite=Strict; Path=/; Secure; HttpOnly",
-                cookie.b64_string()
-            ),
-        )
-        .html(|o| {
-            templates::post_html(
-                o,
-                fluent,
-                &url,
-                &post,
-                &tags,
-                bad_comment,
-                &token.b64_string(),
-                &comments,
-                &other_langs,
-                &related,
-            )
-        })?)
+    let mut html = Vec::new();
+    templates::post_html(
+        &mut html,
+        fluent,
+        &post,
+        &PostPage {
+            canonical_url: &url,
+            tags: &tags,
+            bad_comment,
+            csrf: CSRF_MARK,
+            comments: &Thread::build(comments),
+            mentions: &mentions,
+            other_langs: &other_langs,
+            similar: &related,
+        },
+    )
+    .or_ise()?;
+    let html = String::from_utf8(html).or_ise()?;
+    let page = CachedPage::new(html, post.updated_at, validator);
+    Ok(Ok((page, !bad_comment)))
 }
 
 /// When asked for a page without lang in url, redirect to existing.
@@ -470,18 +743,43 @@ async fn page_fallback(
 }
 
 #[instrument]
-async fn metapage(slug: SlugAndLang, app: App) -> Result<Response> {
+async fn metapage(
+    slug: SlugAndLang,
+    conditions: Conditions,
+    app: App,
+) -> Result<Response> {
+    type Time = chrono::DateTime<chrono::Utc>;
     let mut db = app.db().await?;
+    let (title, content, updated) = m::metapages
+        .select((m::title, m::content, m::updated_at))
+        .filter(m::slug.eq(slug.slug.as_ref()))
+        .filter(m::lang.eq(slug.lang.as_ref()))
+        .first::<(String, String, Time)>(&mut db)
+        .await
+        .optional()?
+        .ok_or(ViewError::NotFound)?;
+
     let fluent = slug.lang.fluent();
     let s1 = slug.clone();
     let other_langs = m::metapages
-        .select((m::lang, m::title))
+        .select((m::lang, m::title, m::updated_at))
         .filter(m::slug.eq(s1.slug.as_ref()))
         .filter(m::lang.ne(s1.lang.as_ref()))
-        .load::<(MyLang, String)>(&mut db)
-        .await?
+        .load::<(MyLang, String, Time)>(&mut db)
+        .await?;
+
+    let validator = Validator::new(
+        &app,
+        other_langs.iter().map(|o| o.2).fold(updated, Time::max),
+        (slug.slug.as_ref(), slug.lang.as_ref()),
+    );
+    if let Some(response) = validator.not_modified(&conditions) {
+        return Ok(response);
+    }
+
+    let other_langs = other_langs
         .into_iter()
-        .map(|(lang, title)| {
+        .map(|(lang, title, _)| {
             let fluent = lang.fluent();
             let name = fl!(fluent, "lang-name");
             let title = fl!(fluent, "in-lang", title=title);
@@ -493,16 +791,7 @@ async fn metapage(slug: SlugAndLang, app: App) -> Result<Response> {
         })
         .collect::<Vec<_>>();
 
-    let (title, content) = m::metapages
-        .select((m::title, m::content))
-        .filter(m::slug.eq(slug.slug.as_ref()))
-        .filter(m::lang.eq(slug.lang.as_ref()))
-        .first::<(String, String)>(&mut db)
-        .await
-        .optional()?
-        .ok_or(ViewError::NotFound)?;
-
-    Ok(response().html(|o| {
+    Ok(validator.headers(response()).html(|o| {
         templates::page_html(o, fluent, &title, &content, &other_langs)
     })?)
 }
@@ -556,14 +845,18 @@ struct PageQuery {
     c: Option<i32>,
 }
 
-fn robots_txt() -> Result<Response> {
+fn robots_txt(app: App) -> Result<Response> {
     use warp::http::header::CONTENT_TYPE;
     response()
         .header(CONTENT_TYPE, mime::TEXT_PLAIN.as_ref())
         .body(
-            "User-agent: *\n\
-             Disallow: /tmp/\n"
-                .into(),
+            format!(
+                "User-agent: *\n\
+                 Disallow: /tmp/\n\
+                 Sitemap: {}/sitemap.xml\n",
+                app.base,
+            )
+            .into(),
         )
         .or_ise()
 }
@@ -0,0 +1,90 @@
+//! Pagination of post listings, by a `?page=N` query.
+use serde::Deserialize;
+
+/// The query part of a paginated listing.
+#[derive(Debug, Deserialize)]
+pub struct PagerQuery {
+    page: Option<u32>,
+}
+
+impl PagerQuery {
+    /// The requested page number, starting at 1.
+    pub fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+    /// The number of posts before the requested page.
+    pub fn offset(&self, per_page: u32) -> u32 {
+        (self.page() - 1).saturating_mul(per_page)
+    }
+}
+
+/// Links to previous and next page of a listing.
+#[derive(Debug, Default)]
+pub struct Pager {
+    path: String,
+    page: u32,
+    has_next: bool,
+}
+
+impl Pager {
+    /// Create a pager for `page` of the listing at `path`.
+    ///
+    /// The `items` are expected to be loaded with a limit one more
+    /// than `per_page`, and are truncated to `per_page`.
+    pub fn new<T>(
+        path: String,
+        query: &PagerQuery,
+        per_page: u32,
+        items: &mut Vec<T>,
+    ) -> Self {
+        let per_page = per_page as usize;
+        let has_next = items.len() > per_page;
+        items.truncate(per_page);
+        Pager {
+            path,
+            page: query.page(),
+            has_next,
+        }
+    }
+
+    /// True if there is more than one page.
+    pub fn is_paged(&self) -> bool {
+        self.page > 1 || self.has_next
+    }
+    /// The page number, if this is not the first page.
+    pub fn page(&self) -> Option<u32> {
+        Some(self.page).filter(|p| *p > 1)
+    }
+    /// Url of the previous (newer) page, if any.
+    pub fn prev(&self) -> Option<String> {
+        match self.page {
+            0 | 1 => None,
+            2 => Some(self.path.clone()),
+            n => Some(format!("{}?page={}", self.path, n - 1)),
+        }
+    }
+    /// Url of the next (older) page, if any.
+    pub fn next(&self) -> Option<String> {
+        self.has_next
+            .then(|| format!("{}?page={}", self.path, self.page + 1))
+    }
+}
+
+#[test]
+fn first_page() {
+    let mut items = vec![1, 2, 3, 4];
+    let query = PagerQuery { page: None };
+    let pager = Pager::new("/en".into(), &query, 3, &mut items);
+    assert_eq!(items, [1, 2, 3]);
+    assert_eq!(pager.prev(), None);
+    assert_eq!(pager.next().as_deref(), Some("/en?page=2"));
+}
+#[test]
+fn second_page() {
+    let mut items = vec![4, 5];
+    let query = PagerQuery { page: Some(2) };
+    assert_eq!(query.offset(3), 3);
+    let pager = Pager::new("/en".into(), &query, 3, &mut items);
+    assert_eq!(pager.prev().as_deref(), Some("/en"));
+    assert_eq!(pager.next(), None);
+}
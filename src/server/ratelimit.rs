@@ -0,0 +1,151 @@
+//! Token bucket rate limiting per client address.
+use super::ViewError;
+use crate::models::MyLang;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A token bucket rate limiter, keyed by client address.
+///
+/// Each address can do `burst` requests at once, and then gets one
+/// more request each `interval`.  An ipv6 address is limited by its
+/// /64 network, see [`key`].
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: f64,
+    interval: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<IpAddr, Bucket>,
+    /// When full buckets were last dropped.
+    pruned: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `burst` requests at once, and `per_hour` requests per hour.
+    pub fn new(burst: u32, per_hour: u32) -> Self {
+        RateLimiter {
+            burst: burst.max(1).into(),
+            interval: Duration::from_secs(3600) / per_hour.max(1),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Take a token for `ip`, or fail with `429 Too Many Requests`.
+    ///
+    /// The error page is shown in `lang`.
+    pub fn check(&self, ip: IpAddr, lang: MyLang) -> Result<(), ViewError> {
+        self.check_at(ip, Instant::now()).map_err(|retry_after| {
+            tracing::info!(%ip, ?retry_after, "Rate limited.");
+            ViewError::TooManyRequests(retry_after, lang)
+        })
+    }
+
+    /// Take a token for `ip` at time `now`.
+    ///
+    /// On failure, returns the time until a token is available.
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().map_err(|_| self.interval)?;
+        let Buckets { buckets, pruned } = &mut *buckets;
+        if pruned
+            .is_none_or(|p| now.saturating_duration_since(p) >= self.interval)
+        {
+            // A full bucket is the same as no bucket, so forget idle
+            // addresses.
+            buckets.retain(|_, b| self.refill(b, now) < self.burst);
+            *pruned = Some(now);
+        }
+        let bucket = buckets.entry(key(ip)).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = self.refill(bucket, now);
+        if tokens >= 1. {
+            bucket.tokens = tokens - 1.;
+            bucket.updated = now;
+            Ok(())
+        } else {
+            Err(self.interval.mul_f64(1. - tokens))
+        }
+    }
+
+    /// The number of tokens in `bucket` at time `now`.
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let new = elapsed.as_secs_f64() / self.interval.as_secs_f64();
+        (bucket.tokens + new).min(self.burst)
+    }
+}
+
+/// The key of the bucket for `ip`.
+///
+/// Nearly every ipv6 client controls at least a /64 network, and can
+/// use a new address in it for each request, so the network is the key.
+/// An ipv4-mapped ipv6 address is the ipv4 address.
+fn key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(
+            ip.to_bits() & !u128::from(u64::MAX),
+        )),
+        ipv4 => ipv4,
+    }
+}
+
+#[test]
+fn burst_then_limit() {
+    let limiter = RateLimiter::new(3, 60);
+    let ip = IpAddr::from([10, 0, 0, 1]);
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(ip, now), Ok(()));
+    assert_eq!(limiter.check_at(ip, now), Ok(()));
+    assert_eq!(limiter.check_at(ip, now), Ok(()));
+    assert_eq!(limiter.check_at(ip, now), Err(Duration::from_secs(60)));
+    let later = now + Duration::from_secs(45);
+    assert_eq!(limiter.check_at(ip, later), Err(Duration::from_secs(15)));
+    let later = now + Duration::from_secs(60);
+    assert_eq!(limiter.check_at(ip, later), Ok(()));
+    assert_eq!(limiter.check_at(ip, later), Err(Duration::from_secs(60)));
+}
+
+#[test]
+fn limit_per_address() {
+    let limiter = RateLimiter::new(1, 10);
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(IpAddr::from([10, 0, 0, 1]), now), Ok(()));
+    assert!(limiter.check_at(IpAddr::from([10, 0, 0, 1]), now).is_err());
+    assert_eq!(limiter.check_at(IpAddr::from([10, 0, 0, 2]), now), Ok(()));
+}
+
+#[test]
+fn limit_per_ipv6_network() {
+    let limiter = RateLimiter::new(1, 10);
+    let now = Instant::now();
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    assert_eq!(limiter.check_at(ip("2001:db8:1:2::1"), now), Ok(()));
+    assert!(limiter.check_at(ip("2001:db8:1:2:abcd::17"), now).is_err());
+    assert_eq!(limiter.check_at(ip("2001:db8:1:3::1"), now), Ok(()));
+    assert_eq!(limiter.check_at(ip("10.0.0.1"), now), Ok(()));
+    assert!(limiter.check_at(ip("::ffff:10.0.0.1"), now).is_err());
+}
+
+#[test]
+fn forget_idle_addresses() {
+    let limiter = RateLimiter::new(2, 60);
+    let now = Instant::now();
+    for i in 0..100 {
+        let _ = limiter.check_at(IpAddr::from([10, 0, 0, i]), now);
+    }
+    let later = now + Duration::from_secs(120);
+    assert_eq!(limiter.check_at(IpAddr::from([10, 0, 1, 1]), later), Ok(()));
+    assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 1);
+}
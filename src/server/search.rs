@@ -0,0 +1,98 @@
+use super::pager::Pager;
+use super::templates::{self, RenderRucte};
+use super::{App, Asides, Result, goh, response};
+use crate::models::{MetaLink, MyLang, Teaser};
+use i18n_embed_fl::fl;
+use serde::Deserialize;
+use std::str::FromStr;
+use tracing::instrument;
+use warp::filters::BoxedFilter;
+use warp::path::{end, param};
+use warp::query;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// Full-text search in posts and meta pages, at `/search.{lang}?q=...`.
+pub fn route(s: BoxedFilter<(App,)>) -> BoxedFilter<(impl Reply,)> {
+    param()
+        .and(end())
+        .and(query())
+        .and(goh())
+        .and(s)
+        .then(search)
+        .boxed()
+}
+
+#[instrument]
+async fn search(
+    path: SearchPath,
+    query: SearchQuery,
+    app: App,
+) -> Result<Response> {
+    let lang = path.0;
+    let q = query.q.as_deref().map(str::trim).unwrap_or_default();
+    let (posts, pages) = if q.is_empty() {
+        (vec![], vec![])
+    } else {
+        let mut db = app.db().await?;
+        (
+            Teaser::search(q, lang.as_ref(), 30, &mut db).await?,
+            MetaLink::search(q, lang.as_ref(), 10, &mut db).await?,
+        )
+    };
+
+    let fluent = lang.fluent();
+    let h1 = if q.is_empty() {
+        fl!(fluent, "search-h")
+    } else if posts.is_empty() && pages.is_empty() {
+        fl!(fluent, "search-none", q = q)
+    } else {
+        fl!(fluent, "search-result", q = q)
+    };
+    let other_langs = lang.other(|_, lang, name| {
+        format!(
+            "<a href='/search.{lang}' hreflang='{lang}' lang='{lang}' rel='alternate'>{name}</a>",
+        )});
+
+    Ok(response().html(|o| {
+        templates::posts_html(
+            o,
+            fluent,
+            &h1,
+            &posts,
+            &Pager::default(),
+            &Asides {
+                pages: &pages,
+                ..Asides::default()
+            },
+            &other_langs,
+        )
+    })?)
+}
+
+/// The path segment of a search url, e.g. `search.en`.
+#[derive(Debug)]
+struct SearchPath(MyLang);
+
+impl FromStr for SearchPath {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lang = s.strip_prefix("search.").ok_or(())?;
+        Ok(SearchPath(lang.parse().map_err(|_| ())?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+}
+
+#[test]
+fn search_path() {
+    let lang = |s: &str| s.parse::<SearchPath>().map(|p| p.0);
+    assert_eq!(lang("search.sv"), Ok(MyLang::Sv));
+    assert_eq!(lang("search.en"), Ok(MyLang::En));
+    assert!(lang("search.xx").is_err());
+    assert!(lang("search").is_err());
+    assert!(lang("sitemap.en").is_err());
+}
@@ -0,0 +1,172 @@
+use super::language::MYLANGS;
+use super::{App, Result, ViewResult, response};
+use crate::models::{MyLang, PostLink};
+use crate::schema::metapages::dsl as m;
+use crate::schema::post_tags::dsl as pt;
+use crate::schema::posts::dsl as p;
+use crate::schema::tags::dsl as t;
+use chrono::{SecondsFormat, Utc};
+use diesel::dsl::max;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use pulldown_cmark_escape::escape_html;
+use std::collections::BTreeMap;
+use tracing::instrument;
+use warp::http::header::CONTENT_TYPE;
+use warp::reply::Response;
+
+type Time = chrono::DateTime<Utc>;
+
+/// A sitemap of all posts, meta pages, tag pages and year pages.
+///
+/// Pages that exist in more than one language lists the other
+/// versions as `hreflang` alternates.
+#[instrument]
+pub async fn sitemap(app: App) -> Result<Response> {
+    let mut db = app.db().await?;
+
+    let posts = p::posts
+        .select((PostLink::as_select(), p::updated_at))
+        .order((p::posted_at, p::lang))
+        .load::<(PostLink, Time)>(&mut db)
+        .await?;
+    let metapages = m::metapages
+        .select((m::slug, m::lang, m::updated_at))
+        .order((m::slug, m::lang))
+        .load::<(String, MyLang, Time)>(&mut db)
+        .await?;
+    let tags = t::tags
+        .inner_join(pt::post_tags.inner_join(p::posts))
+        .group_by(t::slug)
+        .select((t::slug, max(p::updated_at)))
+        .order(t::slug)
+        .load::<(String, Option<Time>)>(&mut db)
+        .await?;
+    let mut years = BTreeMap::<i16, Time>::new();
+    for (post, updated) in &posts {
+        let year = years.entry(post.year).or_insert(*updated);
+        *year = (*year).max(*updated);
+    }
+
+    let mut out = Sitemap::new(&app.base);
+    let lastmod = years.values().max().copied();
+    out.all_langs(|lang| format!("/{lang}"), lastmod);
+
+    let mut grouped = BTreeMap::<_, Vec<_>>::new();
+    for (post, updated) in &posts {
+        grouped
+            .entry((post.year, post.slug.as_ref()))
+            .or_default()
+            .push((post.lang, post.url(), *updated));
+    }
+    for versions in grouped.values() {
+        out.versions(versions);
+    }
+
+    let mut grouped = BTreeMap::<_, Vec<_>>::new();
+    for (slug, lang, updated) in &metapages {
+        grouped.entry(slug).or_default().push((
+            *lang,
+            format!("/{slug}.{lang}"),
+            *updated,
+        ));
+    }
+    for versions in grouped.values() {
+        out.versions(versions);
+    }
+
+    for (slug, lastmod) in &tags {
+        out.all_langs(|lang| format!("/tag/{slug}.{lang}"), *lastmod);
+    }
+    for (year, lastmod) in &years {
+        out.all_langs(|lang| format!("/{year}/{lang}"), Some(*lastmod));
+    }
+
+    response()
+        .header(CONTENT_TYPE, "application/xml")
+        .body(out.finish().into())
+        .or_ise()
+}
+
+struct Sitemap<'a> {
+    base: &'a str,
+    out: String,
+}
+
+impl<'a> Sitemap<'a> {
+    fn new(base: &'a str) -> Self {
+        Sitemap {
+            base,
+            out: "<?xml version='1.0' encoding='UTF-8'?>\n\
+                  <urlset xmlns='http://www.sitemaps.org/schemas/sitemap/0.9' \
+                  xmlns:xhtml='http://www.w3.org/1999/xhtml'>\n"
+                .into(),
+        }
+    }
+
+    /// Add a page that exists in all languages, with a common lastmod.
+    fn all_langs(
+        &mut self,
+        url: impl Fn(MyLang) -> String,
+        lastmod: Option<Time>,
+    ) {
+        let versions = MYLANGS
+            .iter()
+            .map(|lang| (*lang, url(*lang), lastmod))
+            .collect::<Vec<_>>();
+        for (_, loc, lastmod) in &versions {
+            self.url(loc, *lastmod, &versions);
+        }
+    }
+
+    /// Add each of a set of language versions of the same page.
+    fn versions(&mut self, versions: &[(MyLang, String, Time)]) {
+        let alts = versions
+            .iter()
+            .map(|(lang, url, lastmod)| (*lang, url.clone(), Some(*lastmod)))
+            .collect::<Vec<_>>();
+        for (_, loc, lastmod) in &alts {
+            self.url(loc, *lastmod, &alts);
+        }
+    }
+
+    fn url(
+        &mut self,
+        loc: &str,
+        lastmod: Option<Time>,
+        alternates: &[(MyLang, String, Option<Time>)],
+    ) {
+        self.out.push_str("<url><loc>");
+        self.push_url(loc);
+        self.out.push_str("</loc>");
+        if let Some(lastmod) = lastmod {
+            self.out.push_str("<lastmod>");
+            self.out.push_str(
+                &lastmod.to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+            self.out.push_str("</lastmod>");
+        }
+        if alternates.len() > 1 {
+            for (lang, url, _) in alternates {
+                self.out
+                    .push_str("\n  <xhtml:link rel='alternate' hreflang='");
+                self.out.push_str(lang.as_ref());
+                self.out.push_str("' href='");
+                self.push_url(url);
+                self.out.push_str("'/>");
+            }
+        }
+        self.out.push_str("</url>\n");
+    }
+
+    fn push_url(&mut self, path: &str) {
+        // Writing to a String never fails.
+        let _ = escape_html(&mut self.out, self.base);
+        let _ = escape_html(&mut self.out, path);
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("</urlset>\n");
+        self.out
+    }
+}
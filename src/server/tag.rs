@@ -1,5 +1,6 @@
+use super::pager::{Pager, PagerQuery};
 use super::templates::{self, RenderRucte};
-use super::{App, Result, SlugAndLang, ViewError, goh, response};
+use super::{App, Asides, Result, SlugAndLang, ViewError, goh, response};
 use crate::models::{MyLang, Tag, Teaser};
 use crate::schema::post_tags::dsl as pt;
 use crate::schema::tags::dsl as t;
@@ -11,12 +12,18 @@ use std::collections::BTreeMap;
 use tracing::instrument;
 use warp::filters::BoxedFilter;
 use warp::path::{end, param};
+use warp::query;
 use warp::reply::Response;
 use warp::{Filter, Reply};
 
 pub fn routes(s: BoxedFilter<(App,)>) -> BoxedFilter<(impl Reply,)> {
     let cloud = param().and(end()).and(goh()).and(s.clone()).then(tagcloud);
-    let page = param().and(end()).and(goh()).and(s).then(tagpage);
+    let page = param()
+        .and(end())
+        .and(query())
+        .and(goh())
+        .and(s)
+        .then(tagpage);
     cloud.or(page).unify().boxed()
 }
 
@@ -60,14 +67,27 @@ async fn tagcloud(lang: MyLang, app: App) -> Result<Response> {
 }
 
 #[instrument]
-async fn tagpage(tag: SlugAndLang, app: App) -> Result<Response> {
+async fn tagpage(
+    tag: SlugAndLang,
+    query: PagerQuery,
+    app: App,
+) -> Result<Response> {
     let mut db = app.db().await?;
     let lang = tag.lang;
     let tag = Tag::by_slug(&tag.slug, &mut db)
         .await?
         .ok_or(ViewError::NotFound)?;
 
-    let posts = Teaser::tagged(tag.id, lang.as_ref(), 50, &mut db).await?;
+    let per_page = 20;
+    let offset = query.offset(per_page);
+    let mut posts =
+        Teaser::tagged(tag.id, lang.as_ref(), per_page + 1, offset, &mut db)
+            .await?;
+    if posts.is_empty() && offset > 0 {
+        return Err(ViewError::NotFound);
+    }
+    let path = format!("/tag/{}.{lang}", tag.slug);
+    let pager = Pager::new(path, &query, per_page, &mut posts);
 
     let fluent = lang.fluent();
     let h1 = fl!(fluent, "posts-tagged", tag = tag.name);
@@ -83,9 +103,12 @@ async fn tagpage(tag: SlugAndLang, app: App) -> Result<Response> {
             o,
             fluent,
             &h1,
-            Some(&feed),
             &posts,
-            &[],
+            &pager,
+            &Asides {
+                feed: Some(&feed),
+                ..Asides::default()
+            },
             &other_langs,
         )
     })?)
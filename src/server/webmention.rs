@@ -0,0 +1,358 @@
+//! Receive [webmentions](https://www.w3.org/TR/webmention/) of posts.
+//!
+//! A mention is accepted at once, and verified in the background by
+//! fetching the source page and checking that it links to the post.
+//! Verified mentions wait for moderation like comments.
+use super::error::{ViewError, ViewResult};
+use super::language::accept_lang;
+use super::{App, Result, SlugAndLang, TrustedProxies, response};
+use crate::models::{MyLang, PostLink, unescape_html, year_of_date};
+use crate::outgoing::Outgoing;
+use crate::schema::posts::dsl as p;
+use crate::schema::webmentions::dsl as wm;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use ipnetwork::IpNetwork;
+use lazy_regex::regex;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use std::net::IpAddr;
+use tracing::instrument;
+use warp::filters::{BoxedFilter, body};
+use warp::path::end;
+use warp::reply::Response;
+use warp::{Filter, Reply, post};
+
+pub fn route(
+    proxies: TrustedProxies,
+    s: BoxedFilter<(App,)>,
+) -> BoxedFilter<(impl Reply,)> {
+    end()
+        .and(post())
+        .and(proxies.filter())
+        .and(accept_lang())
+        .and(body::form())
+        .and(s)
+        .then(receive)
+        .boxed()
+}
+
+#[derive(Debug, Deserialize)]
+struct MentionForm {
+    source: String,
+    target: String,
+}
+
+#[instrument]
+async fn receive(
+    ip: IpAddr,
+    lang: MyLang,
+    form: MentionForm,
+    app: App,
+) -> Result<Response> {
+    app.comment_limit.check(ip, lang)?;
+    let source = parse_url(&form.source)?;
+    let target = parse_url(&form.target)?;
+    app.http.check(&source).map_err(|e| {
+        tracing::info!("Bad webmention source: {e}");
+        ViewError::BadRequest("Bad source".into())
+    })?;
+    if source == target {
+        return Err(ViewError::BadRequest("Source is target".into()));
+    }
+    let post = target_post(&target, &app).await?.ok_or_else(|| {
+        ViewError::BadRequest("Target is not a post here".into())
+    })?;
+    tokio::spawn(verify(app, ip, post, source, target));
+    response()
+        .status(StatusCode::ACCEPTED)
+        .body("Accepted, the source will be verified.\n".into())
+        .or_ise()
+}
+
+fn parse_url(url: &str) -> Result<Url> {
+    Url::parse(url.trim())
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .ok_or_else(|| ViewError::BadRequest("Bad url".into()))
+}
+
+/// Find the post that `target` is the url of.
+async fn target_post(target: &Url, app: &App) -> Result<Option<PostLink>> {
+    let base = Url::parse(&app.base).or_ise()?;
+    if target.origin() != base.origin() {
+        return Ok(None);
+    }
+    let Some((year, slug)) = target.path_segments().and_then(|mut path| {
+        let year = path.next()?.parse::<i16>().ok()?;
+        let slug = path.next()?.parse::<SlugAndLang>().ok()?;
+        path.next().is_none().then_some((year, slug))
+    }) else {
+        return Ok(None);
+    };
+    let mut db = app.db().await?;
+    Ok(PostLink::all()
+        .filter(year_of_date(p::posted_at).eq(year))
+        .filter(p::slug.eq(slug.slug.as_ref()))
+        .filter(p::lang.eq(slug.lang.as_ref()))
+        .first(&mut db)
+        .await
+        .optional()?)
+}
+
+/// Verify a mention and store, update, or remove it.
+async fn verify(
+    app: App,
+    ip: IpAddr,
+    post: PostLink,
+    source: Url,
+    target: Url,
+) {
+    let found = match fetch_source(&app.http, &source, &target).await {
+        Ok(found) => found,
+        Err(err) => {
+            tracing::info!(%source, "Failed to verify webmention: {err}");
+            return;
+        }
+    };
+    if let Err(err) = store(&app, ip, &post, &source, found).await {
+        tracing::error!(%source, "Failed to store webmention: {err:?}");
+    }
+}
+
+async fn store(
+    app: &App,
+    ip: IpAddr,
+    post: &PostLink,
+    source: &Url,
+    found: Source,
+) -> Result<()> {
+    let mut db = app.db().await?;
+    let mention = wm::webmentions
+        .filter(wm::post_id.eq(post.id))
+        .filter(wm::source.eq(source.as_str()));
+    let existing = mention
+        .select((wm::id, wm::is_public))
+        .first::<(i32, bool)>(&mut db)
+        .await
+        .optional()?;
+    match (found, existing) {
+        (Source::Gone, None) => {
+            tracing::info!(%source, "Webmention source has no link.");
+        }
+        (Source::Gone, Some((id, public))) => {
+            diesel::delete(mention).execute(&mut db).await?;
+            tracing::info!(id, %source, "Removed webmention.");
+            if public {
+                app.pages.clear();
+            }
+        }
+        (Source::Mentions(title), Some((id, public))) => {
+            diesel::update(mention)
+                .set(wm::title.eq(title))
+                .execute(&mut db)
+                .await?;
+            tracing::info!(id, %source, "Updated webmention.");
+            if public {
+                app.pages.clear();
+            }
+        }
+        (Source::Mentions(title), None) => {
+            let id = diesel::insert_into(wm::webmentions)
+                .values((
+                    wm::post_id.eq(post.id),
+                    wm::source.eq(source.as_str()),
+                    wm::title.eq(&title),
+                    wm::from_host.eq(IpNetwork::from(ip)),
+                ))
+                .returning(wm::id)
+                .get_result::<i32>(&mut db)
+                .await?;
+            tracing::info!(id, %source, "Webmention waiting for moderation.");
+            if let Some(mailer) = &app.mailer {
+                let (subject, body) = notification(
+                    source,
+                    title.as_deref(),
+                    ip,
+                    post,
+                    &app.base,
+                    app.admin.is_some(),
+                );
+                mailer.spawn_send(subject, body);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What the source of a mention says about the target.
+#[derive(Debug, PartialEq, Eq)]
+enum Source {
+    /// The source links to the target, and has this title.
+    Mentions(Option<String>),
+    /// The source is gone, or does not link to the target.
+    Gone,
+}
+
+/// The largest part of a source page to look for a link in.
+const MAX_SOURCE: usize = 1 << 20;
+
+async fn fetch_source(
+    http: &Outgoing,
+    source: &Url,
+    target: &Url,
+) -> Result<Source, FetchError> {
+    let mut resp = http.get(source)?.send().await?;
+    if matches!(resp.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+        return Ok(Source::Gone);
+    }
+    resp = resp.error_for_status()?;
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_SOURCE {
+            break;
+        }
+    }
+    let body = String::from_utf8_lossy(&body);
+    Ok(if links_to(&body, target) {
+        Source::Mentions(title(&body))
+    } else {
+        Source::Gone
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+enum FetchError {
+    #[error(transparent)]
+    Blocked(#[from] crate::outgoing::Blocked),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+/// True if the `html` has a link to `target`.
+fn links_to(html: &str, target: &Url) -> bool {
+    regex!(r#"(?i)\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#)
+        .captures_iter(html)
+        .filter_map(|c| c.get(1).or(c.get(2)).or(c.get(3)))
+        .filter_map(|href| Url::parse(&unescape_html(href.as_str())).ok())
+        .any(|href| href == *target)
+}
+
+/// The title of an html page, if any.
+fn title(html: &str) -> Option<String> {
+    let title = regex!(r"(?is)<title[^>]*>(.*?)</title>")
+        .captures(html)?
+        .get(1)?
+        .as_str();
+    let title = unescape_html(title)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let title = match title.char_indices().nth(120) {
+        Some((end, _)) => format!("{} …", &title[..end]),
+        None => title,
+    };
+    Some(title).filter(|t| !t.is_empty())
+}
+
+/// The subject and body of a mail about a new webmention.
+fn notification(
+    source: &Url,
+    title: Option<&str>,
+    ip: IpAddr,
+    post: &PostLink,
+    base: &str,
+    admin: bool,
+) -> (String, String) {
+    let subject = format!("New webmention of {:?}", post.title);
+    let moderate = if admin {
+        format!("Moderate: {base}/admin/comments#mentions")
+    } else {
+        "Moderate with `r4s moderate-comments`.".into()
+    };
+    let body = format!(
+        "{source} ({title}) mentioned {post_title:?}.\n\
+         The mention is waiting for moderation.\n\n\
+         From: {ip}\n\n\
+         The post: {base}{post_url}\n\
+         {moderate}\n",
+        title = title.unwrap_or("untitled"),
+        post_title = post.title,
+        post_url = post.url(),
+    );
+    (subject, body)
+}
+
+#[test]
+fn find_link() {
+    let target = Url::parse("https://example.org/2024/hello.en").unwrap();
+    let html = "<p>See <a class=x href='https://example.org/2024/hello.en'>\
+                this</a>.</p>";
+    assert!(links_to(html, &target));
+    assert!(links_to(
+        "<a href=https://example.org/2024/hello.en>x</a>",
+        &target
+    ));
+    assert!(!links_to(
+        "<a href=\"https://example.org/2024/hello.sv\">x</a>",
+        &target
+    ));
+    assert!(!links_to("https://example.org/2024/hello.en", &target));
+}
+
+#[test]
+fn find_title() {
+    let html = "<html><head><title>\n  Bikes &amp;\n  Trikes </title>";
+    assert_eq!(title(html).as_deref(), Some("Bikes & Trikes"));
+    assert_eq!(title("<title> </title>"), None);
+    assert_eq!(title("<h1>No title</h1>"), None);
+}
+
+/// Serve a single http response on a local port, like a source site.
+#[cfg(test)]
+async fn stub_server(response: &'static str) -> Url {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener =
+        tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 4096];
+        let _ = conn.read(&mut buf).await.unwrap();
+        conn.write_all(response.as_bytes()).await.unwrap();
+        conn.shutdown().await.unwrap();
+    });
+    Url::parse(&format!("http://{addr}/a-post")).unwrap()
+}
+
+#[tokio::test]
+async fn fetch_mentioning_source() {
+    let source = stub_server(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\
+         Connection: close\r\n\r\n\
+         <title>A reply</title><a href=\"https://example.org/2024/a.en\">",
+    )
+    .await;
+    let target = Url::parse("https://example.org/2024/a.en").unwrap();
+    let http = Outgoing::new(true).unwrap();
+    assert_eq!(
+        fetch_source(&http, &source, &target).await.unwrap(),
+        Source::Mentions(Some("A reply".into())),
+    );
+}
+
+#[tokio::test]
+async fn fetch_gone_source() {
+    let source = stub_server(
+        "HTTP/1.1 410 Gone\r\nContent-Length: 0\r\n\
+         Connection: close\r\n\r\n",
+    )
+    .await;
+    let target = Url::parse("https://example.org/2024/a.en").unwrap();
+    let http = Outgoing::new(true).unwrap();
+    assert_eq!(
+        fetch_source(&http, &source, &target).await.unwrap(),
+        Source::Gone,
+    );
+}
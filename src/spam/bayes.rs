@@ -0,0 +1,171 @@
+use crate::dbopt::Connection;
+use crate::schema::spam_tokens::dsl as st;
+use diesel::Connection as _;
+use diesel::dsl::Select;
+use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// A naive bayes spam classifier, trained on moderated comments.
+#[derive(Debug, Default)]
+pub struct Bayes {
+    /// Number of spam and ham texts each token is found in.
+    tokens: HashMap<String, (u32, u32)>,
+    n_spam: u32,
+    n_ham: u32,
+}
+
+impl Bayes {
+    /// The least number of spam and ham texts needed to classify.
+    const MIN_TRAINING: u32 = 10;
+    /// The number of tokens to base a classification on.
+    const INTERESTING: usize = 15;
+    /// The least number of texts a token must be seen in to matter.
+    const MIN_SEEN: u32 = 3;
+
+    pub fn train(&mut self, text: &str, spam: bool) {
+        for token in tokens(text) {
+            let (s, h) = self.tokens.entry(token).or_default();
+            if spam {
+                *s += 1;
+            } else {
+                *h += 1;
+            }
+        }
+        if spam {
+            self.n_spam += 1;
+        } else {
+            self.n_ham += 1;
+        }
+    }
+
+    /// The probability that `text` is spam.
+    ///
+    /// Returns None if the classifier is not trained enough.
+    pub fn probability(&self, text: &str) -> Option<f64> {
+        if self.n_spam < Self::MIN_TRAINING || self.n_ham < Self::MIN_TRAINING
+        {
+            return None;
+        }
+        let mut probs = tokens(text)
+            .into_iter()
+            .filter_map(|t| self.token_probability(&t))
+            .collect::<Vec<_>>();
+        probs.sort_by(|a, b| (b - 0.5).abs().total_cmp(&(a - 0.5).abs()));
+        probs.truncate(Self::INTERESTING);
+        // Combine in log space to avoid underflow.
+        let (spam, ham) = probs.iter().fold((0., 0.), |(s, h), p| {
+            (s + f64::ln(*p), h + f64::ln(1. - p))
+        });
+        Some(1. / (1. + (ham - spam).exp()))
+    }
+
+    fn token_probability(&self, token: &str) -> Option<f64> {
+        let (s, h) = *self.tokens.get(token)?;
+        if s + h < Self::MIN_SEEN {
+            return None;
+        }
+        let s = f64::from(s) / f64::from(self.n_spam);
+        let h = f64::from(h) / f64::from(self.n_ham);
+        Some((s / (s + h)).clamp(0.01, 0.99))
+    }
+}
+
+impl Bayes {
+    /// Load the model stored by [`Bayes::save`].
+    pub fn load(db: &mut PgConnection) -> QueryResult<Self> {
+        stored().load(db).map(Self::from_rows)
+    }
+
+    /// Load the model stored by [`Bayes::save`], asynchronously.
+    pub async fn load_async(db: &mut Connection) -> QueryResult<Self> {
+        diesel_async::RunQueryDsl::load(stored(), db)
+            .await
+            .map(Self::from_rows)
+    }
+
+    /// Store this model in the database, replacing any existing.
+    ///
+    /// Tokens too rare to affect any classification are not stored.
+    pub fn save(&self, db: &mut PgConnection) -> QueryResult<usize> {
+        let totals = (String::new(), (self.n_spam, self.n_ham));
+        let rows = self
+            .tokens
+            .iter()
+            .filter(|(_, (s, h))| s + h >= Self::MIN_SEEN)
+            .map(|(token, counts)| (token.clone(), *counts))
+            .chain([totals])
+            .map(|(token, (s, h))| {
+                (
+                    st::token.eq(token),
+                    st::n_spam.eq(s as i32),
+                    st::n_ham.eq(h as i32),
+                )
+            })
+            .collect::<Vec<_>>();
+        db.transaction(|db| {
+            diesel::delete(st::spam_tokens).execute(db)?;
+            // Stay well below the limit on bind parameters per query.
+            for chunk in rows.chunks(10_000) {
+                diesel::insert_into(st::spam_tokens)
+                    .values(chunk)
+                    .execute(db)?;
+            }
+            Ok(rows.len() - 1)
+        })
+    }
+
+    fn from_rows(rows: Vec<(String, i32, i32)>) -> Self {
+        let mut bayes = Bayes::default();
+        for (token, s, h) in rows {
+            let counts = (s as u32, h as u32);
+            if token.is_empty() {
+                (bayes.n_spam, bayes.n_ham) = counts;
+            } else {
+                bayes.tokens.insert(token, counts);
+            }
+        }
+        bayes
+    }
+}
+
+fn stored() -> Select<st::spam_tokens, (st::token, st::n_spam, st::n_ham)> {
+    st::spam_tokens.select((st::token, st::n_spam, st::n_ham))
+}
+
+/// The distinct words in a text, lowercased.
+fn tokens(text: &str) -> HashSet<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '\''))
+        .filter(|w| (3..=24).contains(&w.chars().count()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+fn trained() -> Bayes {
+    let mut bayes = Bayes::default();
+    for i in 0..10 {
+        bayes.train(&format!("Cheap pills, buy now! Offer {i}"), true);
+        bayes.train(&format!("Nice photo of the bicycle {i}"), false);
+    }
+    bayes
+}
+
+#[test]
+fn untrained() {
+    assert_eq!(Bayes::default().probability("buy cheap pills"), None);
+}
+
+#[test]
+fn classify_spam() {
+    assert!(trained().probability("Buy cheap pills!").unwrap() > 0.95);
+}
+
+#[test]
+fn classify_ham() {
+    assert!(trained().probability("What a nice bicycle").unwrap() < 0.05);
+}
+
+#[test]
+fn classify_unknown() {
+    assert_eq!(trained().probability("Something else"), Some(0.5));
+}
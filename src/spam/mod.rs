@@ -0,0 +1,231 @@
+//! Spam scoring for incoming comments.
+//!
+//! Each [`Check`] gives a score for an [`Incoming`] comment, where
+//! positive is spammy and negative is hammy.  The sum of the scores
+//! gives the [`Verdict`].
+mod bayes;
+
+pub use self::bayes::Bayes;
+use lazy_regex::regex;
+use std::fmt;
+use std::time::Duration;
+
+/// What is known about an incoming comment.
+#[derive(Debug)]
+pub struct Incoming<'a> {
+    /// The markdown source of the comment.
+    pub raw_md: &'a str,
+    /// Content of the honeypot field, that humans don't see.
+    pub honeypot: &'a str,
+    /// Time since the comment form was rendered, if known.
+    pub form_age: Option<Duration>,
+    /// Number of recent comments from the same ip address.
+    pub recent_from_ip: i64,
+    /// The spam probability according to the bayes classifier, if any.
+    pub spam_probability: Option<f64>,
+}
+
+/// A check of a comment, with a spam score.
+pub trait Check: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn score(&self, comment: &Incoming) -> f64;
+}
+
+/// A set of checks to score comments with.
+pub struct SpamFilter {
+    checks: Vec<Box<dyn Check>>,
+}
+
+impl SpamFilter {
+    pub fn new(checks: Vec<Box<dyn Check>>) -> Self {
+        SpamFilter { checks }
+    }
+
+    pub fn score(&self, comment: &Incoming) -> Score {
+        Score {
+            parts: self
+                .checks
+                .iter()
+                .map(|c| (c.name(), c.score(comment)))
+                .filter(|(_, score)| *score != 0.)
+                .collect(),
+        }
+    }
+}
+
+impl Default for SpamFilter {
+    fn default() -> Self {
+        SpamFilter::new(vec![
+            Box::new(Links),
+            Box::new(Honeypot),
+            Box::new(Hurry),
+            Box::new(IpRate),
+            Box::new(Classifier),
+        ])
+    }
+}
+
+/// The score of a comment, with the non-zero parts.
+#[derive(Debug)]
+pub struct Score {
+    parts: Vec<(&'static str, f64)>,
+}
+
+impl Score {
+    pub fn total(&self) -> f64 {
+        self.parts.iter().fold(0., |sum, (_, score)| sum + score)
+    }
+
+    pub fn verdict(&self) -> Verdict {
+        let total = self.total();
+        if total >= 5. {
+            Verdict::Spam
+        } else if total >= 2. {
+            Verdict::Moderate
+        } else {
+            Verdict::Ok
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(out, "{:.1}", self.total())?;
+        for (name, score) in &self.parts {
+            write!(out, " {name}:{score:.1}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Probably not spam.
+    Ok,
+    /// Don't publish without manual moderation.
+    Moderate,
+    /// Probably spam.
+    Spam,
+}
+
+/// One link is fine, each link after that is suspicious.
+pub struct Links;
+
+impl Check for Links {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+    fn score(&self, comment: &Incoming) -> f64 {
+        let links = regex!(r"(?i)https?://|www\.")
+            .find_iter(comment.raw_md)
+            .count();
+        links.saturating_sub(1) as f64
+    }
+}
+
+/// Only robots fill in fields they can't see.
+pub struct Honeypot;
+
+impl Check for Honeypot {
+    fn name(&self) -> &'static str {
+        "honeypot"
+    }
+    fn score(&self, comment: &Incoming) -> f64 {
+        if comment.honeypot.is_empty() { 0. } else { 10. }
+    }
+}
+
+/// Humans take some time to write a comment.
+pub struct Hurry;
+
+impl Check for Hurry {
+    fn name(&self) -> &'static str {
+        "hurry"
+    }
+    fn score(&self, comment: &Incoming) -> f64 {
+        match comment.form_age {
+            Some(age) if age < Duration::from_secs(3) => 4.,
+            Some(age) if age < Duration::from_secs(10) => 2.,
+            _ => 0.,
+        }
+    }
+}
+
+/// A few comments per hour from the same address is fine, more is not.
+pub struct IpRate;
+
+impl Check for IpRate {
+    fn name(&self) -> &'static str {
+        "iprate"
+    }
+    fn score(&self, comment: &Incoming) -> f64 {
+        (comment.recent_from_ip - 3).max(0) as f64
+    }
+}
+
+/// The opinion of the [`Bayes`] classifier.
+pub struct Classifier;
+
+impl Check for Classifier {
+    fn name(&self) -> &'static str {
+        "bayes"
+    }
+    fn score(&self, comment: &Incoming) -> f64 {
+        comment
+            .spam_probability
+            .map(|p| 8. * (p - 0.5))
+            .unwrap_or(0.)
+    }
+}
+
+#[cfg(test)]
+fn incoming(raw_md: &str) -> Incoming<'_> {
+    Incoming {
+        raw_md,
+        honeypot: "",
+        form_age: Some(Duration::from_secs(120)),
+        recent_from_ip: 0,
+        spam_probability: None,
+    }
+}
+
+#[test]
+fn score_ok() {
+    let score = SpamFilter::default()
+        .score(&incoming("Nice post, see https://example.org/"));
+    assert_eq!(score.verdict(), Verdict::Ok);
+    assert_eq!(score.to_string(), "0.0");
+}
+
+#[test]
+fn score_links() {
+    let score = SpamFilter::default().score(&incoming(
+        "Buy http://a.example/ http://b.example/ www.c.example",
+    ));
+    assert_eq!(score.verdict(), Verdict::Moderate);
+    assert_eq!(score.to_string(), "2.0 links:2.0");
+}
+
+#[test]
+fn score_honeypot() {
+    let comment = Incoming {
+        honeypot: "https://spam.example/",
+        ..incoming("Hello")
+    };
+    assert_eq!(
+        SpamFilter::default().score(&comment).verdict(),
+        Verdict::Spam
+    );
+}
+
+#[test]
+fn score_hurry_and_bayes() {
+    let comment = Incoming {
+        form_age: Some(Duration::from_secs(2)),
+        spam_probability: Some(0.75),
+        ..incoming("Hello")
+    };
+    let score = SpamFilter::default().score(&comment);
+    assert_eq!(score.verdict(), Verdict::Spam);
+    assert_eq!(score.to_string(), "6.0 hurry:4.0 bayes:2.0");
+}
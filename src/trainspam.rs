@@ -0,0 +1,34 @@
+//! Train the bayes spam classifier on moderated comments.
+use crate::dbopt::DbOpt;
+use crate::schema::comments::dsl as c;
+use crate::spam::Bayes;
+use anyhow::Result;
+use clap::Parser;
+use diesel::prelude::*;
+
+#[derive(Parser)]
+pub struct Args {
+    #[clap(flatten)]
+    db: DbOpt,
+}
+
+impl Args {
+    pub fn run(self) -> Result<()> {
+        let mut db = self.db.get_db()?;
+        let comments = c::comments
+            .select((c::raw_md, c::is_spam))
+            .filter(c::is_spam.or(c::is_public))
+            .load::<(String, bool)>(&mut db)?;
+        let mut bayes = Bayes::default();
+        for (text, spam) in &comments {
+            bayes.train(text, *spam);
+        }
+        let tokens = bayes.save(&mut db)?;
+        let spam = comments.iter().filter(|(_, spam)| *spam).count();
+        println!(
+            "Trained on {} comments, {spam} spam.  Stored {tokens} tokens.",
+            comments.len(),
+        );
+        Ok(())
+    }
+}